@@ -0,0 +1,53 @@
+// Vertex shader
+
+// group() = corresponds to binding (1st) parameter in set_bind_group in render
+// binding() = corresponds to bind_group_layout entry binding value in layout creation
+@group(1) @binding(0)
+var<uniform> view_proj: mat4x4<f32>;
+
+// Corner of the unit quad, from (0, 0) to (1, 1)
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+};
+
+// One per sprite, see `SpriteInstance`
+struct InstanceInput {
+    @location(1) position: vec2<f32>,
+    @location(2) size: vec2<f32>,
+    // u_min, v_min, u_max, v_max
+    @location(3) atlas_rect: vec4<f32>,
+    @location(4) tint: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) tint: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // The world has y pointing up, the texture has v pointing down
+    let corner = vec2<f32>(model.position.x, 1.0 - model.position.y);
+    out.tex_coords = mix(instance.atlas_rect.xy, instance.atlas_rect.zw, corner);
+    out.tint = instance.tint;
+    let world_position = instance.position + model.position * instance.size;
+    out.clip_position = view_proj * vec4<f32>(world_position, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>; // texture view
+@group(0) @binding(1)
+var s_diffuse: sampler; // texture sampler
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
+}
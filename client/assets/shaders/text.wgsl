@@ -0,0 +1,41 @@
+// Vertex shader
+
+// Projects physical screen pixels (top-left origin) into clip space
+@group(1) @binding(0)
+var<uniform> screen_proj: mat4x4<f32>;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.color = model.color;
+    out.clip_position = screen_proj * vec4<f32>(model.position, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_glyphs: texture_2d<f32>; // single channel glyph coverage
+@group(0) @binding(1)
+var s_glyphs: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_glyphs, s_glyphs, in.tex_coords).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
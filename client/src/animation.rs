@@ -0,0 +1,213 @@
+use instant::Duration;
+use serde::Deserialize;
+
+use super::atlas::Atlas;
+
+/// What happens after the last frame of an animation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+    /// Starts over at the first frame
+    #[default]
+    Loop,
+    /// Stays on the last frame
+    Once,
+    /// Plays backwards to the first frame, then forwards again
+    PingPong,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Atlas sprite shown during the frame
+    pub sprite: String,
+    /// In seconds, above 0
+    pub duration: f64,
+}
+
+/**
+ * A named sequence of atlas sprites, defined in the atlas definition file.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    /// Never empty
+    pub frames: Vec<Frame>,
+    pub mode: LoopMode,
+}
+
+impl Animation {
+    /// Index of the frame shown the given number of seconds after the animation started
+    fn frame_index(&self, time: f64) -> usize {
+        let last = self.frames.len() - 1;
+        let duration: f64 = self.frames.iter().map(|frame| frame.duration).sum();
+        let (time, backwards) = match self.mode {
+            LoopMode::Loop => (time % duration, false),
+            LoopMode::Once if time >= duration => return last,
+            LoopMode::Once => (time, false),
+            LoopMode::PingPong if last == 0 => return 0,
+            LoopMode::PingPong => {
+                // The first & last frame are not repeated when the direction changes
+                let inner = duration - self.frames[0].duration - self.frames[last].duration;
+                let time = time % (duration + inner);
+                if time < duration {
+                    (time, false)
+                } else {
+                    (time - duration + self.frames[last].duration, true)
+                }
+            }
+        };
+        let mut end = 0.0;
+        for step in 0..=last {
+            let index = if backwards { last - step } else { step };
+            end += self.frames[index].duration;
+            if time < end {
+                return index;
+            }
+        }
+        // Rounding can leave the time right at the end
+        if backwards {
+            0
+        } else {
+            last
+        }
+    }
+}
+
+/**
+ * Plays atlas animations for one sprite, advanced by the time passed to every update.
+ */
+#[derive(Debug, Clone)]
+pub struct Animator {
+    animation: String,
+    /// Seconds since the current animation started
+    time: f64,
+}
+
+impl Animator {
+    pub fn new(animation: &str) -> Self {
+        Animator {
+            animation: animation.to_string(),
+            time: 0.0,
+        }
+    }
+
+    /// Switches to another animation starting at its first frame, the current one is not restarted
+    pub fn play(&mut self, animation: &str) {
+        if self.animation != animation {
+            self.animation = animation.to_string();
+            self.time = 0.0;
+        }
+    }
+
+    pub fn update(&mut self, time_elapsed: Duration) {
+        self.time += time_elapsed.as_secs_f64();
+    }
+
+    /// Atlas sprite to show now, none if the atlas lacks the animation
+    pub fn sprite<'a>(&self, atlas: &'a Atlas) -> Option<&'a str> {
+        atlas.animation(&self.animation).map(|animation| {
+            animation.frames[animation.frame_index(self.time)]
+                .sprite
+                .as_str()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(durations: &[f64], mode: LoopMode) -> Animation {
+        Animation {
+            frames: durations
+                .iter()
+                .enumerate()
+                .map(|(index, &duration)| Frame {
+                    sprite: index.to_string(),
+                    duration,
+                })
+                .collect(),
+            mode,
+        }
+    }
+
+    fn indices(animation: &Animation, times: &[f64]) -> Vec<usize> {
+        times
+            .iter()
+            .map(|&time| animation.frame_index(time))
+            .collect()
+    }
+
+    #[test]
+    fn loops_back_to_the_first_frame() {
+        let animation = animation(&[0.5, 0.25], LoopMode::Loop);
+        assert_eq!(
+            indices(&animation, &[0.0, 0.25, 0.5, 0.625, 0.75, 1.25, 1.5]),
+            [0, 0, 1, 1, 0, 1, 0]
+        );
+    }
+
+    #[test]
+    fn stays_on_the_last_frame_when_played_once() {
+        let animation = animation(&[0.5, 0.25], LoopMode::Once);
+        assert_eq!(indices(&animation, &[0.0, 0.5, 0.75, 100.0]), [0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn ping_pong_does_not_repeat_the_turning_frames() {
+        let animation = animation(&[0.5, 0.5, 0.5], LoopMode::PingPong);
+        assert_eq!(
+            indices(&animation, &[0.0, 0.5, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5]),
+            [0, 1, 2, 2, 1, 1, 0, 1]
+        );
+        let single = self::animation(&[0.5], LoopMode::PingPong);
+        assert_eq!(indices(&single, &[0.0, 0.75]), [0, 0]);
+    }
+
+    #[test]
+    fn animator_restarts_only_for_another_animation() {
+        let json = r#"{
+            "pixels_per_square": 32,
+            "sprites": {
+                "a": { "rect": [0, 0, 32, 32] },
+                "b": { "rect": [32, 0, 32, 32] }
+            },
+            "animations": {
+                "walk": { "frames": [{ "sprite": "a", "duration": 0.5 }, { "sprite": "b", "duration": 0.5 }] },
+                "idle": { "frames": [{ "sprite": "b", "duration": 1.0 }], "mode": "once" }
+            }
+        }"#;
+        let atlas = Atlas::parse(json, (64, 32)).unwrap();
+        let mut animator = Animator::new("walk");
+        assert_eq!(animator.sprite(&atlas), Some("a"));
+        animator.update(Duration::from_millis(500));
+        animator.play("walk");
+        assert_eq!(animator.sprite(&atlas), Some("b"));
+        animator.play("idle");
+        animator.play("walk");
+        assert_eq!(animator.sprite(&atlas), Some("a"));
+        animator.play("jump");
+        assert_eq!(animator.sprite(&atlas), None);
+    }
+
+    #[test]
+    fn atlas_rejects_invalid_animations() {
+        let invalid = [
+            r#"{ "frames": [] }"#,
+            r#"{ "frames": [{ "sprite": "b", "duration": 0.5 }] }"#,
+            r#"{ "frames": [{ "sprite": "a", "duration": 0 }] }"#,
+            r#"{ "frames": [{ "sprite": "a", "duration": -1 }] }"#,
+            r#"{ "frames": [{ "sprite": "a", "duration": 0.5 }], "mode": "reverse" }"#,
+        ];
+        for animation in invalid {
+            let json = format!(
+                r#"{{
+                    "pixels_per_square": 32,
+                    "sprites": {{ "a": {{ "rect": [0, 0, 32, 32] }} }},
+                    "animations": {{ "walk": {} }}
+                }}"#,
+                animation
+            );
+            assert!(Atlas::parse(&json, (32, 32)).is_err(), "{}", animation);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::borrow::Cow;
+
+/**
+ * Files the client needs from the `assets` directory.
+ *
+ * Release desktop builds embed them in the binary. Debug desktop builds read them from the assets
+ * directory instead & watch it, so art & shader changes show up without rebuilding. The browser
+ * downloads them before the game starts, except for what the loading screen needs.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Asset {
+    AtlasImage,
+    AtlasDefinition,
+    SpriteShader,
+    TextShader,
+}
+
+impl Asset {
+    /// Path relative to the assets directory
+    pub fn path(self) -> &'static str {
+        match self {
+            Asset::AtlasImage => "textures/atlas-1.png",
+            Asset::AtlasDefinition => "textures/atlas-1.json",
+            Asset::SpriteShader => "shaders/sprite.wgsl",
+            Asset::TextShader => "shaders/text.wgsl",
+        }
+    }
+
+    /// The version compiled into the binary, empty for assets the browser downloads
+    fn embedded(self) -> &'static [u8] {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Asset::AtlasImage => include_bytes!("../assets/textures/atlas-1.png"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Asset::AtlasDefinition => include_bytes!("../assets/textures/atlas-1.json"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Asset::SpriteShader => include_bytes!("../assets/shaders/sprite.wgsl"),
+            Asset::TextShader => include_bytes!("../assets/shaders/text.wgsl"),
+            #[cfg(target_arch = "wasm32")]
+            _ => &[],
+        }
+    }
+}
+
+/// Downloaded by the browser before the game starts, the text shader is embedded for the loading screen
+#[cfg(target_arch = "wasm32")]
+const FETCHED: [Asset; 3] = [
+    Asset::AtlasImage,
+    Asset::AtlasDefinition,
+    Asset::SpriteShader,
+];
+
+/// Asset downloads so far, for the loading screen
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))] // Only the browser downloads assets
+#[derive(Debug)]
+pub struct LoadProgress<'a> {
+    pub loaded: usize,
+    pub total: usize,
+    /// Set while waiting to retry a failed download
+    pub error: Option<&'a str>,
+}
+
+/**
+ * Loads assets, & in debug desktop builds reports which of them changed on disk.
+ */
+pub struct Assets {
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    watcher: Option<watch::AssetWatcher>,
+    /// Kept for the rest of the session, so they are only downloaded once
+    #[cfg(target_arch = "wasm32")]
+    fetched: std::collections::HashMap<Asset, Vec<u8>>,
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Assets {
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            watcher: watch::AssetWatcher::new(&watch::assets_dir()),
+            #[cfg(target_arch = "wasm32")]
+            fetched: std::collections::HashMap::new(),
+        }
+    }
+
+    #[cfg(all(not(debug_assertions), not(target_arch = "wasm32")))]
+    pub fn load(&self, asset: Asset) -> Cow<'_, [u8]> {
+        Cow::Borrowed(asset.embedded())
+    }
+
+    /// Downloaded assets must be fetched first, see `fetch`
+    #[cfg(target_arch = "wasm32")]
+    pub fn load(&self, asset: Asset) -> Cow<'_, [u8]> {
+        match self.fetched.get(&asset) {
+            Some(bytes) => Cow::Borrowed(bytes),
+            None => Cow::Borrowed(asset.embedded()),
+        }
+    }
+
+    /**
+     * Downloads the assets that are not embedded, reporting progress after every download.
+     * Failed downloads are retried until they succeed since the game can not start without them.
+     */
+    #[cfg(target_arch = "wasm32")]
+    pub async fn fetch(&mut self, mut on_progress: impl FnMut(&LoadProgress)) {
+        let total = FETCHED.len();
+        // Every request is started right away so they download in parallel
+        let requests: Vec<_> = FETCHED
+            .into_iter()
+            .filter(|asset| !self.fetched.contains_key(asset))
+            .map(|asset| (asset, web::request(asset.path())))
+            .collect();
+        on_progress(&LoadProgress {
+            loaded: self.fetched.len(),
+            total,
+            error: None,
+        });
+        for (asset, mut request) in requests {
+            loop {
+                match web::response_bytes(request).await {
+                    Ok(bytes) => {
+                        self.fetched.insert(asset, bytes);
+                        break;
+                    }
+                    Err(err) => {
+                        let error = format!("Failed to load {}: {}, retrying", asset.path(), err);
+                        log::warn!("{}", error);
+                        on_progress(&LoadProgress {
+                            loaded: self.fetched.len(),
+                            total,
+                            error: Some(&error),
+                        });
+                        web::sleep(web::RETRY_DELAY_MS).await;
+                        request = web::request(asset.path());
+                    }
+                }
+            }
+            on_progress(&LoadProgress {
+                loaded: self.fetched.len(),
+                total,
+                error: None,
+            });
+        }
+    }
+
+    /// Reads the asset from the assets directory, falls back to the embedded version if that fails
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub fn load(&self, asset: Asset) -> Cow<'_, [u8]> {
+        let path = watch::assets_dir().join(asset.path());
+        std::fs::read(&path).map(Cow::Owned).unwrap_or_else(|err| {
+            log::warn!(
+                "Using embedded {}, failed to read {:?}: {}",
+                asset.path(),
+                path,
+                err
+            );
+            Cow::Borrowed(asset.embedded())
+        })
+    }
+
+    /// Loads a text asset, invalid utf-8 is replaced
+    pub fn load_string(&self, asset: Asset) -> Cow<'_, str> {
+        match self.load(asset) {
+            Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+
+    /// Assets that changed on disk since the last call
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub fn changed(&mut self) -> Vec<Asset> {
+        self.watcher
+            .as_mut()
+            .map(|watcher| watcher.changed())
+            .unwrap_or_default()
+    }
+
+    /// Embedded assets never change
+    #[cfg(not(all(debug_assertions, not(target_arch = "wasm32"))))]
+    pub fn changed(&mut self) -> Vec<Asset> {
+        Vec::new()
+    }
+}
+
+/**
+ * Runs `create` & returns its result, or the validation error wgpu reported while it ran
+ * (e.g. a shader that does not compile) instead of panicking on it.
+ */
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+pub fn catch_validation_error<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> T,
+) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let result = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => Err(err.to_string()),
+        None => Ok(result),
+    }
+}
+
+/// Embedded assets are valid, so errors are left to wgpu's default handler
+#[cfg(not(all(debug_assertions, not(target_arch = "wasm32"))))]
+pub fn catch_validation_error<T>(
+    _device: &wgpu::Device,
+    create: impl FnOnce() -> T,
+) -> Result<T, String> {
+    Ok(create())
+}
+
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+mod watch {
+    use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{channel, Receiver};
+
+    use super::Asset;
+
+    const WATCHED: [Asset; 4] = [
+        Asset::AtlasImage,
+        Asset::AtlasDefinition,
+        Asset::SpriteShader,
+        Asset::TextShader,
+    ];
+
+    pub fn assets_dir() -> PathBuf {
+        PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+    }
+
+    pub struct AssetWatcher {
+        /// Stops watching when dropped
+        _watcher: RecommendedWatcher,
+        events: Receiver<notify::Result<notify::Event>>,
+    }
+
+    impl AssetWatcher {
+        pub fn new(dir: &Path) -> Option<Self> {
+            let (sender, events) = channel();
+            let mut watcher = notify::recommended_watcher(sender)
+                .map_err(|err| log::warn!("Failed to create asset watcher: {}", err))
+                .ok()?;
+            watcher
+                .watch(dir, RecursiveMode::Recursive)
+                .map_err(|err| log::warn!("Failed to watch {:?}: {}", dir, err))
+                .ok()?;
+            log::info!("Watching {:?} for asset changes", dir);
+            Some(AssetWatcher {
+                _watcher: watcher,
+                events,
+            })
+        }
+
+        /// Drains the pending file events, every changed asset is returned once
+        pub fn changed(&mut self) -> Vec<Asset> {
+            let mut changed = Vec::new();
+            for event in self.events.try_iter() {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        log::warn!("Asset watcher error: {}", err);
+                        continue;
+                    }
+                };
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+                for path in event.paths {
+                    let asset = WATCHED
+                        .into_iter()
+                        .find(|asset| path.ends_with(asset.path()));
+                    if let Some(asset) = asset.filter(|asset| !changed.contains(asset)) {
+                        changed.push(asset);
+                    }
+                }
+            }
+            changed
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use js_sys::{Promise, Uint8Array};
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    /// Assets are served next to the page, in the same layout as the assets directory
+    const ASSETS_URL: &str = "assets/";
+    pub const RETRY_DELAY_MS: i32 = 3000;
+
+    fn web_window() -> web_sys::Window {
+        web_sys::window().expect("Failed to get web window")
+    }
+
+    /// Starts downloading the asset at the given path, the browser's http cache applies
+    pub fn request(path: &str) -> Promise {
+        web_window().fetch_with_str(&format!("{}{}", ASSETS_URL, path))
+    }
+
+    pub async fn response_bytes(request: Promise) -> Result<Vec<u8>, String> {
+        let response: web_sys::Response = JsFuture::from(request)
+            .await
+            .and_then(|response| response.dyn_into())
+            .map_err(|err| format!("{:?}", err))?;
+        if !response.ok() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let buffer = response
+            .array_buffer()
+            .map(JsFuture::from)
+            .map_err(|err| format!("{:?}", err))?
+            .await
+            .map_err(|err| format!("{:?}", err))?;
+        Ok(Uint8Array::new(&buffer).to_vec())
+    }
+
+    pub async fn sleep(milliseconds: i32) {
+        let timeout = Promise::new(&mut |resolve, _reject| {
+            web_window()
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, milliseconds)
+                .unwrap_or_else(|err| panic!("Failed to set timeout: {:?}", err));
+        });
+        // The timeout never rejects
+        let _ = JsFuture::from(timeout).await;
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::atlas::Atlas;
+
+    const ALL: [Asset; 4] = [
+        Asset::AtlasImage,
+        Asset::AtlasDefinition,
+        Asset::SpriteShader,
+        Asset::TextShader,
+    ];
+
+    #[test]
+    fn embeds_the_files_in_the_assets_directory() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
+        for asset in ALL {
+            let path = std::path::Path::new(dir).join(asset.path());
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(asset.embedded(), &bytes[..], "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn atlas_definition_matches_its_image() {
+        let assets = Assets::new();
+        let image = image::load_from_memory(&assets.load(Asset::AtlasImage)).unwrap();
+        let definition = assets.load_string(Asset::AtlasDefinition);
+        let atlas = Atlas::parse(&definition, (image.width(), image.height())).unwrap();
+        assert!(atlas.get("player").is_some());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn reports_changed_assets_once() {
+        use std::time::{Duration, Instant};
+
+        let dir = std::env::temp_dir().join(format!("endless-assets-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shaders")).unwrap();
+        let mut watcher = watch::AssetWatcher::new(&dir).unwrap();
+        let path = dir.join(Asset::TextShader.path());
+        std::fs::write(&path, "first").unwrap();
+        std::fs::write(&path, "second").unwrap();
+        std::fs::write(dir.join("shaders/unknown.wgsl"), "").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut changed = Vec::new();
+        while changed.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+            changed = watcher.changed();
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(changed, [Asset::TextShader]);
+    }
+}
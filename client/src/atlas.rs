@@ -0,0 +1,321 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::animation::{Animation, Frame, LoopMode};
+use super::autotile::{Bitmask, TerrainSet};
+use super::sprite::{Layer, Sprite, NO_TINT};
+
+/**
+ * Atlas definition file, stored as json next to the atlas image. For example:
+ *
+ * {
+ *     "pixels_per_square": 32,
+ *     "sprites": {
+ *         "tree": { "rect": [32, 0, 32, 32], "pivot": [16, 32], "flags": ["flip_x"] }
+ *     },
+ *     "animations": {
+ *         "tree_sway": { "frames": [{ "sprite": "tree", "duration": 0.5 }], "mode": "ping_pong" }
+ *     },
+ *     "terrains": {
+ *         "sand": { "bitmask": "4bit", "default": "sand", "tiles": { "5": "sand_north_south" } }
+ *     }
+ * }
+ */
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AtlasFile {
+    /// Image pixels that cover one world square
+    pixels_per_square: u32,
+    sprites: HashMap<String, SpriteDefinition>,
+    #[serde(default)]
+    animations: HashMap<String, AnimationDefinition>,
+    #[serde(default)]
+    terrains: HashMap<String, TerrainDefinition>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpriteDefinition {
+    /// x, y, width & height in pixels, from the top-left corner of the image
+    rect: [u32; 4],
+    /// Point in the rect (in pixels from its top-left corner) placed at the position the sprite is
+    /// drawn at, the bottom-left corner of the rect if left out
+    pivot: Option<[f32; 2]>,
+    #[serde(default)]
+    flags: Vec<SpriteFlag>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationDefinition {
+    frames: Vec<FrameDefinition>,
+    #[serde(default)]
+    mode: LoopMode,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FrameDefinition {
+    sprite: String,
+    /// In seconds
+    duration: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TerrainDefinition {
+    #[serde(default)]
+    bitmask: Bitmask,
+    default: String,
+    /// Sprite names by neighbour mask
+    #[serde(default)]
+    tiles: HashMap<u8, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SpriteFlag {
+    /// Mirrored horizontally
+    FlipX,
+    /// Mirrored vertically
+    FlipY,
+}
+
+/// A sprite from the atlas, converted to world squares & texture coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasSprite {
+    /// [u_min, v_min, u_max, v_max], with min & max swapped for flipped sprites
+    pub atlas_rect: [f32; 4],
+    /// Width & height in world squares
+    pub size: [f32; 2],
+    /// Offset from the bottom-left corner of the sprite to its pivot, in world squares
+    pub pivot: [f32; 2],
+}
+
+/**
+ * Named sprites, animations & terrain sets in the atlas image, so art can add them without code
+ * changes.
+ */
+#[derive(Debug, Clone)]
+pub struct Atlas {
+    sprites: HashMap<String, AtlasSprite>,
+    animations: HashMap<String, Animation>,
+    terrains: HashMap<String, TerrainSet>,
+}
+
+impl Atlas {
+    /// Parses an atlas definition for an image of the given size in pixels
+    pub fn parse(json: &str, (image_width, image_height): (u32, u32)) -> Result<Self, String> {
+        let file: AtlasFile = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        if file.pixels_per_square == 0 {
+            return Err("pixels_per_square must be above 0".to_string());
+        }
+        let pixels_per_square = file.pixels_per_square as f32;
+        let mut sprites = HashMap::new();
+        for (name, definition) in file.sprites {
+            let [x, y, width, height] = definition.rect;
+            if width == 0
+                || height == 0
+                || x.saturating_add(width) > image_width
+                || y.saturating_add(height) > image_height
+            {
+                return Err(format!(
+                    "Sprite {} has rect {:?} outside of the {}x{} image",
+                    name, definition.rect, image_width, image_height
+                ));
+            }
+            let mut atlas_rect = [
+                x as f32 / image_width as f32,
+                y as f32 / image_height as f32,
+                (x + width) as f32 / image_width as f32,
+                (y + height) as f32 / image_height as f32,
+            ];
+            if definition.flags.contains(&SpriteFlag::FlipX) {
+                atlas_rect.swap(0, 2);
+            }
+            if definition.flags.contains(&SpriteFlag::FlipY) {
+                atlas_rect.swap(1, 3);
+            }
+            let [pivot_x, pivot_y] = definition.pivot.unwrap_or([0.0, height as f32]);
+            sprites.insert(
+                name,
+                AtlasSprite {
+                    atlas_rect,
+                    size: [
+                        width as f32 / pixels_per_square,
+                        height as f32 / pixels_per_square,
+                    ],
+                    // The world has y pointing up, the image has it pointing down
+                    pivot: [
+                        pivot_x / pixels_per_square,
+                        (height as f32 - pivot_y) / pixels_per_square,
+                    ],
+                },
+            );
+        }
+        let mut animations = HashMap::new();
+        for (name, definition) in file.animations {
+            if definition.frames.is_empty() {
+                return Err(format!("Animation {} has no frames", name));
+            }
+            let mut frames = Vec::new();
+            for frame in definition.frames {
+                if !sprites.contains_key(&frame.sprite) {
+                    return Err(format!(
+                        "Animation {} uses unknown sprite {}",
+                        name, frame.sprite
+                    ));
+                }
+                if !(frame.duration > 0.0 && frame.duration.is_finite()) {
+                    return Err(format!(
+                        "Animation {} has a frame with duration {}, it must be above 0",
+                        name, frame.duration
+                    ));
+                }
+                frames.push(Frame {
+                    sprite: frame.sprite,
+                    duration: frame.duration,
+                });
+            }
+            animations.insert(
+                name,
+                Animation {
+                    frames,
+                    mode: definition.mode,
+                },
+            );
+        }
+        let mut terrains = HashMap::new();
+        for (name, definition) in file.terrains {
+            let unknown = std::iter::once(&definition.default)
+                .chain(definition.tiles.values())
+                .find(|sprite| !sprites.contains_key(*sprite));
+            if let Some(sprite) = unknown {
+                return Err(format!("Terrain {} uses unknown sprite {}", name, sprite));
+            }
+            let invalid = definition
+                .tiles
+                .keys()
+                .find(|mask| !definition.bitmask.is_valid(**mask));
+            if let Some(mask) = invalid {
+                return Err(format!(
+                    "Terrain {} has a tile for mask {}, which its bitmask never produces",
+                    name, mask
+                ));
+            }
+            terrains.insert(
+                name,
+                TerrainSet {
+                    bitmask: definition.bitmask,
+                    default: definition.default,
+                    tiles: definition.tiles,
+                },
+            );
+        }
+        Ok(Atlas {
+            sprites,
+            animations,
+            terrains,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AtlasSprite> {
+        self.sprites.get(name)
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        self.animations.get(name)
+    }
+
+    pub fn terrain(&self, name: &str) -> Option<&TerrainSet> {
+        self.terrains.get(name)
+    }
+
+    /// The named sprite with its pivot at the given world position, none if the atlas lacks it
+    pub fn sprite(&self, name: &str, (x, y): (f64, f64), layer: Layer) -> Option<Sprite> {
+        self.get(name).map(|sprite| Sprite {
+            position: [x as f32 - sprite.pivot[0], y as f32 - sprite.pivot[1]],
+            size: sprite.size,
+            atlas_rect: sprite.atlas_rect,
+            tint: NO_TINT,
+            layer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_SIZE: (u32, u32) = (64, 32);
+
+    #[test]
+    fn converts_sprites_to_texture_coordinates_and_world_squares() {
+        let json = r#"{
+            "pixels_per_square": 16,
+            "sprites": {
+                "tree": { "rect": [32, 0, 32, 32], "pivot": [16, 32] },
+                "rock": { "rect": [0, 16, 16, 16] }
+            }
+        }"#;
+        let atlas = Atlas::parse(json, IMAGE_SIZE).unwrap();
+        let tree = atlas.get("tree").unwrap();
+        assert_eq!(tree.atlas_rect, [0.5, 0.0, 1.0, 1.0]);
+        assert_eq!(tree.size, [2.0, 2.0]);
+        assert_eq!(tree.pivot, [1.0, 0.0]);
+        // Without a pivot the sprite is placed by its bottom-left corner
+        let rock = atlas.get("rock").unwrap();
+        assert_eq!(rock.atlas_rect, [0.0, 0.5, 0.25, 1.0]);
+        assert_eq!(rock.pivot, [0.0, 0.0]);
+        assert!(atlas.get("bush").is_none());
+    }
+
+    #[test]
+    fn flipped_sprites_swap_their_texture_coordinates() {
+        let json = r#"{
+            "pixels_per_square": 32,
+            "sprites": {
+                "left": { "rect": [0, 0, 32, 32], "flags": ["flip_x"] },
+                "down": { "rect": [0, 0, 32, 32], "flags": ["flip_y"] },
+                "both": { "rect": [0, 0, 32, 32], "flags": ["flip_x", "flip_y"] }
+            }
+        }"#;
+        let atlas = Atlas::parse(json, IMAGE_SIZE).unwrap();
+        assert_eq!(atlas.get("left").unwrap().atlas_rect, [0.5, 0.0, 0.0, 1.0]);
+        assert_eq!(atlas.get("down").unwrap().atlas_rect, [0.0, 1.0, 0.5, 0.0]);
+        assert_eq!(atlas.get("both").unwrap().atlas_rect, [0.5, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn places_sprites_by_their_pivot() {
+        let json = r#"{
+            "pixels_per_square": 32,
+            "sprites": { "tree": { "rect": [32, 0, 32, 32], "pivot": [16, 32] } }
+        }"#;
+        let atlas = Atlas::parse(json, IMAGE_SIZE).unwrap();
+        let sprite = atlas.sprite("tree", (3.0, 4.0), Layer::Objects).unwrap();
+        assert_eq!(sprite.position, [2.5, 4.0]);
+        assert_eq!(sprite.size, [1.0, 1.0]);
+        assert_eq!(sprite.tint, NO_TINT);
+        assert_eq!(sprite.layer, Layer::Objects);
+        assert!(atlas.sprite("bush", (0.0, 0.0), Layer::Objects).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let invalid = [
+            // Not json
+            "sprites",
+            r#"{ "pixels_per_square": 0, "sprites": {} }"#,
+            r#"{ "pixels_per_square": 32, "sprites": {}, "scale": 2 }"#,
+            r#"{ "pixels_per_square": 32, "sprites": { "a": { "rect": [0, 0, 32, 32], "flags": ["spin"] } } }"#,
+            r#"{ "pixels_per_square": 32, "sprites": { "a": { "rect": [48, 0, 32, 32] } } }"#,
+            r#"{ "pixels_per_square": 32, "sprites": { "a": { "rect": [0, 16, 32, 32] } } }"#,
+            r#"{ "pixels_per_square": 32, "sprites": { "a": { "rect": [0, 0, 0, 32] } } }"#,
+            r#"{ "pixels_per_square": 32, "sprites": { "a": { "rect": [4294967295, 0, 32, 32] } } }"#,
+        ];
+        for json in invalid {
+            assert!(Atlas::parse(json, IMAGE_SIZE).is_err(), "{}", json);
+        }
+    }
+}
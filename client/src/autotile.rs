@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/**
+ * Which neighbours of a square pick its tile. Every neighbour with the same terrain sets a bit:
+ *
+ * 4bit: north 1, east 2, south 4, west 8, for 16 tiles.
+ *
+ * 8bit: north 1, north-east 2, east 4, south-east 8, south 16, south-west 32, west 64,
+ * north-west 128. A corner only counts when both sides next to it are set, which leaves 47 tiles.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Bitmask {
+    #[default]
+    #[serde(rename = "4bit")]
+    FourBit,
+    #[serde(rename = "8bit")]
+    EightBit,
+}
+
+/// Offsets of the neighbours in bit order, for each bitmask
+const FOUR_BIT_NEIGHBOURS: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const EIGHT_BIT_NEIGHBOURS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+impl Bitmask {
+    /// Mask of the neighbours `same` returns true for, given their offset from the square
+    pub fn mask(self, same: impl Fn((i32, i32)) -> bool) -> u8 {
+        match self {
+            Bitmask::FourBit => FOUR_BIT_NEIGHBOURS
+                .iter()
+                .enumerate()
+                .filter(|(_, offset)| same(**offset))
+                .fold(0, |mask, (bit, _)| mask | 1 << bit),
+            Bitmask::EightBit => {
+                let mask = EIGHT_BIT_NEIGHBOURS
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, offset)| same(**offset))
+                    .fold(0, |mask, (bit, _)| mask | 1 << bit);
+                reduce_corners(mask)
+            }
+        }
+    }
+
+    /// Whether `mask` can return the given mask, tiles for other masks would never be shown
+    pub fn is_valid(self, mask: u8) -> bool {
+        match self {
+            Bitmask::FourBit => mask < 16,
+            Bitmask::EightBit => reduce_corners(mask) == mask,
+        }
+    }
+}
+
+/// Clears the corners of an 8 bit mask that lack one of the sides next to them
+fn reduce_corners(mask: u8) -> u8 {
+    let mut reduced = mask;
+    for corner in [1, 3, 5, 7] {
+        let sides = 1 << (corner - 1) | 1 << ((corner + 1) % 8);
+        if mask & sides != sides {
+            reduced &= !(1 << corner);
+        }
+    }
+    reduced
+}
+
+/**
+ * Atlas sprites for one type of terrain, picked by which neighbours have the same terrain so
+ * edges & corners blend into the terrain around them.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerrainSet {
+    pub bitmask: Bitmask,
+    /// Sprite for masks without a tile of their own
+    pub default: String,
+    /// Sprite for each mask
+    pub tiles: HashMap<u8, String>,
+}
+
+impl TerrainSet {
+    /// Atlas sprite for a square, `same` tells if the neighbour at an offset has the same terrain
+    pub fn tile(&self, same: impl Fn((i32, i32)) -> bool) -> &str {
+        self.tiles
+            .get(&self.bitmask.mask(same))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::Atlas;
+
+    fn neighbours(offsets: &[(i32, i32)]) -> impl Fn((i32, i32)) -> bool + '_ {
+        move |offset| offsets.contains(&offset)
+    }
+
+    #[test]
+    fn four_bit_masks_count_the_sides() {
+        let bitmask = Bitmask::FourBit;
+        assert_eq!(bitmask.mask(neighbours(&[])), 0);
+        assert_eq!(bitmask.mask(neighbours(&[(0, 1), (0, -1)])), 5);
+        // Corners are ignored
+        assert_eq!(bitmask.mask(neighbours(&[(-1, 0), (1, 1)])), 8);
+        assert_eq!((0..=255).filter(|mask| bitmask.is_valid(*mask)).count(), 16);
+    }
+
+    #[test]
+    fn eight_bit_corners_need_both_sides() {
+        let bitmask = Bitmask::EightBit;
+        // North-east without east
+        assert_eq!(bitmask.mask(neighbours(&[(0, 1), (1, 1)])), 1);
+        assert_eq!(
+            bitmask.mask(neighbours(&[(0, 1), (1, 1), (1, 0)])),
+            1 | 2 | 4
+        );
+        assert_eq!(bitmask.mask(|_| true), 255);
+        assert!(!bitmask.is_valid(2));
+        assert!(bitmask.is_valid(1 | 2 | 4));
+        assert_eq!((0..=255).filter(|mask| bitmask.is_valid(*mask)).count(), 47);
+    }
+
+    #[test]
+    fn picks_the_tile_for_the_mask_or_the_default() {
+        let terrain = TerrainSet {
+            bitmask: Bitmask::FourBit,
+            default: "sand".to_string(),
+            tiles: HashMap::from([(5, "sand_north_south".to_string())]),
+        };
+        assert_eq!(
+            terrain.tile(neighbours(&[(0, 1), (0, -1)])),
+            "sand_north_south"
+        );
+        assert_eq!(terrain.tile(neighbours(&[(0, 1)])), "sand");
+    }
+
+    #[test]
+    fn atlas_rejects_invalid_terrains() {
+        let invalid = [
+            r#"{ "default": "b" }"#,
+            r#"{ "default": "a", "tiles": { "1": "b" } }"#,
+            r#"{ "default": "a", "tiles": { "16": "a" } }"#,
+            r#"{ "bitmask": "8bit", "default": "a", "tiles": { "2": "a" } }"#,
+            r#"{ "bitmask": "6bit", "default": "a" }"#,
+        ];
+        for terrain in invalid {
+            let json = format!(
+                r#"{{
+                    "pixels_per_square": 32,
+                    "sprites": {{ "a": {{ "rect": [0, 0, 32, 32] }} }},
+                    "terrains": {{ "sand": {} }}
+                }}"#,
+                terrain
+            );
+            assert!(Atlas::parse(&json, (32, 32)).is_err(), "{}", terrain);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+/// Buffers grow to at least this many bytes, so small meshes don't reallocate while they grow
+const MIN_CAPACITY: u64 = 1024;
+
+/**
+ * A gpu buffer that is allocated once with headroom and rewritten in place with `queue.write_buffer`.
+ * It is only reallocated (at twice the needed size) when the content no longer fits.
+ */
+pub struct GrowableBuffer {
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    buffer: wgpu::Buffer,
+    capacity: u64,
+    /// Bytes written by the last `write`
+    len: u64,
+}
+
+impl GrowableBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        capacity: u64,
+    ) -> Self {
+        let usage = usage | wgpu::BufferUsages::COPY_DST;
+        let capacity = aligned(capacity.max(MIN_CAPACITY));
+        GrowableBuffer {
+            label,
+            usage,
+            buffer: create_buffer(device, label, usage, capacity),
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Replaces the content of the buffer, reallocating it if the data does not fit
+    pub fn write<T: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[T],
+    ) {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let len = bytes.len() as u64;
+        if aligned(len) > self.capacity {
+            self.capacity = aligned(len * 2);
+            log::debug!("Growing {} to {} bytes", self.label, self.capacity);
+            self.buffer = create_buffer(device, self.label, self.usage, self.capacity);
+        }
+        // Writes must be a multiple of 4 bytes long, e.g. an uneven number of u16 indices is padded
+        if len.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+            queue.write_buffer(&self.buffer, 0, bytes);
+        } else {
+            let mut padded = bytes.to_vec();
+            padded.resize(aligned(len) as usize, 0);
+            queue.write_buffer(&self.buffer, 0, &padded);
+        }
+        self.len = len;
+    }
+
+    /// The part of the buffer written by the last `write`
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(..self.len)
+    }
+}
+
+fn create_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    usage: wgpu::BufferUsages,
+    size: u64,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+/// Rounds up to the alignment `queue.write_buffer` requires
+fn aligned(size: u64) -> u64 {
+    let alignment = wgpu::COPY_BUFFER_ALIGNMENT;
+    size.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_sizes_up_to_the_copy_alignment() {
+        assert_eq!(aligned(0), 0);
+        assert_eq!(aligned(1), wgpu::COPY_BUFFER_ALIGNMENT);
+        assert_eq!(
+            aligned(wgpu::COPY_BUFFER_ALIGNMENT),
+            wgpu::COPY_BUFFER_ALIGNMENT
+        );
+        assert_eq!(
+            aligned(wgpu::COPY_BUFFER_ALIGNMENT + 1),
+            2 * wgpu::COPY_BUFFER_ALIGNMENT
+        );
+        assert_eq!(aligned(MIN_CAPACITY), MIN_CAPACITY);
+    }
+}
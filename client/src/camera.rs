@@ -0,0 +1,297 @@
+/// Size of a square in logical pixels at the default zoom level
+const DEFAULT_SQUARE_SIZE: f64 = 96.0;
+/// Zoom limits, as the size of a square in logical pixels
+const MIN_SQUARE_SIZE: f64 = 32.0;
+const MAX_SQUARE_SIZE: f64 = 192.0;
+/// The target can move this many squares away from the center before the camera follows
+const DEAD_ZONE: f64 = 0.75;
+/// Distance outside of the dead zone that is close enough, the camera would creep towards it forever otherwise
+const FOLLOW_PRECISION: f64 = 0.001;
+/// How quickly the camera catches up with the target, higher is faster
+const FOLLOW_SPEED: f64 = 6.0;
+/// Targets further away than this (in squares) are jumped to instead of followed
+const MAX_FOLLOW_DISTANCE: f64 = 20.0;
+/// Offset in squares at full shake
+const MAX_SHAKE_OFFSET: f64 = 0.25;
+/// Shake strength lost per second
+const SHAKE_DECAY: f64 = 2.0;
+
+/**
+ * The view on the world: which world position is at the center of the screen and how large squares are.
+ *
+ * World coordinates are in squares with y pointing up, screen coordinates are logical pixels from
+ * the top-left corner of the window with y pointing down.
+ */
+#[derive(Debug)]
+pub struct Camera {
+    /// World position the camera looks at, without shake
+    position: (f64, f64),
+    /// Size of a square in logical pixels
+    square_size: f64,
+    /// Window size in logical pixels
+    viewport: (f64, f64),
+    /// Between 0 (still) & 1 (full shake), the offset grows with its square so small shakes stay subtle
+    shake: f64,
+    /// Seconds since the camera was created, drives the shake pattern
+    time: f64,
+}
+
+impl Camera {
+    pub fn new(position: (f64, f64), viewport: (f64, f64)) -> Self {
+        Camera {
+            position,
+            square_size: DEFAULT_SQUARE_SIZE,
+            viewport,
+            shake: 0.0,
+            time: 0.0,
+        }
+    }
+
+    pub fn resize(&mut self, viewport: (f64, f64)) {
+        self.viewport = viewport;
+    }
+
+    /// Window size in logical pixels
+    pub fn viewport(&self) -> (f64, f64) {
+        self.viewport
+    }
+
+    /**
+     * Moves the camera towards the target & calms the shake down, returns true if the view changed.
+     */
+    pub fn update(&mut self, target: (f64, f64), delta_time: f64) -> bool {
+        let previous = self.view_position();
+        self.time += delta_time;
+        self.shake = (self.shake - SHAKE_DECAY * delta_time).max(0.0);
+
+        let offset = (target.0 - self.position.0, target.1 - self.position.1);
+        let distance = (offset.0 * offset.0 + offset.1 * offset.1).sqrt();
+        if distance > MAX_FOLLOW_DISTANCE {
+            self.position = target;
+        } else if distance > DEAD_ZONE + FOLLOW_PRECISION {
+            // Only the part outside of the dead zone is caught up with, framerate independent
+            let catch_up =
+                (1.0 - (-FOLLOW_SPEED * delta_time).exp()) * (distance - DEAD_ZONE) / distance;
+            self.position.0 += offset.0 * catch_up;
+            self.position.1 += offset.1 * catch_up;
+        }
+        self.view_position() != previous
+    }
+
+    /// Zooms in (factor above 1) or out (below 1), within the zoom limits
+    pub fn zoom_by(&mut self, factor: f64) {
+        if factor.is_finite() && factor > 0.0 {
+            self.square_size = (self.square_size * factor).clamp(MIN_SQUARE_SIZE, MAX_SQUARE_SIZE);
+        }
+    }
+
+    /// Shakes the screen, strength between 0 & 1 adds up with shakes that are still going on
+    pub fn shake(&mut self, strength: f64) {
+        self.shake = (self.shake + strength).clamp(0.0, 1.0);
+    }
+
+    /// World position at the center of the screen, including shake
+    fn view_position(&self) -> (f64, f64) {
+        if self.shake == 0.0 {
+            return self.position;
+        }
+        // Overlapping sine waves are irregular enough to pass as noise
+        let amount = self.shake * self.shake * MAX_SHAKE_OFFSET;
+        let t = self.time;
+        (
+            self.position.0 + amount * ((t * 47.0).sin() * 0.6 + (t * 71.0).sin() * 0.4),
+            self.position.1 + amount * ((t * 53.0).sin() * 0.6 + (t * 89.0).sin() * 0.4),
+        )
+    }
+
+    pub fn world_to_screen(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (view_x, view_y) = self.view_position();
+        (
+            (x - view_x) * self.square_size + self.viewport.0 / 2.0,
+            self.viewport.1 / 2.0 - (y - view_y) * self.square_size,
+        )
+    }
+
+    pub fn screen_to_world(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (view_x, view_y) = self.view_position();
+        (
+            (x - self.viewport.0 / 2.0) / self.square_size + view_x,
+            (self.viewport.1 / 2.0 - y) / self.square_size + view_y,
+        )
+    }
+
+    /**
+     * Squares that are (partly) on screen, as ranges of x & y coordinates.
+     * One extra square is included on every side so new squares are ready before they come into view.
+     */
+    pub fn visible_squares(&self) -> (std::ops::Range<i32>, std::ops::Range<i32>) {
+        let (left, top) = self.screen_to_world((0.0, 0.0));
+        let (right, bottom) = self.screen_to_world(self.viewport);
+        (
+            (left.floor() as i32 - 1)..(right.ceil() as i32 + 1),
+            (bottom.floor() as i32 - 1)..(top.ceil() as i32 + 1),
+        )
+    }
+
+    /**
+     * The following is an explanation for the used projection matrix.
+     *
+     * Scaling x by (2 / horizontal_squares) & y by (2 / vertical_squares) will scale the squares to fit in the clip space.
+     * This is because the clip space has a width of 2 (-1 to +1), and we are trying to fit in "horizontal_squares" number of squares in the width
+     * and "vertical_squares" number of squares in the height, so (2 / horizontal_squares) will give us the size each square will need
+     * to be to fit inside the clip-space. Multiplying each grid coordinate by this number will resize each vertex to fit inside the grid.
+     * This gives us: clip_scale = 2 / horizontal_squares
+     *
+     * After this transformation the grid will be contained within the clip space (-1 to 1). However it will be streched!
+     * To solve this we add a correction scaling. We want each square to be of size square_size but after initial scaling they will have a width
+     * of (real_square_width = window_width / horizontal_squares). To correct this we want to find X for: (real_square_width * X = square_size).
+     * Some basic algebra:
+     * Given: real_square_width = window_width / horizontal_squares
+     * Find "correction" in: real_square_width * correction = square_size
+     * => real_square_width * correction = square_size
+     * => correction = square_size / real_square_width
+     * => correction = square_size / (window_width / horizontal_squares)
+     * => correction = square_size * (horizontal_squares / window_width)
+     *
+     * We want to scale each vector by both the clip_scale (to fit them in clip space) and correction (to give them correct size).
+     * As the final scaling factor we use:
+     * scale = clip_scale * correction
+     * => scale = (2 / horizontal_squares) * square_size * (horizontal_squares / window_width)
+     * => scale = (2 * square_size) * (1 / window_width)
+     * => scale = (2 * square_size) / window_width
+     * Which give us a final scaling factors of:
+     * scale_x = (2 * square_size) / window_width
+     * scale_y = (2 * square_size) / window_height
+     * [solution for height is analogous]
+     *
+     * Scale_x and scale_y are coincidentally also the width and height of a square in clip space. These two values are not the same
+     * because clip space goes from a constant -1 to 1, but the screen is a dynamic width and height. So if the width is greater than the
+     * height than the width of a square in clip space will be less than it's height in clip space.
+     *
+     * Finally the grid is moved by (-1 * scale_x * view_x) and (-1 * scale_y * view_y), where view is the world position the camera
+     * looks at. This puts that position in the middle of the screen. The camera looks at the center of the player's square, so the
+     * player is presented in the middle of the screen.
+     */
+    pub fn projection_matrix(&self) -> [[f32; 4]; 4] {
+        let (view_x, view_y) = self.view_position();
+        let scale_x = 2.0 * self.square_size / self.viewport.0;
+        let scale_y = 2.0 * self.square_size / self.viewport.1;
+        [
+            [scale_x as f32, 0.0, 0.0, 0.0],
+            [0.0, scale_y as f32, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [
+                (-scale_x * view_x) as f32,
+                (-scale_y * view_y) as f32,
+                0.0,
+                1.0,
+            ],
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEWPORT: (f64, f64) = (800.0, 600.0);
+
+    fn assert_close(a: (f64, f64), b: (f64, f64)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn converts_between_world_and_screen() {
+        let camera = Camera::new((2.0, 3.0), VIEWPORT);
+        assert_close(camera.world_to_screen((2.0, 3.0)), (400.0, 300.0));
+        // One square right & up is one square size right & up (screen y points down)
+        assert_close(
+            camera.world_to_screen((3.0, 4.0)),
+            (400.0 + DEFAULT_SQUARE_SIZE, 300.0 - DEFAULT_SQUARE_SIZE),
+        );
+        for point in [(0.0, 0.0), (-7.5, 12.25), (3.0, -1.0)] {
+            assert_close(camera.screen_to_world(camera.world_to_screen(point)), point);
+        }
+    }
+
+    #[test]
+    fn lists_the_visible_squares_with_a_margin() {
+        let camera = Camera::new(
+            (0.0, 0.0),
+            (4.0 * DEFAULT_SQUARE_SIZE, 2.0 * DEFAULT_SQUARE_SIZE),
+        );
+        assert_eq!(camera.visible_squares(), (-3..3, -2..2));
+    }
+
+    #[test]
+    fn follows_targets_outside_of_the_dead_zone() {
+        let mut camera = Camera::new((0.0, 0.0), VIEWPORT);
+        assert!(!camera.update((DEAD_ZONE / 2.0, 0.0), 0.1));
+        assert_eq!(camera.position, (0.0, 0.0));
+
+        assert!(camera.update((5.0, 0.0), 0.1));
+        assert!(camera.position.0 > 0.0 && camera.position.0 < 5.0 - DEAD_ZONE);
+        for _ in 0..100 {
+            camera.update((5.0, 0.0), 0.1);
+        }
+        // Catches up until the target is at the edge of the dead zone, then stops
+        assert!((camera.position.0 - (5.0 - DEAD_ZONE)).abs() <= FOLLOW_PRECISION);
+        assert!(!camera.update((5.0, 0.0), 0.1));
+
+        // Far targets (e.g. after respawning) are jumped to
+        assert!(camera.update((100.0, -50.0), 0.1));
+        assert_eq!(camera.position, (100.0, -50.0));
+    }
+
+    #[test]
+    fn zooms_within_the_limits() {
+        let mut camera = Camera::new((0.0, 0.0), VIEWPORT);
+        camera.zoom_by(1.5);
+        assert_eq!(camera.square_size, DEFAULT_SQUARE_SIZE * 1.5);
+        camera.zoom_by(100.0);
+        assert_eq!(camera.square_size, MAX_SQUARE_SIZE);
+        camera.zoom_by(0.0001);
+        assert_eq!(camera.square_size, MIN_SQUARE_SIZE);
+        for invalid in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+            camera.zoom_by(invalid);
+            assert_eq!(camera.square_size, MIN_SQUARE_SIZE);
+        }
+    }
+
+    #[test]
+    fn shakes_fade_out() {
+        let mut camera = Camera::new((0.0, 0.0), VIEWPORT);
+        camera.shake(0.4);
+        camera.shake(0.8);
+        assert_eq!(camera.shake, 1.0);
+        assert!(camera.update((0.0, 0.0), 0.01));
+        let (x, y) = camera.view_position();
+        assert!(x.abs() <= MAX_SHAKE_OFFSET && y.abs() <= MAX_SHAKE_OFFSET);
+        // Fully calmed down after half a second
+        camera.update((0.0, 0.0), 0.5);
+        assert_eq!(camera.view_position(), (0.0, 0.0));
+        assert!(!camera.update((0.0, 0.0), 0.1));
+    }
+
+    #[test]
+    fn the_projection_centers_the_view() {
+        let camera = Camera::new((1.0, 2.0), VIEWPORT);
+        let matrix = camera.projection_matrix();
+        let clip = |(x, y): (f32, f32)| {
+            (
+                matrix[0][0] * x + matrix[3][0],
+                matrix[1][1] * y + matrix[3][1],
+            )
+        };
+        assert_eq!(clip((1.0, 2.0)), (0.0, 0.0));
+        // Half the viewport away from the center is the edge of clip space
+        let half_width = (VIEWPORT.0 / 2.0 / DEFAULT_SQUARE_SIZE) as f32;
+        let (x, _y) = clip((1.0 + half_width, 2.0));
+        assert!((x - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+
+use super::text::{Color, TextAlign, TextAnchor, TextRenderer, TextSection};
+
+/// Amount of lines kept in the chat log
+const MAX_LINES: usize = 50;
+/// Matches the server side limit, longer messages would be rejected anyway
+const MAX_INPUT_LENGTH: usize = 200;
+/// Messages starting with this prefix are only sent to nearby players
+const LOCAL_PREFIX: &str = "/l ";
+
+/// Overlay layout in logical pixels
+const FONT_SIZE: f32 = 16.0;
+const MARGIN: f32 = 12.0;
+const PADDING: f32 = 6.0;
+const OVERLAY_WIDTH: f32 = 480.0;
+/// Amount of log lines shown while typing, fewer are shown otherwise to keep the world visible
+const VISIBLE_LINES_TYPING: usize = 10;
+const VISIBLE_LINES: usize = 5;
+
+const GLOBAL_COLOR: Color = [1.0, 1.0, 1.0, 1.0];
+const LOCAL_COLOR: Color = [0.6, 1.0, 0.6, 1.0];
+const SYSTEM_COLOR: Color = [1.0, 0.85, 0.3, 1.0];
+const BACKGROUND_COLOR: Color = [0.0, 0.0, 0.0, 0.5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatLineKind {
+    Global,
+    Local,
+    /// Messages from the game itself (connection status, rejected messages...)
+    System,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatLine {
+    pub kind: ChatLineKind,
+    pub sender: Option<String>,
+    pub text: String,
+}
+
+/**
+ * Client side chat state: the log of received messages and the text the player is typing.
+ */
+#[derive(Debug, Default)]
+pub struct Chat {
+    lines: VecDeque<ChatLine>,
+    /// Some while the input box is open
+    input: Option<String>,
+}
+
+impl Chat {
+    pub fn is_typing(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn open(&mut self) {
+        self.input.get_or_insert_with(String::new);
+    }
+
+    pub fn cancel(&mut self) {
+        self.input = None;
+    }
+
+    pub fn handle_character(&mut self, character: char) {
+        if let Some(input) = &mut self.input {
+            match character {
+                // Backspace
+                '\u{8}' => {
+                    input.pop();
+                }
+                c if c.is_control() => {}
+                c if input.chars().count() < MAX_INPUT_LENGTH => input.push(c),
+                _ => {}
+            }
+        }
+    }
+
+    /**
+     * Closes the input box and returns the chat message to send to the server, if anything was typed.
+     */
+    pub fn submit(&mut self) -> Option<String> {
+        let input = self.input.take()?;
+        let (channel, text) = match input.strip_prefix(LOCAL_PREFIX) {
+            Some(text) => ("local", text.trim()),
+            None => ("global", input.trim()),
+        };
+        if text.is_empty() {
+            None
+        } else {
+            Some(format!("chat|{}|{}", channel, text))
+        }
+    }
+
+    pub fn push_system(&mut self, text: String) {
+        self.push(ChatLine {
+            kind: ChatLineKind::System,
+            sender: None,
+            text,
+        });
+    }
+
+    /**
+     * Adds `chat|channel|name|text` and `chat_error|reason` server messages to the log.
+     * Returns false if the message is not chat related.
+     */
+    pub fn handle_server_message(&mut self, message: &str) -> bool {
+        let data: Vec<&str> = message.splitn(4, '|').collect();
+        match data[..] {
+            ["chat", channel, sender, text] => self.push(ChatLine {
+                kind: if channel == "local" {
+                    ChatLineKind::Local
+                } else {
+                    ChatLineKind::Global
+                },
+                sender: Some(sender.to_string()),
+                text: text.to_string(),
+            }),
+            ["chat_error", ..] => self.push_system(message["chat_error|".len()..].to_string()),
+            _ => return false,
+        }
+        true
+    }
+
+    /**
+     * Queues the chat log overlay & input box in the bottom-left corner of the window.
+     */
+    pub fn draw(&self, text_renderer: &mut TextRenderer, window_height: f32) {
+        let (char_width, line_height) = text_renderer.measure(" ", FONT_SIZE);
+        let chars_per_line = (((OVERLAY_WIDTH - 2.0 * PADDING) / char_width) as usize).max(1);
+        let visible_lines = if self.is_typing() {
+            VISIBLE_LINES_TYPING
+        } else {
+            VISIBLE_LINES
+        };
+        let rows = self.rows(chars_per_line, visible_lines);
+
+        let input_height = if self.is_typing() {
+            line_height + 2.0 * PADDING
+        } else {
+            0.0
+        };
+        let log_height = rows.len() as f32 * line_height;
+        let mut y = window_height - MARGIN - input_height - PADDING - log_height;
+        if !rows.is_empty() {
+            text_renderer.queue_rect(
+                MARGIN,
+                y - PADDING,
+                OVERLAY_WIDTH,
+                log_height + 2.0 * PADDING,
+                BACKGROUND_COLOR,
+            );
+        }
+        for (row, color) in &rows {
+            text_renderer.queue_text(&TextSection {
+                text: row,
+                anchor: TextAnchor::Screen {
+                    x: MARGIN + PADDING,
+                    y,
+                },
+                size: FONT_SIZE,
+                color: *color,
+                align: TextAlign::TopLeft,
+            });
+            y += line_height;
+        }
+
+        if let Some(input) = &self.input {
+            let y = window_height - MARGIN - input_height;
+            text_renderer.queue_rect(MARGIN, y, OVERLAY_WIDTH, input_height, BACKGROUND_COLOR);
+            text_renderer.queue_text(&TextSection {
+                text: &format!("> {}_", input_tail(input, chars_per_line)),
+                anchor: TextAnchor::Screen {
+                    x: MARGIN + PADDING,
+                    y: y + PADDING,
+                },
+                size: FONT_SIZE,
+                color: GLOBAL_COLOR,
+                align: TextAlign::TopLeft,
+            });
+        }
+    }
+
+    /// The most recent lines wrapped to fit in the overlay, oldest first
+    fn rows(&self, chars_per_line: usize, visible_lines: usize) -> Vec<(String, Color)> {
+        let mut rows: Vec<(String, Color)> = Vec::new();
+        for line in self.lines.iter().rev() {
+            let (text, color) = match (&line.kind, &line.sender) {
+                (ChatLineKind::Local, Some(sender)) => {
+                    (format!("{} (nearby): {}", sender, line.text), LOCAL_COLOR)
+                }
+                (_, Some(sender)) => (format!("{}: {}", sender, line.text), GLOBAL_COLOR),
+                (_, None) => (line.text.clone(), SYSTEM_COLOR),
+            };
+            let chars: Vec<char> = text.chars().collect();
+            for chunk in chars.chunks(chars_per_line).rev() {
+                rows.push((chunk.iter().collect(), color));
+            }
+            if rows.len() >= visible_lines {
+                break;
+            }
+        }
+        rows.truncate(visible_lines);
+        rows.reverse();
+        rows
+    }
+
+    fn push(&mut self, line: ChatLine) {
+        log::info!("[chat] {:?}: {}", line.sender, line.text);
+        if self.lines.len() == MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+/// The end of the input that fits in a line next to the `> ` prompt & `_` cursor
+fn input_tail(input: &str, chars_per_line: usize) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let start = (chars.len() + 3)
+        .saturating_sub(chars_per_line)
+        .min(chars.len());
+    chars[start..].iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str) -> Chat {
+        let mut chat = Chat::default();
+        chat.open();
+        text.chars().for_each(|c| chat.handle_character(c));
+        chat
+    }
+
+    #[test]
+    fn types_into_the_open_input_only() {
+        let mut chat = Chat::default();
+        chat.handle_character('a');
+        assert!(!chat.is_typing());
+
+        let mut chat = typed("hex\u{8}llo\r\t");
+        assert_eq!(chat.input.as_deref(), Some("hello"));
+        // Backspace on an empty input does nothing
+        let mut empty = typed("\u{8}");
+        assert_eq!(empty.input.as_deref(), Some(""));
+        empty.cancel();
+        assert!(!empty.is_typing());
+
+        for _ in 0..MAX_INPUT_LENGTH {
+            chat.handle_character('é');
+        }
+        assert_eq!(
+            chat.input.as_ref().unwrap().chars().count(),
+            MAX_INPUT_LENGTH
+        );
+    }
+
+    #[test]
+    fn submits_global_and_local_messages() {
+        assert_eq!(
+            typed("  hello | world ").submit().as_deref(),
+            Some("chat|global|hello | world")
+        );
+        assert_eq!(typed("/l hi").submit().as_deref(), Some("chat|local|hi"));
+        assert_eq!(typed("   ").submit(), None);
+        assert_eq!(typed("/l  ").submit(), None);
+        assert_eq!(Chat::default().submit(), None);
+
+        let mut chat = typed("hi");
+        chat.submit();
+        assert!(!chat.is_typing());
+    }
+
+    #[test]
+    fn logs_chat_messages_and_errors() {
+        let mut chat = Chat::default();
+        assert!(chat.handle_server_message("chat|global|alice|hi | there"));
+        assert!(chat.handle_server_message("chat|local|bob|psst"));
+        assert!(chat.handle_server_message("chat_error|Message is too long"));
+        assert!(!chat.handle_server_message("state|1|"));
+        assert!(!chat.handle_server_message("chat|global|alice"));
+
+        let rows = chat.rows(80, VISIBLE_LINES);
+        assert_eq!(
+            rows,
+            [
+                ("alice: hi | there".to_string(), GLOBAL_COLOR),
+                ("bob (nearby): psst".to_string(), LOCAL_COLOR),
+                ("Message is too long".to_string(), SYSTEM_COLOR),
+            ]
+        );
+    }
+
+    #[test]
+    fn wraps_the_most_recent_lines() {
+        let mut chat = Chat::default();
+        for index in 0..MAX_LINES + 5 {
+            chat.push_system(format!("line {}", index));
+        }
+        assert_eq!(chat.lines.len(), MAX_LINES);
+        chat.push_system("abcdefghij".to_string());
+        let rows: Vec<String> = chat.rows(4, 4).into_iter().map(|(row, _)| row).collect();
+        assert_eq!(rows, [" 54", "abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn shows_the_end_of_long_input() {
+        assert_eq!(input_tail("hello", 10), "hello");
+        assert_eq!(input_tail("hello", 6), "llo");
+        // Too narrow for the prompt & cursor
+        assert_eq!(input_tail("hello", 2), "");
+        assert_eq!(input_tail("", 1), "");
+    }
+}
@@ -1,10 +1,11 @@
 #![cfg(not(target_arch = "wasm32"))]
 use async_std::task::sleep;
 use instant::Duration;
-use std::thread;
+use std::{env, thread};
 use winit::{event_loop::EventLoop, window::WindowBuilder};
 
 use super::graphics;
+use super::network::ConnectOptions;
 
 pub fn run() {
     // Start logger, uses env variable MY_LOG_LEVEL to determine log level
@@ -24,5 +25,13 @@ pub fn run() {
             }
         })
     });
-    pollster::block_on(graphics::run_loop(event_loop, window));
+    // Fill in the main menu from the environment, e.g. export ENDLESS_NAME=player_1 ENDLESS_PASSWORD=secret_password
+    // ENDLESS_DISPLAY_NAME optionally sets the name shown above the player
+    let connect_options = ConnectOptions::new(
+        env::var("ENDLESS_SERVER").ok(),
+        env::var("ENDLESS_NAME").ok(),
+        env::var("ENDLESS_PASSWORD").ok(),
+        env::var("ENDLESS_DISPLAY_NAME").ok(),
+    );
+    pollster::block_on(graphics::run_loop(event_loop, window, connect_options));
 }
@@ -1,13 +1,14 @@
 #![cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::Closure;
 use wasm_bindgen::JsCast;
-use web_sys::Element;
+use web_sys::{Element, UrlSearchParams};
 use winit::dpi::LogicalSize;
 use winit::event_loop::EventLoop;
 use winit::platform::web::WindowExtWebSys;
 use winit::window::Window;
 
 use super::graphics;
+use super::network::ConnectOptions;
 
 fn init_logs() {
     // Start the panic hook if enabled
@@ -74,6 +75,24 @@ fn start_web_window() -> (Window, EventLoop<()>) {
     (window, event_loop)
 }
 
+/**
+ * Reads the main menu defaults from the page url, e.g. `?name=player_1&display_name=Player`.
+ * Passwords are never read from the url, they would end up in the browser history & server logs.
+ */
+fn connect_options(web_window: &web_sys::Window) -> Option<ConnectOptions> {
+    let params = web_window
+        .location()
+        .search()
+        .ok()
+        .and_then(|search| UrlSearchParams::new_with_str(&search).ok())?;
+    ConnectOptions::new(
+        params.get("server"),
+        params.get("name"),
+        None,
+        params.get("display_name"),
+    )
+}
+
 pub fn run() {
     init_logs();
 
@@ -83,5 +102,6 @@ pub fn run() {
         // Give this a go: https://rustwasm.github.io/wasm-bindgen/examples/closures.html
         log::warn!("Looping");
     });
-    wasm_bindgen_futures::spawn_local(graphics::run_loop(event_loop, window));
+    let connect_options = web_sys::window().and_then(|web_window| connect_options(&web_window));
+    wasm_bindgen_futures::spawn_local(graphics::run_loop(event_loop, window, connect_options));
 }
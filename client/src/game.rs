@@ -0,0 +1,541 @@
+use instant::Duration;
+use std::collections::VecDeque;
+
+use super::animation::Animator;
+use super::camera::Camera;
+use super::input::{Action, Bindings, Input};
+use endless_game_shared::{
+    components::{Collider, PlayerControlled, Sprite, Transform},
+    ecs::{Entities, Entity},
+    movement::{digital_input, Movement},
+    simulation::{self, FixedTimestep, TICKS_PER_SECOND},
+    world::{World, PLAYER_COLLIDER, SPAWN_POSITION},
+};
+
+/// The player moves at full speed once the cursor is this many squares away from it,
+/// and slower the closer it is to the player
+const CURSOR_FULL_SPEED: f64 = 1.5;
+/// Screen shake when the player runs into something
+const BUMP_SHAKE: f64 = 0.4;
+/// Predicted ticks kept to compare with the server, more than the round trip to any server takes
+const HISTORY_TICKS: usize = 2 * TICKS_PER_SECOND as usize;
+/// Atlas sprite of players, the local one plays the animations below instead
+pub const PLAYER_SPRITE: &str = "player";
+/// Atlas animation names
+pub const IDLE_ANIMATION: &str = "player_idle";
+pub const WALK_LEFT_ANIMATION: &str = "player_walk_left";
+pub const WALK_RIGHT_ANIMATION: &str = "player_walk_right";
+pub const WALK_UP_ANIMATION: &str = "player_walk_up";
+pub const WALK_DOWN_ANIMATION: &str = "player_walk_down";
+
+#[derive(Debug)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Walks in the direction of the larger velocity component, idles when standing still
+fn player_animation(movement: &Movement) -> &'static str {
+    let (x, y) = movement.velocity;
+    if !movement.is_moving() {
+        IDLE_ANIMATION
+    } else if x.abs() >= y.abs() {
+        if x < 0.0 {
+            WALK_LEFT_ANIMATION
+        } else {
+            WALK_RIGHT_ANIMATION
+        }
+    } else if y < 0.0 {
+        WALK_DOWN_ANIMATION
+    } else {
+        WALK_UP_ANIMATION
+    }
+}
+
+/// The camera follows the center of the player's square
+pub fn player_center((x, y): (f64, f64)) -> (f64, f64) {
+    (x + 0.5, y + 0.5)
+}
+
+/// Input the game state does not track itself, read from the devices & menus every update
+#[derive(Debug)]
+pub struct Controls<'a> {
+    pub bindings: &'a Bindings,
+    /// False while a menu is open, nothing moves then even if keys are still held
+    pub playing: bool,
+    /// Gamepad or touch stick with the deadzone removed, (0, 0) if neither is used
+    pub stick: (f64, f64),
+    /// Browsers also report touches as mouse clicks, the finger should not pull the player towards it
+    pub touching: bool,
+}
+
+/// The local player after a tick, as predicted from the input of that tick
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Prediction {
+    tick: u64,
+    input: (f64, f64),
+    position: (f64, f64),
+    velocity: (f64, f64),
+}
+
+/// The local player as simulated by the server, see `GameState::reconcile`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuthoritativeState {
+    /// The local tick this state matches, None until the server received input from us
+    pub tick: Option<u64>,
+    pub position: (f64, f64),
+    pub velocity: (f64, f64),
+}
+
+/// What an update changed, so the caller knows whether to redraw
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub player_moved: bool,
+    pub camera_moved: bool,
+}
+
+/**
+ * Everything the game simulates: the player, the world & the players the server reports, along
+ * with the input that drives them. It knows nothing about the window or the gpu, so updates also
+ * run in tests, & the renderer only reads it to draw.
+ */
+#[derive(Debug)]
+pub struct GameState {
+    /// World objects, the local player & the players the server reports, see `players`
+    pub entities: Entities,
+    /// The local player, moved by the input & animated by its `Animator`
+    pub player: Entity,
+    /// Terrain & the objects spawned into the entities
+    pub world: World,
+    /// Whether the player ran into something last tick, only new collisions shake the screen
+    blocked: bool,
+    /// Splits the frame times into the fixed ticks the server simulates as well
+    timestep: FixedTimestep,
+    /// Ticks simulated so far
+    tick: u64,
+    /// Tick the current input was first simulated at
+    input_tick: u64,
+    /// The last HISTORY_TICKS predictions, oldest first
+    history: VecDeque<Prediction>,
+    pub camera: Camera,
+    /// Keys & mouse buttons held down, read through the action bindings of the settings
+    pub input: Input,
+    /// From 0 to 1 across the window, from the top-left corner
+    pub cursor: Position,
+}
+
+impl GameState {
+    /// The player starts at the spawn position of the default world, the viewport is in logical pixels
+    pub fn new(viewport: (f64, f64)) -> Self {
+        let mut entities = Entities::default();
+        let player = entities.spawn();
+        entities.insert(player, PlayerControlled);
+        entities.insert(player, Transform::new(SPAWN_POSITION.0, SPAWN_POSITION.1));
+        // Velocity & input, simulated the same way as on the server
+        entities.insert(player, Movement::default());
+        entities.insert(player, Collider(PLAYER_COLLIDER));
+        entities.insert(player, Sprite(PLAYER_SPRITE.to_string()));
+        entities.insert(player, Animator::new(IDLE_ANIMATION));
+        let world = World::default();
+        world.spawn_objects(&mut entities);
+        GameState {
+            camera: Camera::new(player_center(SPAWN_POSITION), viewport),
+            entities,
+            player,
+            world,
+            blocked: false,
+            timestep: FixedTimestep::default(),
+            tick: 0,
+            input_tick: 0,
+            history: VecDeque::with_capacity(HISTORY_TICKS),
+            input: Input::default(),
+            cursor: Position { x: 0.0, y: 0.0 },
+        }
+    }
+
+    /// Position of the local player's bottom-left corner in squares
+    pub fn player_position(&self) -> (f64, f64) {
+        self.entities
+            .get::<Transform>(self.player)
+            .map_or((0.0, 0.0), Transform::position)
+    }
+
+    pub fn player_movement(&self) -> Movement {
+        self.entities
+            .get::<Movement>(self.player)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn player_animator(&self) -> Option<&Animator> {
+        self.entities.get(self.player)
+    }
+
+    /// The tick the current input direction was first simulated at & the direction, sent to the server
+    pub fn player_input(&self) -> (u64, (f64, f64)) {
+        (self.input_tick, self.player_movement().input)
+    }
+
+    /// Puts the player back to where the server spawns players, standing still
+    pub fn respawn(&mut self) {
+        self.set_player_state(SPAWN_POSITION, (0.0, 0.0));
+        self.history.clear();
+    }
+
+    /**
+     * Corrects the local player with the state the server simulated for it, returns whether the
+     * player changed.
+     *
+     * The server reports which of our ticks its state matches. If our prediction of that tick is
+     * off (e.g. the server received an input later than we started using it), the player is reset
+     * to the server's state & the inputs of every later tick are simulated again. States of
+     * ticks missing from the history are taken as they are.
+     */
+    pub fn reconcile(&mut self, state: &AuthoritativeState) -> bool {
+        let index = state.tick.and_then(|tick| {
+            self.history
+                .iter()
+                .position(|prediction| prediction.tick == tick)
+        });
+        let index = match index {
+            Some(index) => index,
+            None => return self.snap(state),
+        };
+        // The server only reports later ticks from now on
+        self.history.drain(..index);
+        let predicted = &mut self.history[0];
+        if (predicted.position, predicted.velocity) == (state.position, state.velocity) {
+            return false;
+        }
+        (predicted.position, predicted.velocity) = (state.position, state.velocity);
+        let input = self.player_movement().input;
+        self.set_player_state(state.position, state.velocity);
+        for index in 1..self.history.len() {
+            if let Some(movement) = self.entities.get_mut::<Movement>(self.player) {
+                movement.input = self.history[index].input;
+            }
+            simulation::step(&mut self.entities);
+            let prediction = &mut self.history[index];
+            prediction.position = self
+                .entities
+                .get::<Transform>(self.player)
+                .map_or(SPAWN_POSITION, Transform::position);
+            prediction.velocity = self
+                .entities
+                .get::<Movement>(self.player)
+                .map_or((0.0, 0.0), |movement| movement.velocity);
+        }
+        if let Some(movement) = self.entities.get_mut::<Movement>(self.player) {
+            movement.input = input;
+        }
+        true
+    }
+
+    /// Takes a state that can not be compared with a prediction as it is
+    fn snap(&mut self, state: &AuthoritativeState) -> bool {
+        // The server is ahead if we skipped ticks (e.g. while in the background), catch up with it
+        if let Some(tick) = state.tick.filter(|tick| *tick > self.tick) {
+            self.tick = tick;
+        }
+        self.history.clear();
+        let movement = self.player_movement();
+        if (self.player_position(), movement.velocity) == (state.position, state.velocity) {
+            return false;
+        }
+        self.set_player_state(state.position, state.velocity);
+        true
+    }
+
+    fn set_player_state(&mut self, (x, y): (f64, f64), velocity: (f64, f64)) {
+        self.entities.insert(self.player, Transform::new(x, y));
+        if let Some(movement) = self.entities.get_mut::<Movement>(self.player) {
+            movement.velocity = velocity;
+        }
+    }
+
+    /**
+     * Moves the player by its input & the camera after it. Keys, the stick & the cursor all result
+     * in an input direction, the first one in use wins. The player moves in the fixed ticks of the
+     * shared simulation, the camera & animations every frame.
+     */
+    pub fn update(&mut self, controls: &Controls, time_elapsed: Duration) -> Changes {
+        let delta_time = time_elapsed.as_secs_f64();
+        let (bindings, playing) = (controls.bindings, controls.playing);
+        let axis = |negative: Action, positive: Action| {
+            (playing && self.input.is_active(bindings, positive)) as i8
+                - (playing && self.input.is_active(bindings, negative)) as i8
+        };
+        let keys = (
+            axis(Action::MoveLeft, Action::MoveRight),
+            axis(Action::MoveDown, Action::MoveUp),
+        );
+        let move_to_cursor =
+            playing && !controls.touching && self.input.is_active(bindings, Action::MoveToCursor);
+        let input = if keys != (0, 0) {
+            digital_input(keys.0, keys.1)
+        } else if playing && controls.stick != (0.0, 0.0) {
+            // The stick deadzones were already removed, the stick position itself sets the speed
+            controls.stick
+        } else if move_to_cursor {
+            let (width, height) = self.camera.viewport();
+            let (cursor_x, cursor_y) = self
+                .camera
+                .screen_to_world((self.cursor.x * width, self.cursor.y * height));
+            let (player_x, player_y) = player_center(self.player_position());
+            (
+                (cursor_x - player_x) / CURSOR_FULL_SPEED,
+                (cursor_y - player_y) / CURSOR_FULL_SPEED,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        if let Some(movement) = self.entities.get_mut::<Movement>(self.player) {
+            let previous = movement.input;
+            movement.set_input(input);
+            if movement.input != previous {
+                self.input_tick = self.tick;
+            }
+        }
+
+        let mut player_moved = false;
+        for _ in 0..self.timestep.advance(time_elapsed) {
+            let previous = self.player_position();
+            let deltas = simulation::step(&mut self.entities);
+            self.tick += 1;
+            let movement = self.player_movement();
+            if self.history.len() == HISTORY_TICKS {
+                self.history.pop_front();
+            }
+            self.history.push_back(Prediction {
+                tick: self.tick,
+                input: movement.input,
+                position: self.player_position(),
+                velocity: movement.velocity,
+            });
+            if !movement.is_moving() {
+                continue;
+            }
+            player_moved = true;
+            let delta = deltas
+                .iter()
+                .find(|(entity, _delta)| *entity == self.player)
+                .map_or((0.0, 0.0), |(_entity, delta)| *delta);
+            let (x, y) = self.player_position();
+            // Running into something head-on gives a small bump, sliding along it does not
+            let blocked = (x - previous.0).abs() + (y - previous.1).abs()
+                < 0.1 * (delta.0.abs() + delta.1.abs());
+            if blocked && !self.blocked {
+                self.camera.shake(BUMP_SHAKE);
+            }
+            self.blocked = blocked;
+        }
+        let movement = self.player_movement();
+        if let Some(animator) = self.entities.get_mut::<Animator>(self.player) {
+            animator.play(player_animation(&movement));
+            animator.update(time_elapsed);
+        }
+        let camera_moved = self
+            .camera
+            .update(player_center(self.player_position()), delta_time);
+        Changes {
+            player_moved,
+            camera_moved,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+    const VIEWPORT: (f64, f64) = (800.0, 600.0);
+    const STEP: Duration = Duration::from_millis(16);
+
+    fn run(game: &mut GameState, controls: &Controls, seconds: f64) {
+        for _ in 0..(seconds / STEP.as_secs_f64()) as u32 {
+            game.update(controls, STEP);
+        }
+    }
+
+    fn controls(bindings: &Bindings) -> Controls<'_> {
+        Controls {
+            bindings,
+            playing: true,
+            stick: (0.0, 0.0),
+            touching: false,
+        }
+    }
+
+    #[test]
+    fn held_keys_move_the_player() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        game.input
+            .handle_key(VirtualKeyCode::Up, ElementState::Pressed);
+        // Frames shorter than a tick do not move anything yet
+        assert!(!game.update(&controls(&bindings), STEP / 2).player_moved);
+        assert!(game.update(&controls(&bindings), STEP).player_moved);
+        run(&mut game, &controls(&bindings), 1.0);
+        let (x, y) = game.player_position();
+        assert!(y > 1.0);
+        assert_eq!(x, 0.0);
+
+        game.input
+            .handle_key(VirtualKeyCode::Up, ElementState::Released);
+        run(&mut game, &controls(&bindings), 1.0);
+        assert!(!game.player_movement().is_moving());
+    }
+
+    #[test]
+    fn nothing_moves_while_a_menu_is_open() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        game.input
+            .handle_key(VirtualKeyCode::Right, ElementState::Pressed);
+        let controls = Controls {
+            playing: false,
+            stick: (1.0, 0.0),
+            ..controls(&bindings)
+        };
+        run(&mut game, &controls, 1.0);
+        assert_eq!(game.player_position(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn trees_block_the_player() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        // The default world has a tree at (2, -1)
+        *game.entities.get_mut(game.player).unwrap() = Transform::new(0.0, -1.0);
+        game.input
+            .handle_key(VirtualKeyCode::Right, ElementState::Pressed);
+        run(&mut game, &controls(&bindings), 2.0);
+        let (x, y) = game.player_position();
+        assert!(x > 1.0 && x < 2.0);
+        assert_eq!(y, -1.0);
+    }
+
+    #[test]
+    fn the_player_walks_towards_the_held_cursor() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        // Left of the player, half way to the left edge of the window
+        game.cursor = Position { x: 0.25, y: 0.5 };
+        game.input
+            .handle_mouse(MouseButton::Left, ElementState::Pressed);
+        run(&mut game, &controls(&bindings), 0.5);
+        assert!(game.player_position().0 < -0.5);
+
+        // Touches are reported as clicks too, they do not pull the player along
+        let mut game = GameState::new(VIEWPORT);
+        game.cursor = Position { x: 0.25, y: 0.5 };
+        game.input
+            .handle_mouse(MouseButton::Left, ElementState::Pressed);
+        let controls = Controls {
+            touching: true,
+            ..controls(&bindings)
+        };
+        run(&mut game, &controls, 0.5);
+        assert_eq!(game.player_position().0, 0.0);
+    }
+
+    #[test]
+    fn keys_win_over_the_stick() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        game.input
+            .handle_key(VirtualKeyCode::Left, ElementState::Pressed);
+        let controls = Controls {
+            stick: (1.0, 0.0),
+            ..controls(&bindings)
+        };
+        run(&mut game, &controls, 0.5);
+        assert!(game.player_position().0 < 0.0);
+    }
+
+    #[test]
+    fn input_is_sent_with_the_tick_it_started_at() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        run(&mut game, &controls(&bindings), 0.5);
+        assert_eq!(game.player_input(), (0, (0.0, 0.0)));
+        let tick = game.tick;
+        game.input
+            .handle_key(VirtualKeyCode::Right, ElementState::Pressed);
+        run(&mut game, &controls(&bindings), 0.5);
+        assert_eq!(game.player_input(), (tick, (1.0, 0.0)));
+    }
+
+    #[test]
+    fn matching_server_states_change_nothing() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        game.input
+            .handle_key(VirtualKeyCode::Right, ElementState::Pressed);
+        run(&mut game, &controls(&bindings), 0.5);
+        let position = game.player_position();
+        let predicted = game.history[10];
+        assert!(!game.reconcile(&AuthoritativeState {
+            tick: Some(predicted.tick),
+            position: predicted.position,
+            velocity: predicted.velocity,
+        }));
+        assert_eq!(game.player_position(), position);
+        // Older predictions are dropped, the newer ones kept
+        assert_eq!(game.history[0], predicted);
+    }
+
+    #[test]
+    fn corrections_replay_the_later_inputs() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        game.input
+            .handle_key(VirtualKeyCode::Right, ElementState::Pressed);
+        run(&mut game, &controls(&bindings), 0.25);
+        game.input
+            .handle_key(VirtualKeyCode::Right, ElementState::Released);
+        run(&mut game, &controls(&bindings), 0.25);
+        let (x, y) = game.player_position();
+        let input = game.player_input();
+
+        // The server saw us half a square further up a few ticks into walking right
+        let predicted = game.history[5];
+        assert!(game.reconcile(&AuthoritativeState {
+            tick: Some(predicted.tick),
+            position: (predicted.position.0, predicted.position.1 + 0.5),
+            velocity: predicted.velocity,
+        }));
+        // Walking right & stopping again is simulated from there, ending up as far right as before
+        assert_eq!(game.player_position(), (x, y + 0.5));
+        assert!(!game.player_movement().is_moving());
+        assert_eq!(game.player_input(), input);
+        let last = game.history.back().copied().unwrap();
+        assert_eq!(last.position, (x, y + 0.5));
+    }
+
+    #[test]
+    fn server_states_of_unknown_ticks_are_taken_as_they_are() {
+        let bindings = Bindings::default();
+        let mut game = GameState::new(VIEWPORT);
+        run(&mut game, &controls(&bindings), 0.25);
+        let state = AuthoritativeState {
+            tick: None,
+            position: (3.0, 4.0),
+            velocity: (0.0, 0.0),
+        };
+        assert!(game.reconcile(&state));
+        assert_eq!(game.player_position(), (3.0, 4.0));
+        assert!(!game.reconcile(&state));
+
+        // A server ahead of us moves our clock forward
+        assert!(!game.reconcile(&AuthoritativeState {
+            tick: Some(1000),
+            ..state
+        }));
+        assert_eq!(game.tick, 1000);
+
+        game.respawn();
+        assert_eq!(game.player_position(), SPAWN_POSITION);
+    }
+}
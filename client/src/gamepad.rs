@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+        pub use super::gamepad_wasm::Gamepads;
+    } else {
+        pub use super::gamepad_desktop::Gamepads;
+    }
+}
+
+/// Stick input below this distance from the center is ignored, worn sticks never quite return to 0
+const STICK_DEADZONE: f64 = 0.2;
+
+/// Gamepad buttons named after their position on the standard (xbox-like) layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Mode,
+}
+
+/**
+ * Removes the deadzone from a stick position (x right, y up), the remaining range is stretched so
+ * the stick still reaches full speed at its edge.
+ */
+pub fn apply_deadzone((x, y): (f64, f64)) -> (f64, f64) {
+    let magnitude = (x * x + y * y).sqrt();
+    if magnitude < STICK_DEADZONE {
+        return (0.0, 0.0);
+    }
+    let scale = ((magnitude - STICK_DEADZONE) / (1.0 - STICK_DEADZONE)).min(1.0) / magnitude;
+    (x * scale, y * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn length((x, y): (f64, f64)) -> f64 {
+        (x * x + y * y).sqrt()
+    }
+
+    #[test]
+    fn ignores_the_stick_within_the_deadzone() {
+        assert_eq!(apply_deadzone((0.0, 0.0)), (0.0, 0.0));
+        assert_eq!(apply_deadzone((0.1, -0.1)), (0.0, 0.0));
+        assert_eq!(apply_deadzone((0.0, STICK_DEADZONE * 0.99)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn stretches_the_rest_of_the_range_keeping_the_direction() {
+        assert!(length(apply_deadzone((STICK_DEADZONE, 0.0))) < 1e-9);
+        let (x, y) = apply_deadzone((0.0, -(1.0 + STICK_DEADZONE) / 2.0));
+        assert_eq!(x, 0.0);
+        assert!((y - -0.5).abs() < 1e-9);
+        // Full speed at the edge, even for sticks that report a bit more than 1 in the corners
+        assert!((length(apply_deadzone((1.0, 0.0))) - 1.0).abs() < 1e-9);
+        let (x, y) = apply_deadzone((0.9, 0.9));
+        assert!((length((x, y)) - 1.0).abs() < 1e-9);
+        assert_eq!(x, y);
+    }
+}
@@ -0,0 +1,97 @@
+#![cfg(not(target_arch = "wasm32"))]
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use winit::event::ElementState;
+
+use super::gamepad::GamepadButton;
+
+/**
+ * Gamepads connected to the computer, read through gilrs.
+ */
+pub struct Gamepads {
+    /// None if the platform's gamepad API could not be opened, the game then runs without gamepads
+    gilrs: Option<Gilrs>,
+    /// Gamepad that was used last, its stick moves the player
+    active: Option<GamepadId>,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        let gilrs = Gilrs::new()
+            .map_err(|err| log::warn!("Gamepads are not available: {}", err))
+            .ok();
+        Gamepads {
+            gilrs,
+            active: None,
+        }
+    }
+
+    /// Button presses & releases since the last poll
+    pub fn poll(&mut self) -> Vec<(GamepadButton, ElementState)> {
+        let gilrs = match &mut self.gilrs {
+            Some(gilrs) => gilrs,
+            None => return Vec::new(),
+        };
+        let mut events = Vec::new();
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _code) => {
+                    self.active = Some(event.id);
+                    events.extend(map_button(button).map(|button| (button, ElementState::Pressed)));
+                }
+                EventType::ButtonReleased(button, _code) => {
+                    events
+                        .extend(map_button(button).map(|button| (button, ElementState::Released)));
+                }
+                EventType::AxisChanged(..) => self.active = Some(event.id),
+                EventType::Connected => {
+                    log::info!("Gamepad connected: {}", gilrs.gamepad(event.id).name())
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {}", gilrs.gamepad(event.id).name());
+                    if self.active == Some(event.id) {
+                        self.active = None;
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Left stick position of the active gamepad, x right & y up, without deadzone
+    pub fn stick(&self) -> (f64, f64) {
+        match (&self.gilrs, self.active) {
+            (Some(gilrs), Some(id)) => {
+                let gamepad = gilrs.gamepad(id);
+                (
+                    gamepad.value(Axis::LeftStickX) as f64,
+                    gamepad.value(Axis::LeftStickY) as f64,
+                )
+            }
+            _ => (0.0, 0.0),
+        }
+    }
+}
+
+fn map_button(button: Button) -> Option<GamepadButton> {
+    match button {
+        Button::South => Some(GamepadButton::South),
+        Button::East => Some(GamepadButton::East),
+        Button::West => Some(GamepadButton::West),
+        Button::North => Some(GamepadButton::North),
+        Button::LeftTrigger => Some(GamepadButton::LeftBumper),
+        Button::RightTrigger => Some(GamepadButton::RightBumper),
+        Button::LeftTrigger2 => Some(GamepadButton::LeftTrigger),
+        Button::RightTrigger2 => Some(GamepadButton::RightTrigger),
+        Button::Select => Some(GamepadButton::Select),
+        Button::Start => Some(GamepadButton::Start),
+        Button::LeftThumb => Some(GamepadButton::LeftStick),
+        Button::RightThumb => Some(GamepadButton::RightStick),
+        Button::DPadUp => Some(GamepadButton::DPadUp),
+        Button::DPadDown => Some(GamepadButton::DPadDown),
+        Button::DPadLeft => Some(GamepadButton::DPadLeft),
+        Button::DPadRight => Some(GamepadButton::DPadRight),
+        Button::Mode => Some(GamepadButton::Mode),
+        _ => None,
+    }
+}
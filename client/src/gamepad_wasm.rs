@@ -0,0 +1,137 @@
+#![cfg(target_arch = "wasm32")]
+use std::collections::HashMap;
+use wasm_bindgen::JsCast;
+use web_sys::{Gamepad, GamepadButton as WebGamepadButton, GamepadMappingType};
+use winit::event::ElementState;
+
+use super::gamepad::GamepadButton;
+
+/// Button order of the "standard" gamepad mapping: https://w3c.github.io/gamepad/#remapping
+const STANDARD_BUTTONS: [GamepadButton; 17] = [
+    GamepadButton::South,
+    GamepadButton::East,
+    GamepadButton::West,
+    GamepadButton::North,
+    GamepadButton::LeftBumper,
+    GamepadButton::RightBumper,
+    GamepadButton::LeftTrigger,
+    GamepadButton::RightTrigger,
+    GamepadButton::Select,
+    GamepadButton::Start,
+    GamepadButton::LeftStick,
+    GamepadButton::RightStick,
+    GamepadButton::DPadUp,
+    GamepadButton::DPadDown,
+    GamepadButton::DPadLeft,
+    GamepadButton::DPadRight,
+    GamepadButton::Mode,
+];
+
+/**
+ * Gamepads connected to the browser, read through the Gamepad API.
+ *
+ * The browser only exposes the current state of every gamepad, so each poll compares the buttons
+ * with the previous poll to find presses & releases.
+ */
+pub struct Gamepads {
+    /// Pressed buttons of every gamepad at the last poll, by gamepad index
+    pressed: HashMap<u32, Vec<bool>>,
+    /// Gamepad that was used last, its stick moves the player
+    active: Option<u32>,
+    stick: (f64, f64),
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Gamepads {
+            pressed: HashMap::new(),
+            active: None,
+            stick: (0.0, 0.0),
+        }
+    }
+
+    /// Button presses & releases since the last poll
+    pub fn poll(&mut self) -> Vec<(GamepadButton, ElementState)> {
+        let mut events = Vec::new();
+        let mut connected = HashMap::new();
+        for gamepad in gamepads() {
+            // Other mappings have no fixed button order
+            if gamepad.mapping() != GamepadMappingType::Standard {
+                continue;
+            }
+            let pressed: Vec<bool> = gamepad
+                .buttons()
+                .iter()
+                .map(|button| button.unchecked_into::<WebGamepadButton>().pressed())
+                .collect();
+            let previous = self.pressed.remove(&gamepad.index()).unwrap_or_default();
+            for (index, button) in STANDARD_BUTTONS.iter().enumerate() {
+                let is_pressed = pressed.get(index).copied().unwrap_or(false);
+                let was_pressed = previous.get(index).copied().unwrap_or(false);
+                if is_pressed != was_pressed {
+                    self.active = Some(gamepad.index());
+                    events.push((
+                        *button,
+                        if is_pressed {
+                            ElementState::Pressed
+                        } else {
+                            ElementState::Released
+                        },
+                    ));
+                }
+            }
+            let axes: Vec<f64> = gamepad
+                .axes()
+                .iter()
+                .map(|axis| axis.as_f64().unwrap_or(0.0))
+                .collect();
+            // The browser's y axis points down
+            let stick = (
+                axes.first().copied().unwrap_or(0.0),
+                -axes.get(1).copied().unwrap_or(0.0),
+            );
+            if stick.0.abs() > 0.5 || stick.1.abs() > 0.5 {
+                self.active = Some(gamepad.index());
+            }
+            if self.active.is_none() || self.active == Some(gamepad.index()) {
+                self.stick = stick;
+            }
+            connected.insert(gamepad.index(), pressed);
+        }
+        if self
+            .active
+            .is_some_and(|active| !connected.contains_key(&active))
+        {
+            self.active = None;
+            self.stick = (0.0, 0.0);
+        }
+        // Buttons still held on a disconnected gamepad are released
+        for pressed in self.pressed.values() {
+            for (index, button) in STANDARD_BUTTONS.iter().enumerate() {
+                if pressed.get(index).copied().unwrap_or(false) {
+                    events.push((*button, ElementState::Released));
+                }
+            }
+        }
+        self.pressed = connected;
+        events
+    }
+
+    /// Left stick position of the active gamepad, x right & y up, without deadzone
+    pub fn stick(&self) -> (f64, f64) {
+        self.stick
+    }
+}
+
+fn gamepads() -> Vec<Gamepad> {
+    web_sys::window()
+        .and_then(|window| window.navigator().get_gamepads().ok())
+        .map(|gamepads| {
+            gamepads
+                .iter()
+                .filter_map(|gamepad| gamepad.dyn_into::<Gamepad>().ok())
+                .filter(Gamepad::connected)
+                .collect()
+        })
+        .unwrap_or_default()
+}
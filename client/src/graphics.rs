@@ -1,13 +1,46 @@
 use instant::{Duration, Instant};
-use std::collections::HashSet;
+use std::ops::Range;
+
+use super::assets::{catch_validation_error, Asset, Assets};
+use super::atlas::Atlas;
+use super::camera::Camera;
+use super::chat::Chat;
+use super::game::{
+    Controls, GameState, IDLE_ANIMATION, PLAYER_SPRITE, WALK_DOWN_ANIMATION, WALK_LEFT_ANIMATION,
+    WALK_RIGHT_ANIMATION, WALK_UP_ANIMATION,
+};
+use super::gamepad::{apply_deadzone, GamepadButton, Gamepads};
+use super::input::{Action, Binding};
+#[cfg(target_arch = "wasm32")]
+use super::loading::draw_loading_screen;
+use super::menu::{HudInfo, Menu, MenuAction};
+use super::network::{ConnectOptions, Connection, NetworkEvent};
+use super::players;
+use super::render_target::RenderTarget;
+#[cfg(not(target_arch = "wasm32"))]
+use super::render_target::OFFSCREEN_FORMAT;
+use super::settings::Settings;
+use super::sprite::{Layer, SpriteBatch};
+use super::text::{TextAlign, TextAnchor, TextRenderer, TextSection};
+#[cfg(target_arch = "wasm32")]
+use super::touch::TouchEvents;
+use super::touch::{TouchControls, TouchGesture};
+use super::ui::Ui;
+use endless_game_shared::{
+    components::{NetworkId, PlayerControlled, Sprite, Transform},
+    ecs::Entities,
+    world::{Object, Terrain, World},
+};
+use std::borrow::Cow;
 use wgpu::{
-    include_wgsl, util::DeviceExt, Device, PipelineLayoutDescriptor, Queue,
-    RenderPipelineDescriptor, SurfaceConfiguration, TextureFormat,
+    util::DeviceExt, Device, PipelineLayoutDescriptor, Queue, RenderPipelineDescriptor,
+    SurfaceConfiguration, TextureFormat,
 };
 use winit::{
     dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize},
     event::{
-        ElementState, Event, KeyboardInput, MouseButton, StartCause, VirtualKeyCode, WindowEvent,
+        ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, StartCause, Touch,
+        TouchPhase, VirtualKeyCode, WindowEvent,
     },
     event_loop::{ControlFlow, EventLoop},
     window::Window,
@@ -26,67 +59,65 @@ type WindowSize = LogicalSize<u32>;
 // TODO: Refactor this into multiple files
 // Keeping as-is for now to make sure that when we do the division we have all the information required
 
-enum MoveDirection {
-    Left,
-    DownLeft,
-    Down,
-    DownRight,
-    Right,
-    UpRight,
-    Up,
-    UpLeft,
-}
-
-#[derive(Debug)]
-struct Position {
-    x: f64,
-    y: f64,
-}
-
 struct GraphicState {
     size: WindowSize,
     scale_factor: f64,
-    cursor: Position,
-    pressed_keys: HashSet<VirtualKeyCode>,
-    mouse_down: bool,
-    player: Position, // TODO: this needs to be extracted since it is not at all graphics related
-    surface: wgpu::Surface,
+    /// The player, world & input, everything drawn is read from it
+    game: GameState,
+    gamepads: Gamepads,
+    /// Virtual joystick & taps on touch screens
+    touch: TouchControls,
+    #[cfg(target_arch = "wasm32")]
+    touch_events: TouchEvents,
+    /// Last input sent to the server as `move|tick|x,y`, only changes are sent
+    sent_input: Option<String>,
+    chat: Chat,
+    connection: Option<Connection>,
+    menu: Menu,
+    settings: Settings,
+    ui: Ui,
+    /// Set when the player quits from the menu, the event loop exits after the current event
+    exit_requested: bool,
+    show_debug: bool,
+    /// Set when something other than the player changed (e.g. the chat log) so the next update redraws
+    needs_redraw: bool,
+    /// The window, or a texture when drawing without one
+    target: RenderTarget,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    /// Textures, atlas definitions & shaders, reloaded when they change in debug desktop builds
+    assets: Assets,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    index_count: u32,
+    /// Named sprites in the atlas texture
+    atlas: Atlas,
+    /// Only rebuilt when other squares come into view
+    ground_sprites: SpriteBatch,
+    /// Squares the ground sprites were built for
+    ground_sprite_squares: (Range<i32>, Range<i32>),
+    /// Objects & players in view, rebuilt & y-sorted whenever something moves
+    scene_sprites: SpriteBatch,
+    diffuse_bind_group_layout: wgpu::BindGroupLayout,
     diffuse_bind_group: wgpu::BindGroup,
     projection_bind_group: wgpu::BindGroup,
     projection_buffer: wgpu::Buffer,
+    text_renderer: TextRenderer,
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 2],
-    tex_coords: [f32; 2],
-}
-
-impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2];
-    // This tells the render_pipeline how to read the buffer
-    // Since the buffer is an array of bytes it will need to be told how to handle those bytes
-    fn buffer_layout_description<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress, // The size of each vertex in bytes
-            step_mode: wgpu::VertexStepMode::Vertex, // Go over each one vertex-by-vertex
-            attributes: &Self::ATTRIBS,
-        }
-    }
+/// Gpu handles & what they draw to, created for a window or without one
+struct Gpu {
+    target: RenderTarget,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
 }
 
-const SQUARE_SIZE: f32 = 96.0;
 const DEFAULT_UPDATE_TIME: u32 = refresh_time!(60.0);
-const SPEED: f64 = 0.004;
+/// Zoom factor of one mouse wheel step
+const WHEEL_ZOOM: f64 = 1.1;
+/// Pixel scroll distance (e.g. touchpads) that counts as one mouse wheel step
+const PIXELS_PER_WHEEL_STEP: f64 = 50.0;
 
 fn next_update(wait_time: u32) -> Instant {
     Instant::now()
@@ -94,8 +125,12 @@ fn next_update(wait_time: u32) -> Instant {
         .expect("Failed to set next update time")
 }
 
-pub async fn run_loop(event_loop: EventLoop<()>, window: Window) {
-    let mut state = GraphicState::new(&window).await;
+pub async fn run_loop(
+    event_loop: EventLoop<()>,
+    window: Window,
+    connect_options: Option<ConnectOptions>,
+) {
+    let mut state = GraphicState::new(&window, connect_options).await;
 
     let mut last_update = Instant::now();
     let update_wait_time = window
@@ -136,38 +171,33 @@ pub async fn run_loop(event_loop: EventLoop<()>, window: Window) {
                     new_inner_size,
                     scale_factor,
                 } => state.handle_resize(new_inner_size, Some(scale_factor), &window),
-                WindowEvent::CloseRequested
-                | WindowEvent::KeyboardInput {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            state: key_state,
+                            virtual_keycode: Some(keycode),
                             ..
                         },
                     ..
-                } => *control_flow = ControlFlow::Exit,
-                WindowEvent::KeyboardInput { input, .. } => match input {
-                    KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(keycode),
-                        ..
-                    } => state.handle_key_press(&keycode),
-                    KeyboardInput {
-                        state: ElementState::Released,
-                        virtual_keycode: Some(keycode),
-                        ..
-                    } => state.handle_key_release(&keycode),
-                    _ => {}
-                },
+                } => state.handle_key_input(keycode, key_state),
+                WindowEvent::ReceivedCharacter(character) => state.handle_character(character),
                 WindowEvent::CursorMoved { position, .. } => state.handle_cursor(position),
                 WindowEvent::MouseInput {
                     state: mouse_state,
-                    button: MouseButton::Left,
+                    button,
                     ..
-                } => state.handle_mouse_input(&mouse_state),
+                } => state.handle_mouse_input(button, mouse_state),
+                WindowEvent::Touch(touch) => state.handle_window_touch(touch),
+                WindowEvent::MouseWheel { delta, .. } => state.handle_mouse_wheel(delta),
                 _ => {}
             },
-            Event::RedrawRequested(_) => state.handle_redraw(),
+            Event::RedrawRequested(_) => {
+                state.handle_redraw();
+                if state.exit_requested {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
             _ => {}
         }
     });
@@ -177,8 +207,7 @@ impl GraphicState {
     /**
      * INITIALISATION STUFF
      */
-    async fn new(window: &Window) -> Self {
-        let player = Position { x: 0.0, y: 0.0 };
+    async fn new(window: &Window, connect_options: Option<ConnectOptions>) -> Self {
         // Create size, instance, surface & adapter
         let (size, scale_factor, surface, adapter) = init_adapter(window).await;
         // Create the logical device and command queue
@@ -189,167 +218,511 @@ impl GraphicState {
         let config = init_default_surface_config(&size.to_physical(scale_factor), &texture_format);
         // Configure the surface to use this device & configuration
         surface.configure(&device, &config);
+        #[allow(unused_mut)]
+        let mut assets = Assets::new();
+        // Text gets its own pipeline & bind groups, drawn on top of the world
+        #[allow(unused_mut)]
+        let mut text_renderer = TextRenderer::new(
+            &device,
+            config.format,
+            &assets.load_string(Asset::TextShader),
+        );
+        // The browser downloads the other assets first, the text renderer draws the progress
+        #[cfg(target_arch = "wasm32")]
+        assets
+            .fetch(|progress| {
+                draw_loading_screen(
+                    &surface,
+                    &device,
+                    &queue,
+                    &mut text_renderer,
+                    (size.to_physical(scale_factor), scale_factor),
+                    progress,
+                )
+            })
+            .await;
+        let gpu = Gpu {
+            target: RenderTarget::Surface(surface),
+            device,
+            queue,
+            config,
+        };
+        GraphicState::init(
+            gpu,
+            (size, scale_factor),
+            assets,
+            text_renderer,
+            Menu::new(connect_options),
+            Settings::load(),
+            #[cfg(target_arch = "wasm32")]
+            TouchEvents::new(window),
+        )
+    }
+
+    /**
+     * Draws to a texture instead of a window, with the software adapter so it also works on
+     * machines without a gpu. Frames are read back with `RenderTarget::read_pixels`. The settings
+     * file is not read, so every run starts out the same.
+     */
+    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg_attr(not(test), allow(dead_code))] // Only the screenshot tests draw without a window
+    fn new_headless(physical_size: PhysicalSize<u32>) -> Result<Self, String> {
+        let adapter = pollster::block_on(init_fallback_adapter())?;
+        let (device, queue) = pollster::block_on(init_device_queue(&adapter));
+        let config = init_default_surface_config(&physical_size, &OFFSCREEN_FORMAT);
+        let target = RenderTarget::offscreen(&device, &config);
+        let assets = Assets::new();
+        let text_renderer = TextRenderer::new(
+            &device,
+            config.format,
+            &assets.load_string(Asset::TextShader),
+        );
+        let gpu = Gpu {
+            target,
+            device,
+            queue,
+            config,
+        };
+        Ok(GraphicState::init(
+            gpu,
+            (physical_size.to_logical(1.0), 1.0),
+            assets,
+            text_renderer,
+            Menu::new(None),
+            Settings::default(),
+        ))
+    }
+
+    /// Everything the window & headless states share, after the assets are available
+    fn init(
+        gpu: Gpu,
+        (size, scale_factor): (WindowSize, f64),
+        assets: Assets,
+        text_renderer: TextRenderer,
+        menu: Menu,
+        settings: Settings,
+        #[cfg(target_arch = "wasm32")] touch_events: TouchEvents,
+    ) -> Self {
+        let Gpu {
+            target,
+            device,
+            queue,
+            config,
+        } = gpu;
+        let game = GameState::new((size.width as f64, size.height as f64));
         // Create texture bind group
-        let (diffuse_bind_group, diffuse_bind_group_layout) = init_texture(&device, &queue);
+        let diffuse_bind_group_layout = init_texture_bind_group_layout(&device);
+        let (diffuse_bind_group, atlas_size) = init_texture(
+            &device,
+            &queue,
+            &diffuse_bind_group_layout,
+            &assets.load(Asset::AtlasImage),
+        )
+        .unwrap_or_else(|err| panic!("Failed to load atlas image: {}", err));
+        let atlas = init_atlas(&assets.load_string(Asset::AtlasDefinition), atlas_size)
+            .unwrap_or_else(|err| panic!("Invalid atlas definition: {}", err));
         // Create projection matrix buffer
         let projection_bind_group_layout = init_projection_bind_group_layout(&device);
-        let projection_buffer =
-            init_projection_matrix_buffer(&device, &size, &player, wgpu::BufferUsages::COPY_DST);
+        let projection_buffer = init_projection_matrix_buffer(&device, &game.camera);
         let projection_bind_group =
             init_projection_bind_group(&device, &projection_buffer, &projection_bind_group_layout);
         // Create render pipeline
-        let render_pipeline = init_render_pipeline(
+        let render_pipeline_layout = init_render_pipeline_layout(
             &device,
-            &config,
             &diffuse_bind_group_layout,
             &projection_bind_group_layout,
         );
-        // Create vertex buffer
-        let (vertex_buffer, index_buffer, index_count) =
-            init_vertex_index_buffer(&device, &size, &player);
+        let render_pipeline = init_render_pipeline(
+            &device,
+            &config,
+            &render_pipeline_layout,
+            &assets.load_string(Asset::SpriteShader),
+        );
+        let ground_sprites = SpriteBatch::new(&device, "Ground sprite buffer", 4096);
+        let scene_sprites = SpriteBatch::new(&device, "Scene sprite buffer", 256);
 
-        GraphicState {
+        let mut state = GraphicState {
             size,
             scale_factor,
-            cursor: Position { x: 0.0, y: 0.0 },
-            pressed_keys: HashSet::new(),
-            mouse_down: false,
-            player,
-            surface,
+            game,
+            gamepads: Gamepads::new(),
+            touch: TouchControls::default(),
+            #[cfg(target_arch = "wasm32")]
+            touch_events,
+            sent_input: None,
+            chat: Chat::default(),
+            connection: None,
+            menu,
+            settings,
+            ui: Ui::default(),
+            exit_requested: false,
+            show_debug: false,
+            needs_redraw: false,
+            target,
             device,
             queue,
             config,
+            assets,
+            render_pipeline_layout,
             render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            index_count,
+            atlas,
+            ground_sprites,
+            ground_sprite_squares: (0..0, 0..0),
+            scene_sprites,
+            diffuse_bind_group_layout,
             diffuse_bind_group,
             projection_buffer,
             projection_bind_group,
-        }
+            text_renderer,
+        };
+        state.refresh_buffers();
+        state
     }
 
     fn update(&mut self, window: &Window, time_elapsed: Duration) {
-        let delta_time = time_elapsed.as_millis() as f64;
-        let mut move_direction: Option<MoveDirection> = None;
-        let delta_space = SPEED * delta_time;
-        if !self.pressed_keys.is_empty() {
-            if self.pressed_keys.contains(&VirtualKeyCode::Left) {
-                if self.pressed_keys.contains(&VirtualKeyCode::Up) {
-                    move_direction = Some(MoveDirection::UpLeft);
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Down) {
-                    move_direction = Some(MoveDirection::DownLeft);
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Right) {
-                    move_direction = None;
-                } else {
-                    move_direction = Some(MoveDirection::Left);
-                }
-            } else if self.pressed_keys.contains(&VirtualKeyCode::Down) {
-                if self.pressed_keys.contains(&VirtualKeyCode::Up) {
-                    move_direction = None;
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Left) {
-                    move_direction = Some(MoveDirection::DownLeft);
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Right) {
-                    move_direction = Some(MoveDirection::DownRight);
-                } else {
-                    move_direction = Some(MoveDirection::Down);
-                }
-            } else if self.pressed_keys.contains(&VirtualKeyCode::Right) {
-                if self.pressed_keys.contains(&VirtualKeyCode::Up) {
-                    move_direction = Some(MoveDirection::UpRight);
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Left) {
-                    move_direction = None;
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Down) {
-                    move_direction = Some(MoveDirection::DownRight);
-                } else {
-                    move_direction = Some(MoveDirection::Right);
-                }
-            } else if self.pressed_keys.contains(&VirtualKeyCode::Up) {
-                if self.pressed_keys.contains(&VirtualKeyCode::Right) {
-                    move_direction = Some(MoveDirection::UpRight);
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Down) {
-                    move_direction = None;
-                } else if self.pressed_keys.contains(&VirtualKeyCode::Left) {
-                    move_direction = Some(MoveDirection::UpLeft);
-                } else {
-                    move_direction = Some(MoveDirection::Up);
-                }
-            }
-            if let Some(direction) = &move_direction {
-                match direction {
-                    MoveDirection::Left => self.player.x -= delta_space,
-                    MoveDirection::DownLeft => {
-                        self.player.x -= delta_space / 2.0;
-                        self.player.y -= delta_space / 2.0;
-                    }
-                    MoveDirection::Down => self.player.y -= delta_space,
-                    MoveDirection::DownRight => {
-                        self.player.x += delta_space / 2.0;
-                        self.player.y -= delta_space / 2.0;
-                    }
-                    MoveDirection::Right => self.player.x += delta_space,
-                    MoveDirection::UpRight => {
-                        self.player.x += delta_space / 2.0;
-                        self.player.y += delta_space / 2.0;
-                    }
-                    MoveDirection::Up => self.player.y += delta_space,
-                    MoveDirection::UpLeft => {
-                        self.player.x -= delta_space / 2.0;
-                        self.player.y += delta_space / 2.0;
-                    }
-                }
-            }
-        } else if self.mouse_down {
-            let x = self.cursor.x;
-            let y = self.cursor.y;
-            // Project the coordinates into clip-space
-            // Old space: top-left: (0,0), bottom-right: (1,1)
-            // Clip space: top-left (-1,1), bottom-right: (1,-1)
-            let clip_x = (x - 0.5) * 2.0;
-            let clip_y = (y - 0.5) * -2.0;
-            // Cursor deadzone is at -0.25 to 0.25
-            // If the cursor is in this deadzone the sprite will move slower the closer the cursor is
-            // and faster the further away the cursor is. Beyond the deadzone the sprite will move at max speed.
-            let deadzone_percentage = 0.25;
-            let threshold_x = (clip_x / deadzone_percentage).min(1.0).max(-1.0);
-            let threshold_y = (clip_y / deadzone_percentage).min(1.0).max(-1.0);
-            // Calculate the relative weights to be used to correct the x and y movement components to ensure constant speed
-            let alpha = clip_y / clip_x;
-            let k_x = 1.0 / (1.0 + alpha.abs());
-            let k_y = 1.0 - k_x;
-            // Multiply the thresholded values by these weights to find the true x and y movement components
-            let corrected_x = k_x * threshold_x;
-            let corrected_y = k_y * threshold_y;
-            self.player.x += corrected_x * delta_space;
-            self.player.y += corrected_y * delta_space;
-        }
-
-        if move_direction.is_some() || self.mouse_down {
+        for asset in self.assets.changed() {
+            self.reload_asset(asset);
+        }
+        self.handle_network();
+        for (button, state) in self.gamepads.poll() {
+            self.handle_gamepad_input(button, state);
+        }
+        #[cfg(target_arch = "wasm32")]
+        for (id, phase, position) in self.touch_events.poll() {
+            self.handle_touch(id, phase, position);
+        }
+        let controls = Controls {
+            bindings: &self.settings.bindings,
+            playing: !self.menu.is_open(),
+            stick: match self.touch.stick() {
+                (0.0, 0.0) => apply_deadzone(self.gamepads.stick()),
+                touch_stick => touch_stick,
+            },
+            touching: self.touch.is_active(),
+        };
+        let animator_sprite = |game: &GameState| {
+            game.player_animator()
+                .and_then(|animator| animator.sprite(&self.atlas))
+        };
+        let previous_sprite = animator_sprite(&self.game);
+        let changes = self.game.update(&controls, time_elapsed);
+        let animated = animator_sprite(&self.game) != previous_sprite;
+        self.send_input();
+        if changes.player_moved || changes.camera_moved || animated {
             self.refresh_buffers();
             window.request_redraw();
+        } else if self.needs_redraw || self.ui.is_dirty() {
+            window.request_redraw();
         }
     }
 
-    /**
+    /// Tells the server about a changed input direction, it moves the player with the same rules
+    fn send_input(&mut self) {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return,
+        };
+        let (tick, input) = self.game.player_input();
+        // Full precision, the server has to simulate the exact input the player is predicted with
+        let message = format!("move|{}|{},{}", tick, input.0, input.1);
+        if self.sent_input.as_ref() != Some(&message) {
+            connection.send(message.clone());
+            self.sent_input = Some(message);
+        }
+    }
+
+    /*
      * HANDLES
      */
 
     fn handle_cursor(&mut self, physical: PhysicalPosition<f64>) {
         let position: LogicalPosition<f64> = physical.to_logical(self.scale_factor);
-        self.cursor.x = position.x / (self.size.width as f64);
-        self.cursor.y = position.y / (self.size.height as f64);
+        self.game.cursor.x = position.x / (self.size.width as f64);
+        self.game.cursor.y = position.y / (self.size.height as f64);
+        if self.menu.is_open() {
+            self.ui.handle_cursor_moved();
+        }
+    }
+
+    fn handle_key_input(&mut self, keycode: VirtualKeyCode, state: ElementState) {
+        if state == ElementState::Released {
+            self.game.input.handle_key(keycode, state);
+        } else if self.menu.is_rebinding() {
+            // Escape cancels rebinding, it can not be bound to anything else itself
+            let binding = Some(Binding::Key(keycode)).filter(|_| keycode != VirtualKeyCode::Escape);
+            self.menu
+                .finish_rebinding(&mut self.settings.bindings, binding);
+            self.needs_redraw = true;
+        } else if self.menu.is_open() {
+            let actions: Vec<Action> = self
+                .settings
+                .bindings
+                .actions(Binding::Key(keycode))
+                .collect();
+            if actions.contains(&Action::Pause) {
+                self.menu_back();
+            } else if actions.contains(&Action::ToggleDebug) {
+                self.handle_action(Action::ToggleDebug);
+            } else {
+                self.ui.handle_key_press(keycode);
+            }
+        } else if self.chat.is_typing() {
+            // The chat reads raw keys, Enter & Escape are not rebindable while typing
+            self.needs_redraw = true;
+            match keycode {
+                VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                    if let (Some(message), Some(connection)) =
+                        (self.chat.submit(), &self.connection)
+                    {
+                        connection.send(message);
+                    }
+                }
+                VirtualKeyCode::Escape => self.chat.cancel(),
+                _ => {}
+            }
+        } else if let Some(binding) = self.game.input.handle_key(keycode, state) {
+            self.handle_binding(binding);
+        }
     }
 
-    fn handle_key_press(&mut self, keycode: &VirtualKeyCode) {
-        self.pressed_keys.insert(*keycode);
+    fn handle_gamepad_input(&mut self, button: GamepadButton, state: ElementState) {
+        if state == ElementState::Released {
+            self.game.input.handle_gamepad(button, state);
+        } else if self.menu.is_rebinding() {
+            self.menu
+                .finish_rebinding(&mut self.settings.bindings, Some(Binding::Gamepad(button)));
+            self.needs_redraw = true;
+        } else if self.menu.is_open() {
+            // Menus are navigated with the d-pad, confirmed with South and left with East or pause
+            let binding = Binding::Gamepad(button);
+            if button == GamepadButton::East
+                || self
+                    .settings
+                    .bindings
+                    .actions(binding)
+                    .any(|action| action == Action::Pause)
+            {
+                self.menu_back();
+            } else {
+                let keycode = match button {
+                    GamepadButton::DPadUp => Some(VirtualKeyCode::Up),
+                    GamepadButton::DPadDown => Some(VirtualKeyCode::Down),
+                    GamepadButton::DPadLeft => Some(VirtualKeyCode::Left),
+                    GamepadButton::DPadRight => Some(VirtualKeyCode::Right),
+                    GamepadButton::South => Some(VirtualKeyCode::Return),
+                    _ => None,
+                };
+                if let Some(keycode) = keycode {
+                    self.ui.handle_key_press(keycode);
+                }
+            }
+        } else if !self.chat.is_typing() {
+            if let Some(binding) = self.game.input.handle_gamepad(button, state) {
+                self.handle_binding(binding);
+            }
+        }
+    }
+
+    fn menu_back(&mut self) {
+        if let Some(action) = self.menu.back() {
+            self.handle_menu_action(action);
+        }
+        self.needs_redraw = true;
     }
 
-    fn handle_key_release(&mut self, keycode: &VirtualKeyCode) {
-        self.pressed_keys.remove(keycode);
+    /**
+     * Triggers the actions bound to a key, mouse or gamepad button that was just pressed while playing.
+     * Held actions (e.g. movement) are read in `update` instead.
+     */
+    fn handle_binding(&mut self, binding: Binding) {
+        let actions: Vec<Action> = self.settings.bindings.actions(binding).collect();
+        for action in actions {
+            self.handle_action(action);
+        }
     }
 
-    fn handle_mouse_input(&mut self, state: &ElementState) {
-        self.mouse_down = *state == ElementState::Pressed;
+    fn handle_action(&mut self, action: Action) {
+        match action {
+            Action::OpenChat => {
+                // Stop moving while typing
+                self.game.input.clear();
+                self.touch.clear();
+                self.chat.open();
+            }
+            Action::Pause => {
+                // Stop moving while the game is paused
+                self.game.input.clear();
+                self.touch.clear();
+                self.menu.pause();
+            }
+            Action::ToggleDebug => self.show_debug = !self.show_debug,
+            Action::Interact => {
+                let (x, y) = self.game.player_position();
+                log::info!("Interact at square {}, {}", x.floor(), y.floor())
+            }
+            Action::MoveUp
+            | Action::MoveDown
+            | Action::MoveLeft
+            | Action::MoveRight
+            | Action::MoveToCursor => {}
+        }
+        self.needs_redraw = true;
+    }
+
+    /**
+     * Touches reported by winit (desktop only), menus are tapped like they are clicked since not
+     * every platform emulates the mouse for touches.
+     */
+    fn handle_window_touch(&mut self, touch: Touch) {
+        if self.menu.is_open() {
+            self.handle_cursor(touch.location);
+            match touch.phase {
+                TouchPhase::Started => self.ui.handle_mouse_input(&ElementState::Pressed),
+                TouchPhase::Ended => self.ui.handle_mouse_input(&ElementState::Released),
+                TouchPhase::Moved | TouchPhase::Cancelled => {}
+            }
+        }
+        let position: LogicalPosition<f64> = touch.location.to_logical(self.scale_factor);
+        self.handle_touch(touch.id, touch.phase, (position.x, position.y));
+    }
+
+    /// Moves the virtual joystick, position in logical pixels from the top-left corner of the window
+    fn handle_touch(&mut self, id: u64, phase: TouchPhase, position: (f64, f64)) {
+        if self.menu.is_open() || self.chat.is_typing() {
+            self.touch.clear();
+            return;
+        }
+        match self.touch.handle_touch(id, phase, position) {
+            Some(TouchGesture::Tap) => self.handle_action(Action::Interact),
+            Some(TouchGesture::Pinch(factor)) => {
+                self.game.camera.zoom_by(factor);
+                // Zooming does not move the view, so the camera update does not refresh them
+                self.refresh_buffers();
+            }
+            None => {}
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Scrolling zooms the camera while playing
+    fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta) {
+        if self.menu.is_open() {
+            return;
+        }
+        let steps = match delta {
+            MouseScrollDelta::LineDelta(_x, y) => y as f64,
+            MouseScrollDelta::PixelDelta(position) => position.y / PIXELS_PER_WHEEL_STEP,
+        };
+        self.game.camera.zoom_by(WHEEL_ZOOM.powf(steps));
+        self.refresh_buffers();
+        self.needs_redraw = true;
+    }
+
+    fn handle_character(&mut self, character: char) {
+        if self.menu.is_open() {
+            self.ui.handle_character(character);
+        } else if self.chat.is_typing() {
+            self.chat.handle_character(character);
+            self.needs_redraw = true;
+        }
+    }
+
+    fn handle_network(&mut self) {
+        let events = match &mut self.connection {
+            Some(connection) => connection.poll(),
+            None => return,
+        };
+        for event in events {
+            match event {
+                NetworkEvent::Connected { player_id } => {
+                    players::set_own_id(&mut self.game.entities, player_id);
+                    // The server spawns the player anew, the offline position is not kept
+                    self.game.respawn();
+                    self.refresh_buffers();
+                    // Messages sent before the connection was open are lost
+                    self.sent_input = None;
+                    self.chat
+                        .push_system(format!("Connected as player {}", player_id));
+                    self.needs_redraw = true;
+                }
+                NetworkEvent::Message(message) => {
+                    if self.chat.handle_server_message(&message) {
+                        self.needs_redraw = true;
+                        continue;
+                    }
+                    // State messages arrive every tick, most of them change nothing
+                    match players::handle_server_message(&mut self.game.entities, &message) {
+                        Some(update) => {
+                            let corrected = update.own.is_some_and(|own| self.game.reconcile(&own));
+                            let changed = update.changed || corrected;
+                            if changed {
+                                self.refresh_buffers();
+                            }
+                            self.needs_redraw |= changed;
+                        }
+                        None => log::debug!("Received msg {}", message),
+                    }
+                }
+                NetworkEvent::Disconnected(reason) => {
+                    self.needs_redraw = true;
+                    self.chat.push_system(format!("Disconnected: {}", reason));
+                    self.menu
+                        .show_main_menu(Some(format!("Disconnected: {}", reason)));
+                    self.disconnect();
+                }
+            }
+        }
+    }
+
+    fn handle_mouse_input(&mut self, button: MouseButton, state: ElementState) {
+        if state == ElementState::Released {
+            self.game.input.handle_mouse(button, state);
+            if self.menu.is_open() && button == MouseButton::Left {
+                self.ui.handle_mouse_input(&state);
+            }
+        } else if self.menu.is_rebinding() {
+            self.menu
+                .finish_rebinding(&mut self.settings.bindings, Some(Binding::Mouse(button)));
+            self.needs_redraw = true;
+        } else if self.menu.is_open() {
+            // Menus are only clicked with the left mouse button
+            if button == MouseButton::Left {
+                self.ui.handle_mouse_input(&state);
+            }
+        } else if let Some(binding) = self.game.input.handle_mouse(button, state) {
+            self.handle_binding(binding);
+        }
+    }
+
+    /**
+     * Carries out what the player picked in the menus
+     */
+    fn handle_menu_action(&mut self, action: MenuAction) {
+        match action {
+            MenuAction::Connect(options) => {
+                self.disconnect();
+                self.chat
+                    .push_system(format!("Connecting to {}", options.server));
+                self.connection = Some(Connection::connect(options));
+            }
+            MenuAction::PlayOffline => self.disconnect(),
+            MenuAction::Disconnect => {
+                self.disconnect();
+                self.menu.show_main_menu(None);
+            }
+            MenuAction::SaveSettings => self.settings.save(),
+            MenuAction::Quit => self.exit_requested = true,
+        }
+        self.ui.reset();
+        self.needs_redraw = true;
+    }
+
+    /// Drops the server connection (if any) and the players it reported
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.sent_input = None;
+        players::clear(&mut self.game.entities);
+        self.refresh_buffers();
     }
 
     /**
@@ -365,55 +738,182 @@ impl GraphicState {
             // Reconfigure the surface with the new size
             self.scale_factor = scale_factor.unwrap_or(self.scale_factor);
             self.size = new_size.to_logical(self.scale_factor);
+            self.game
+                .camera
+                .resize((self.size.width as f64, self.size.height as f64));
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            self.target.configure(&self.device, &self.config);
             self.refresh_buffers();
             // On macos the window needs to be redrawn manually after resizing
             window.request_redraw();
         }
     }
 
+    /**
+     * Rebuilds the gpu resources that use a changed asset. Invalid assets are logged & the
+     * previous version stays in use, so a half saved file does not end the game.
+     */
+    fn reload_asset(&mut self, asset: Asset) {
+        log::info!("Reloading {}", asset.path());
+        let result = match asset {
+            // The atlas definition is checked against the image size, so both are reloaded together
+            Asset::AtlasImage | Asset::AtlasDefinition => {
+                let texture = init_texture(
+                    &self.device,
+                    &self.queue,
+                    &self.diffuse_bind_group_layout,
+                    &self.assets.load(Asset::AtlasImage),
+                );
+                let definition = self.assets.load_string(Asset::AtlasDefinition);
+                texture
+                    .and_then(|(bind_group, atlas_size)| {
+                        init_atlas(&definition, atlas_size).map(|atlas| (bind_group, atlas))
+                    })
+                    .map(|(bind_group, atlas)| {
+                        self.diffuse_bind_group = bind_group;
+                        self.atlas = atlas;
+                        // Forces the ground sprites to be rebuilt with the new atlas
+                        self.ground_sprite_squares = (0..0, 0..0);
+                    })
+            }
+            Asset::SpriteShader => {
+                let shader = self.assets.load_string(Asset::SpriteShader);
+                catch_validation_error(&self.device, || {
+                    init_render_pipeline(
+                        &self.device,
+                        &self.config,
+                        &self.render_pipeline_layout,
+                        &shader,
+                    )
+                })
+                .map(|pipeline| self.render_pipeline = pipeline)
+            }
+            Asset::TextShader => {
+                let shader = self.assets.load_string(Asset::TextShader);
+                self.text_renderer.reload_shader(&self.device, &shader)
+            }
+        };
+        match result {
+            Ok(()) => self.needs_redraw = true,
+            Err(err) => log::warn!("Failed to reload {}: {}", asset.path(), err),
+        }
+    }
+
+    /**
+     * Uploads the camera & everything that changed since the last refresh. Buffers are rewritten in
+     * place, the ground sprites only when the visible squares changed.
+     */
     fn refresh_buffers(&mut self) {
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Refresh Buffer Command Encoder"),
-            });
-        // Creates a new projection buffer and copies it over to the existing one
-        let next_projection_buffer = init_projection_matrix_buffer(
-            &self.device,
-            &self.size,
-            &self.player,
-            wgpu::BufferUsages::COPY_SRC,
-        );
-        encoder.copy_buffer_to_buffer(
-            &next_projection_buffer,
-            0,
+        self.queue.write_buffer(
             &self.projection_buffer,
             0,
-            std::mem::size_of::<[[f32; 4]; 4]>() as u64,
+            bytemuck::cast_slice(&self.game.camera.projection_matrix()),
+        );
+        let visible_squares = self.game.camera.visible_squares();
+        self.scene_sprites.clear();
+        add_scene_sprites(
+            &mut self.scene_sprites,
+            &self.atlas,
+            &visible_squares,
+            &self.game.entities,
+            self.game
+                .player_animator()
+                .and_then(|animator| animator.sprite(&self.atlas)),
         );
+        self.scene_sprites.upload(&self.device, &self.queue);
+        if visible_squares != self.ground_sprite_squares {
+            self.ground_sprites.clear();
+            add_ground_sprites(
+                &mut self.ground_sprites,
+                &self.atlas,
+                &visible_squares,
+                &self.game.world,
+            );
+            self.ground_sprites.upload(&self.device, &self.queue);
+            self.ground_sprite_squares = visible_squares;
+        }
+    }
 
-        (self.vertex_buffer, self.index_buffer, self.index_count) =
-            init_vertex_index_buffer(&self.device, &self.size, &self.player);
-        self.queue.submit(Some(encoder.finish()));
+    /**
+     * Queues all text drawn on top of the world for the next frame
+     */
+    fn queue_text(&mut self) {
+        // Text & menus are laid out in logical pixels multiplied by the ui scale
+        let ui_scale_factor = self.scale_factor * self.settings.ui_scale as f64;
+        let ui_size: LogicalSize<f32> =
+            PhysicalSize::new(self.config.width, self.config.height).to_logical(ui_scale_factor);
+        self.text_renderer.begin_frame(
+            PhysicalSize::new(self.config.width, self.config.height),
+            ui_scale_factor,
+            self.game.camera.projection_matrix(),
+        );
+        if self.show_debug {
+            let (x, y) = self.game.player_position();
+            let position = format!("x: {:.2}\ny: {:.2}", x, y);
+            self.text_renderer.queue_text(&TextSection {
+                text: &position,
+                anchor: TextAnchor::Screen { x: 8.0, y: 8.0 },
+                size: 14.0,
+                color: [1.0, 1.0, 1.0, 1.0],
+                align: TextAlign::TopLeft,
+            });
+            // Label the square the player is standing on
+            let square = format!("{}, {}", x.floor(), y.floor());
+            self.text_renderer.queue_text(&TextSection {
+                text: &square,
+                anchor: TextAnchor::World {
+                    x: x.floor() as f32 + 0.5,
+                    y: y.floor() as f32,
+                },
+                size: 12.0,
+                color: [1.0, 1.0, 0.0, 1.0],
+                align: TextAlign::BottomCenter,
+            });
+        }
+        players::draw_name_tags(
+            &self.game.entities,
+            &mut self.text_renderer,
+            &self.game.camera,
+            self.settings.ui_scale as f64,
+        );
+        self.chat.draw(&mut self.text_renderer, ui_size.height);
+        self.touch
+            .draw(&mut self.text_renderer, self.settings.ui_scale as f64);
+
+        // Menus & HUD are drawn on top of everything else
+        let hud = HudInfo {
+            online: self.connection.is_some(),
+            own_name: players::own_name(&self.game.entities),
+            // Offline the local player has no network id
+            player_count: players::count(&self.game.entities).max(1),
+        };
+        let mut ui = self.ui.begin_frame(
+            self.text_renderer.layout(),
+            (ui_size.width, ui_size.height),
+            (
+                (self.game.cursor.x as f32) * ui_size.width,
+                (self.game.cursor.y as f32) * ui_size.height,
+            ),
+        );
+        let action = self.menu.draw(&mut ui, &mut self.settings, &hud);
+        ui.end_frame();
+        self.text_renderer.prepare(&self.device, &self.queue);
+        if let Some(action) = action {
+            self.handle_menu_action(action);
+        }
     }
 
     /**
      * Handle redraw events
      */
-    fn handle_redraw(&self) {
+    fn handle_redraw(&mut self) {
+        // Everything requested so far is drawn now
+        self.needs_redraw = false;
         log::debug!("Redraw!!");
-        // Get a TextureSurface "frame" from the surface that we can render to
-        let frame = self
-            .surface
-            .get_current_texture()
-            .expect("Failed to acquire next swap chain texture");
-        // Create a texture view with default settings
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.queue_text();
+        // Get a "frame" from the window surface or offscreen texture that we can render to
+        let frame = self.target.next_frame();
         // Create a command encoder, makes command buffers to send to the gpu with commands in them
         let mut encoder = self
             .device
@@ -428,7 +928,7 @@ impl GraphicState {
                 // Describe where to draw color to
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     // Which texture view to save color to
-                    view: &view,
+                    view: &frame.view,
                     // Which texture view receives resolved output (view by default if not multisampling)
                     resolve_target: None,
                     // What to do with colors on the screen
@@ -447,12 +947,11 @@ impl GraphicState {
             rpass.set_bind_group(0, &self.diffuse_bind_group, &[]);
             // Set the projection matrix bind group
             rpass.set_bind_group(1, &self.projection_bind_group, &[]);
-            // Set the vertex buffer
-            rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            // Set the index buffer
-            rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            // Draw indices instead of vertices
-            rpass.draw_indexed(0..self.index_count, 0, 0..1);
+            // Draw the ground, then objects & players in y-sorted order
+            self.ground_sprites.draw(&mut rpass);
+            self.scene_sprites.draw(&mut rpass);
+            // Draw text on top of the world
+            self.text_renderer.render(&mut rpass);
         }
         // Finish command buffer and submit it to GPU's render
         self.queue.submit(Some(encoder.finish()));
@@ -480,6 +979,21 @@ async fn init_adapter(window: &Window) -> (WindowSize, f64, wgpu::Surface, wgpu:
     (size, scale_factor, surface, adapter)
 }
 
+/// Software adapter that needs no window or gpu, e.g. llvmpipe on linux
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg_attr(not(test), allow(dead_code))]
+async fn init_fallback_adapter() -> Result<wgpu::Adapter, String> {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: true,
+            compatible_surface: None,
+        })
+        .await
+        .ok_or_else(|| "No fallback adapter available".to_string())
+}
+
 async fn init_device_queue(adapter: &wgpu::Adapter) -> (Device, Queue) {
     // Use adapter to create Device and Queue
     // Device = a connection to a physical device
@@ -527,14 +1041,46 @@ fn init_default_surface_config(
 
 // TODO: this is way too big. I think textures will need their own module in the future
 // We can then also use some compiler flags to load in images in different ways
+fn init_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    // Bind group layout will be used to crate a bind group
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                // Sampled texture at binding 0
+                binding: 0,
+                // Only visible to fragment shader
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                // Type of binding
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                // If Some = Indicates that this entry is an array or a TEXTURE_BINDING_ARRAY
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                // Sampler at binding 1
+                binding: 1,
+                // Only visible to fragment shader
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("Main texture bind group layout"),
+    })
+}
+
+/// Uploads the atlas image, returns its bind group & size in pixels
 fn init_texture(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-) -> (wgpu::BindGroup, wgpu::BindGroupLayout) {
-    // Load in bytes from file (good enough for now since all our textures will be very small)
-    let diffuse_bytes = include_bytes!("textures/atlas-1.png");
+    texture_bind_group_layout: &wgpu::BindGroupLayout,
+    image_bytes: &[u8],
+) -> Result<(wgpu::BindGroup, (u32, u32)), String> {
     // Turn the bytes into an image
-    let diffuse_image = image::load_from_memory(diffuse_bytes).expect("Failed to load image");
+    let diffuse_image = image::load_from_memory(image_bytes).map_err(|err| err.to_string())?;
     // Get Vec of rgba bytes
     let diffuse_rgba = diffuse_image.to_rgba8();
     use image::GenericImageView;
@@ -590,40 +1136,10 @@ fn init_texture(
         ..Default::default()
     });
 
-    // Bind group layout will be used to crate a bind group
-    let texture_bind_group_layout =
-        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    // Sampled texture at binding 0
-                    binding: 0,
-                    // Only visible to fragment shader
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    // Type of binding
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    // If Some = Indicates that this entry is an array or a TEXTURE_BINDING_ARRAY
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    // Sampler at binding 1
-                    binding: 1,
-                    // Only visible to fragment shader
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-            label: Some("Main texture bind group layout"),
-        });
-
     // Create texture bind group, each texture will require their own bind group
     // This is the final object required to use the texture
     let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &texture_bind_group_layout,
+        layout: texture_bind_group_layout,
         entries: &[
             wgpu::BindGroupEntry {
                 // entry binds the previously created texture view
@@ -638,34 +1154,75 @@ fn init_texture(
         ],
         label: Some("Main texture bind group"),
     });
-    (texture_bind_group, texture_bind_group_layout)
+    Ok((texture_bind_group, dimensions))
 }
 
-fn init_render_pipeline(
+/// Sprite definitions for the atlas texture, warns about sprites the game uses that are missing
+fn init_atlas(definition: &str, image_size: (u32, u32)) -> Result<Atlas, String> {
+    let atlas = Atlas::parse(definition, image_size)?;
+    let used = [PLAYER_SPRITE]
+        .into_iter()
+        .chain([Object::Tree].map(|object| object.sprite()));
+    for name in used {
+        if atlas.get(name).is_none() {
+            log::warn!("Atlas has no sprite named {}, it will not be drawn", name);
+        }
+    }
+    for name in [Terrain::Grass, Terrain::Sand].map(terrain_name) {
+        if atlas.terrain(name).is_none() {
+            log::warn!("Atlas has no terrain named {}, it will not be drawn", name);
+        }
+    }
+    let animations = [
+        IDLE_ANIMATION,
+        WALK_LEFT_ANIMATION,
+        WALK_RIGHT_ANIMATION,
+        WALK_UP_ANIMATION,
+        WALK_DOWN_ANIMATION,
+    ];
+    for name in animations {
+        if atlas.animation(name).is_none() {
+            log::warn!(
+                "Atlas has no animation named {}, the player will not animate",
+                name
+            );
+        }
+    }
+    Ok(atlas)
+}
+
+fn init_render_pipeline_layout(
     device: &wgpu::Device,
-    config: &SurfaceConfiguration,
     diffuse_bind_group_layout: &wgpu::BindGroupLayout,
     projection_bind_group_layout: &wgpu::BindGroupLayout,
-) -> wgpu::RenderPipeline {
-    // Load in the wgsl shader
-    let shader = device.create_shader_module(include_wgsl!("shaders/tutorial.wgsl"));
-    // Set pipeline layout
-    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&PipelineLayoutDescriptor {
         label: Some("Main render pipeline layout"),
         bind_group_layouts: &[diffuse_bind_group_layout, projection_bind_group_layout],
         push_constant_ranges: &[],
+    })
+}
+
+fn init_render_pipeline(
+    device: &wgpu::Device,
+    config: &SurfaceConfiguration,
+    pipeline_layout: &wgpu::PipelineLayout,
+    shader: &str,
+) -> wgpu::RenderPipeline {
+    // Compile the wgsl shader
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Sprite shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(shader)),
     });
     // Create render pipeline
     device.create_render_pipeline(&RenderPipelineDescriptor {
         label: None,
-        layout: Some(&pipeline_layout),
+        layout: Some(pipeline_layout),
         vertex: wgpu::VertexState {
             module: &shader,
             entry_point: "vs_main", // Entrypoint vertex shader function inside shader
-            buffers: &[
-                // Description of the buffers you want to pass to the shader
-                Vertex::buffer_layout_description(),
-            ],
+            // Description of the buffers you want to pass to the shader: the unit quad & the sprite instances
+            buffers: &SpriteBatch::buffer_layouts(),
         },
         fragment: Some(wgpu::FragmentState {
             // Some() because this is optional
@@ -696,264 +1253,78 @@ fn init_render_pipeline(
     })
 }
 
-/**
- * Always returns an uneven number, adds one if even to ensure full grid coverage.
- */
-fn keep_uneven(value: u32) -> u32 {
-    if value % 2 == 0 {
-        value + 1
-    } else {
-        value
+/// Atlas terrain set the ground is drawn with
+fn terrain_name(terrain: Terrain) -> &'static str {
+    match terrain {
+        Terrain::Grass => "grass",
+        Terrain::Sand => "sand",
     }
 }
 
-/**
- * Returns the number of squares that will be needed to fill the window in squares
- * with size SQUARE_SIZE based on given parameter.
- */
-fn number_of_squares(parameter: u32) -> u32 {
-    let divided = (parameter as f32) / SQUARE_SIZE;
-    let ceiled = divided.ceil();
-    keep_uneven(ceiled as u32)
-}
-
-/**
- * Returns the number of squares that will be needed to fill the window horizontally
- */
-fn number_of_squares_horionztally(size: &WindowSize) -> u32 {
-    number_of_squares(size.width)
-}
-
-/**
- * Returns the number of squares that will be needed to fill the window vertically
- */
-fn number_of_squares_vertically(size: &WindowSize) -> u32 {
-    number_of_squares(size.height)
-}
-
-/**
- * Takes a square count of the world and an offset (player position) and calculates the lowest index in the range which will
- * be used to calculate the world grid.
- *
- * By dividing and ceiling the negation of the size by two we get the lowest index of the left-most (or bottom-most) square in the grid.
- * By adding the floor of the offset we ensure that negative numbers are rounded to their lowest value
- * and an extra square is always prepared in the grid before it comes into view.
- */
-fn grid_range_start(square_count: f32, offset: f32) -> i32 {
-    ((-1.0 * square_count) / 2.0).ceil() as i32 + offset.floor() as i32
+/// Ground squares in view, with edge tiles where the terrain changes
+fn add_ground_sprites(
+    batch: &mut SpriteBatch,
+    atlas: &Atlas,
+    (x_range, y_range): &(Range<i32>, Range<i32>),
+    world: &World,
+) {
+    for y in y_range.clone() {
+        for x in x_range.clone() {
+            let terrain = world.terrain((x, y));
+            if let Some(set) = atlas.terrain(terrain_name(terrain)) {
+                let tile = set.tile(|(dx, dy)| world.terrain((x + dx, y + dy)) == terrain);
+                batch.extend(atlas.sprite(tile, (x as f64, y as f64), Layer::Ground));
+            }
+        }
+    }
 }
 
 /**
- * Take square count of the world and an offset (player position) and calculates the highest index in the range which will
- * be used during calculation of the world grid.
- *
- * We divide the square count by two and floor it to get the bottom-left coordinate of the right most square in the grid. This
- * could cause glitches when even numbers are passed to this calculation. But we fix this by ensuring the sizes
- * of the grid are always uneven numbers. At the end we add one because range calculations are not inclusive in rust.
+ * Entities with a sprite in view, players on the character layer. The local player is drawn over
+ * others at the same height & shows the current frame of its animation if there is one.
  */
-fn grid_range_end(square_count: f32, offset: f32) -> i32 {
-    (square_count / 2.0).floor() as i32 + offset.ceil() as i32 + 1
-}
-
-fn texture_coords(index: u16) -> [[f32; 2]; 4] {
-    let tex_width = 1.0 / 3.0;
-    let tex_start = (index as f32) * tex_width;
-    let tex_end = tex_start + (1.0 * tex_width);
-    [
-        [tex_start, 1.0],
-        [tex_end, 1.0],
-        [tex_end, 0.0],
-        [tex_start, 0.0],
-    ]
-}
-
-fn vertices_for_coords(x: f32, y: f32, tex_index: u16) -> Vec<Vertex> {
-    let is_mid_ground = x == 0.0 && y == 0.0 && tex_index == 0;
-    let tex_coords = texture_coords(tex_index);
-    Vec::from([
-        Vertex {
-            position: [x, y],
-            tex_coords: if is_mid_ground {
-                tex_coords[1]
-            } else {
-                tex_coords[0]
-            },
-        },
-        Vertex {
-            position: [x + 1.0, y],
-            tex_coords: if is_mid_ground {
-                tex_coords[2]
-            } else {
-                tex_coords[1]
-            },
-        },
-        Vertex {
-            position: [x + 1.0, y + 1.0],
-            tex_coords: if is_mid_ground {
-                tex_coords[3]
-            } else {
-                tex_coords[2]
-            },
-        },
-        Vertex {
-            position: [x, y + 1.0],
-            tex_coords: if is_mid_ground {
-                tex_coords[0]
-            } else {
-                tex_coords[3]
-            },
-        },
-    ])
-}
-
-fn indices_for_index(index: u16, offset: u16) -> Vec<u16> {
-    let i = index * offset;
-    Vec::from([i, i + 1, i + 3, i + 1, i + 2, i + 3])
-}
-
-// NOTE: this function is just a temp fix to make the rest of the code more readable
-fn add_int_square(
-    vertices: &mut Vec<Vertex>,
-    indices: &mut Vec<u16>,
-    index: &mut u16,
-    x: i32,
-    y: i32,
-    tex_index: u16,
+fn add_scene_sprites(
+    batch: &mut SpriteBatch,
+    atlas: &Atlas,
+    (x_range, y_range): &(Range<i32>, Range<i32>),
+    entities: &Entities,
+    own_sprite: Option<&str>,
 ) {
-    add_square(vertices, indices, index, x as f64, y as f64, tex_index)
-}
-
-fn add_square(
-    vertices: &mut Vec<Vertex>,
-    indices: &mut Vec<u16>,
-    index: &mut u16,
-    x: f64,
-    y: f64,
-    tex_index: u16,
-) {
-    let mut next_vertices = vertices_for_coords(x as f32, y as f32, tex_index);
-    let vertex_count = next_vertices.len() as u16;
-    vertices.append(&mut next_vertices);
-    indices.append(&mut indices_for_index(*index, vertex_count).to_vec());
-    *index += 1;
-}
-
-// TODO: Get rid of all these tuple returns and make sure it returns a proper struct instead to avoid confusion
-fn init_vertex_index_buffer(
-    device: &wgpu::Device,
-    size: &WindowSize,
-    player: &Position,
-) -> (wgpu::Buffer, wgpu::Buffer, u32) {
-    let mut vertices: Vec<Vertex> = Vec::new();
-    let mut indices: Vec<u16> = Vec::new();
-    let horizontal_len = number_of_squares_horionztally(size) as f32;
-    let vertical_len = number_of_squares_vertically(size) as f32;
-
-    let y_start = grid_range_start(vertical_len, player.y as f32);
-    let y_end = grid_range_end(vertical_len, player.y as f32);
-    let x_start = grid_range_start(horizontal_len, player.x as f32);
-    let x_end = grid_range_end(horizontal_len, player.x as f32);
-
-    // Add world ground grid
-    let mut index = 0;
-    for y in y_start..y_end {
-        for x in x_start..x_end {
-            add_int_square(&mut vertices, &mut indices, &mut index, x, y, 0);
-        }
-    }
-    // Add static world objects
-    add_int_square(&mut vertices, &mut indices, &mut index, 2, -1, 1);
-    add_int_square(&mut vertices, &mut indices, &mut index, -3, 2, 1);
-    add_int_square(&mut vertices, &mut indices, &mut index, -4, -1, 1);
-    add_int_square(&mut vertices, &mut indices, &mut index, 5, 3, 1);
-    // Add player
-    add_square(
-        &mut vertices,
-        &mut indices,
-        &mut index,
-        player.x,
-        player.y,
-        2,
-    );
-
-    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Main vertex buffer"),
-        contents: bytemuck::cast_slice(&vertices),
-        usage: wgpu::BufferUsages::VERTEX,
-    });
-    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Main index buffer"),
-        contents: bytemuck::cast_slice(&indices),
-        usage: wgpu::BufferUsages::INDEX,
-    });
-    (vertex_buffer, index_buffer, indices.len() as u32)
+    let in_view = |transform: &Transform| {
+        x_range.contains(&(transform.x.floor() as i32))
+            && y_range.contains(&(transform.y.floor() as i32))
+    };
+    for (entity, sprite, transform) in entities.iter2::<Sprite, Transform>() {
+        if entities.has::<PlayerControlled>(entity) || !in_view(transform) {
+            continue;
+        }
+        let layer = if entities.has::<NetworkId>(entity) {
+            Layer::Characters
+        } else {
+            Layer::Objects
+        };
+        batch.extend(atlas.sprite(&sprite.0, transform.position(), layer));
+    }
+    if let Some(player) = entities.find::<PlayerControlled>() {
+        if let (Some(sprite), Some(transform)) = (
+            entities.get::<Sprite>(player),
+            entities.get::<Transform>(player),
+        ) {
+            let name = own_sprite.unwrap_or(&sprite.0);
+            batch.extend(atlas.sprite(name, transform.position(), Layer::Characters));
+        }
+    }
 }
 
-/**
- * The following is an explanation for the used projection matrix.
- *
- * Scaling x by (2 / horizontal_squares) & y by (2 / vertical_squares) will scale the squares to fit in the clip space.
- * This is because the clip space has a width of 2 (-1 to +1), and we are trying to fit in "horizontal_squares" number of squares in the width
- * and "vertical_squares" number of squares in the height, so (2 / horizontal_squares) will give us the size each square will need
- * to be to fit inside the clip-space. Multiplying each grid coordinate by this number will resize each vertex to fit inside the grid.
- * This gives us: clip_scale = 2 / horizontal_squares
- *
- * After this transformation the grid will be contained within the clip space (-1 to 1). However it will be streched!
- * To solve this we add a correction scaling. We want each square to be of size SQUARE_SIZE but after initial scaling they will have a width
- * of (real_square_width = window_width / horizontal_squares). To correct this we want to find X for: (real_square_width * X = SQUARE_SIZE).
- * Some basic algebra:
- * Given: real_square_width = window_width / horizontal_squares
- * Find "correction" in: real_square_width * correction = SQUARE_SIZE
- * => real_square_width * correction = SQUARE_SIZE
- * => correction = SQUARE_SIZE / real_square_width
- * => correction = SQUARE_SIZE / (window_width / horizontal_squares)
- * => correction = SQUARE_SIZE * (horizontal_squares / window_width)
- *
- * We want to scale each vector by both the clip_scale (to fit them in clip space) and correction (to give them correct size).
- * As the final scaling factor we use:
- * scale = clip_scale * correction
- * => scale = (2 / horizontal_squares) * SQUARE_SIZE * (horizontal_squares / window_width)
- * => scale = (2 * SQUARE_SIZE) * (1 / window_width)
- * => scale = (2 * SQUARE_SIZE) / window_width
- * Which give us a final scaling factors of:
- * scale_x = (2 * SQUARE_SIZE) / window_width
- * scale_y = (2 * SQUARE_SIZE) / window_height
- * [solution for height is analogous]
- *
- * Scale_x and scale_y are coincidentally also the width and height of a square in clip space. These two values are not the same
- * because clip space goes from a constant -1 to 1, but the screen is a dynamic width and height. So if the width is greater than the
- * height than the width of a square in clip space will be less than it's height in clip space.
- *
- * Because of this we add (-1 * scale_x / 2) and  (-1 * scale_y / 2) to the x and y transformations respectively. This will shift the
- * entire grid by half a square to the left and half a square down. This ensures that the center square is presented in the middle of
- * the screen.
- */
-fn init_projection_matrix_buffer(
-    device: &wgpu::Device,
-    size: &WindowSize,
-    player: &Position,
-    usage: wgpu::BufferUsages, // COPY_DST if it's the main reference, COPY_SRC for update sources
-) -> wgpu::Buffer {
+/// Updated in place with `queue.write_buffer` whenever the camera moves
+fn init_projection_matrix_buffer(device: &wgpu::Device, camera: &Camera) -> wgpu::Buffer {
     device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("Main projection matrix buffer"),
-        contents: bytemuck::cast_slice(&calculate_projection_matrix(size, player)),
-        usage: wgpu::BufferUsages::UNIFORM | usage,
+        contents: bytemuck::cast_slice(&camera.projection_matrix()),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
     })
 }
 
-fn calculate_projection_matrix(size: &WindowSize, player: &Position) -> [[f32; 4]; 4] {
-    let scale_x = (2.0 * SQUARE_SIZE) / (size.width as f32);
-    let scale_y = (2.0 * SQUARE_SIZE) / (size.height as f32);
-    let transform_x = scale_x / -2.0 - (scale_x * player.x as f32);
-    let transform_y = scale_y / -2.0 - (scale_y * player.y as f32);
-    [
-        [scale_x, 0.0, 0.0, 0.0],
-        [0.0, scale_y, 0.0, 0.0],
-        [0.0, 0.0, 1.0, 0.0],
-        [transform_x, transform_y, 0.0, 1.0],
-    ]
-}
-
 fn init_projection_bind_group_layout(device: &Device) -> wgpu::BindGroupLayout {
     device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         entries: &[wgpu::BindGroupLayoutEntry {
@@ -984,3 +1355,103 @@ fn init_projection_bind_group(
         label: Some("Project matrix bind group"),
     })
 }
+
+/**
+ * Screenshot tests: frames drawn headless are compared with the golden images in `tests/golden`.
+ * Run with `UPDATE_GOLDEN=1` to save the frames as the new golden images after a deliberate change,
+ * or with `SKIP_GPU_TESTS=1` on machines without a software adapter.
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(640, 480);
+    /// Largest difference in a color channel that still counts as the same, software renderers
+    /// round differently between versions
+    const CHANNEL_TOLERANCE: u8 = 8;
+    /// Share of the pixels that may differ by more than the tolerance, e.g. at glyph edges
+    const MAX_DIFFERENT_PIXELS: f64 = 0.002;
+
+    /// Fails without a software adapter (e.g. on macos), unless the test is skipped explicitly
+    fn headless_state() -> Option<GraphicState> {
+        match GraphicState::new_headless(SIZE) {
+            Ok(state) => Some(state),
+            Err(err) if std::env::var_os("SKIP_GPU_TESTS").is_some() => {
+                eprintln!("Skipping screenshot test: {}", err);
+                None
+            }
+            Err(err) => panic!(
+                "Failed to draw headless: {}, set SKIP_GPU_TESTS=1 to skip the screenshot tests",
+                err
+            ),
+        }
+    }
+
+    fn assert_matches_golden(state: &mut GraphicState, name: &str) {
+        state.handle_redraw();
+        let actual = state
+            .target
+            .read_pixels(&state.device, &state.queue)
+            .expect("Headless states draw offscreen");
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.png", name));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            actual
+                .save(&path)
+                .unwrap_or_else(|err| panic!("Failed to save {:?}: {}", path, err));
+            return;
+        }
+        let expected = image::open(&path)
+            .unwrap_or_else(|err| panic!("Failed to open {:?}: {}", path, err))
+            .to_rgba8();
+        assert_eq!(actual.dimensions(), expected.dimensions());
+        let different = actual
+            .pixels()
+            .zip(expected.pixels())
+            .filter(|(actual, expected)| {
+                actual
+                    .0
+                    .iter()
+                    .zip(expected.0)
+                    .any(|(actual, expected)| actual.abs_diff(expected) > CHANNEL_TOLERANCE)
+            })
+            .count();
+        let allowed = (MAX_DIFFERENT_PIXELS * (SIZE.width * SIZE.height) as f64) as usize;
+        if different > allowed {
+            let actual_path = std::env::temp_dir().join(format!("{}-actual.png", name));
+            let _ = actual.save(&actual_path);
+            panic!(
+                "{} pixels differ from {:?}, at most {} may, the frame was saved to {:?}",
+                different, path, allowed, actual_path
+            );
+        }
+    }
+
+    #[test]
+    fn draws_main_menu() {
+        let mut state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        assert_matches_golden(&mut state, "main_menu");
+    }
+
+    #[test]
+    fn draws_world() {
+        let mut state = match headless_state() {
+            Some(state) => state,
+            None => return,
+        };
+        // Going back from the pause menu closes the main menu as well
+        state.menu.pause();
+        state.menu.back();
+        // Just behind the tree at (5, 3), it covers the player's feet
+        *state.game.entities.get_mut(state.game.player).unwrap() = Transform::new(5.0, 3.4);
+        // Trees, a sand patch & the player all in view
+        state.game.camera = Camera::new((2.5, 5.5), (SIZE.width as f64, SIZE.height as f64));
+        state.game.camera.zoom_by(0.5);
+        state.refresh_buffers();
+        assert_matches_golden(&mut state, "world");
+    }
+}
@@ -0,0 +1,318 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+use super::gamepad::GamepadButton;
+
+/// Everything the player can do, gameplay code only looks at actions and never at raw keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    /// Move towards the cursor while held
+    MoveToCursor,
+    Interact,
+    OpenChat,
+    Pause,
+    ToggleDebug,
+}
+
+impl Action {
+    /// All actions in the order they are listed in the settings menu
+    pub const ALL: [Action; 9] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveToCursor,
+        Action::Interact,
+        Action::OpenChat,
+        Action::Pause,
+        Action::ToggleDebug,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "Move up",
+            Action::MoveDown => "Move down",
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::MoveToCursor => "Move to cursor",
+            Action::Interact => "Interact",
+            Action::OpenChat => "Open chat",
+            Action::Pause => "Pause",
+            Action::ToggleDebug => "Debug overlay",
+        }
+    }
+}
+
+/// A key, mouse button or gamepad button that triggers an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+impl Binding {
+    pub fn label(&self) -> String {
+        match self {
+            Binding::Key(keycode) => format!("{:?}", keycode),
+            Binding::Mouse(MouseButton::Other(button)) => format!("Mouse {}", button),
+            Binding::Mouse(button) => format!("{:?} mouse", button),
+            Binding::Gamepad(button) => format!("Pad {:?}", button),
+        }
+    }
+
+    /// Keyboard & mouse are used together, gamepads are bound separately from them
+    fn is_gamepad(&self) -> bool {
+        matches!(self, Binding::Gamepad(_button))
+    }
+}
+
+/**
+ * Maps every action to the keys & mouse buttons that trigger it.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    from = "BTreeMap<Action, Vec<Binding>>",
+    into = "BTreeMap<Action, Vec<Binding>>"
+)]
+pub struct Bindings {
+    actions: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+        let actions = BTreeMap::from([
+            (
+                Action::MoveUp,
+                vec![
+                    Binding::Key(Up),
+                    Binding::Key(W),
+                    Binding::Gamepad(GamepadButton::DPadUp),
+                ],
+            ),
+            (
+                Action::MoveDown,
+                vec![
+                    Binding::Key(Down),
+                    Binding::Key(S),
+                    Binding::Gamepad(GamepadButton::DPadDown),
+                ],
+            ),
+            (
+                Action::MoveLeft,
+                vec![
+                    Binding::Key(Left),
+                    Binding::Key(A),
+                    Binding::Gamepad(GamepadButton::DPadLeft),
+                ],
+            ),
+            (
+                Action::MoveRight,
+                vec![
+                    Binding::Key(Right),
+                    Binding::Key(D),
+                    Binding::Gamepad(GamepadButton::DPadRight),
+                ],
+            ),
+            (
+                Action::MoveToCursor,
+                vec![Binding::Mouse(MouseButton::Left)],
+            ),
+            (
+                Action::Interact,
+                vec![
+                    Binding::Key(E),
+                    Binding::Key(Space),
+                    Binding::Gamepad(GamepadButton::South),
+                ],
+            ),
+            (Action::OpenChat, vec![Binding::Key(Return)]),
+            (
+                Action::Pause,
+                vec![Binding::Key(Escape), Binding::Gamepad(GamepadButton::Start)],
+            ),
+            (Action::ToggleDebug, vec![Binding::Key(F3)]),
+        ]);
+        Bindings { actions }
+    }
+}
+
+/// Bindings saved by an older version miss newer actions, those keep their default bindings
+impl From<BTreeMap<Action, Vec<Binding>>> for Bindings {
+    fn from(actions: BTreeMap<Action, Vec<Binding>>) -> Self {
+        let mut bindings = Bindings::default();
+        bindings.actions.extend(actions);
+        bindings
+    }
+}
+
+impl From<Bindings> for BTreeMap<Action, Vec<Binding>> {
+    fn from(bindings: Bindings) -> Self {
+        bindings.actions
+    }
+}
+
+impl Bindings {
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /**
+     * Replaces the bindings of the action on the same device as the new binding, e.g. rebinding
+     * a key keeps the gamepad button of the action.
+     */
+    pub fn set(&mut self, action: Action, binding: Binding) {
+        let bindings = self.actions.entry(action).or_default();
+        bindings.retain(|bound| bound.is_gamepad() != binding.is_gamepad());
+        bindings.push(binding);
+    }
+
+    /// Actions triggered by the binding
+    pub fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        Action::ALL
+            .into_iter()
+            .filter(move |action| self.get(*action).contains(&binding))
+    }
+}
+
+/**
+ * Tracks which keys & mouse buttons are held down and translates them to actions.
+ */
+#[derive(Debug, Default)]
+pub struct Input {
+    held: HashSet<Binding>,
+}
+
+impl Input {
+    /**
+     * Updates the held bindings, returns the binding if it was just pressed.
+     */
+    fn handle(&mut self, binding: Binding, state: ElementState) -> Option<Binding> {
+        match state {
+            ElementState::Pressed => self.held.insert(binding).then_some(binding),
+            ElementState::Released => {
+                self.held.remove(&binding);
+                None
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, keycode: VirtualKeyCode, state: ElementState) -> Option<Binding> {
+        self.handle(Binding::Key(keycode), state)
+    }
+
+    pub fn handle_mouse(&mut self, button: MouseButton, state: ElementState) -> Option<Binding> {
+        self.handle(Binding::Mouse(button), state)
+    }
+
+    pub fn handle_gamepad(
+        &mut self,
+        button: GamepadButton,
+        state: ElementState,
+    ) -> Option<Binding> {
+        self.handle(Binding::Gamepad(button), state)
+    }
+
+    /// Whether any binding of the action is held down
+    pub fn is_active(&self, bindings: &Bindings, action: Action) -> bool {
+        bindings
+            .get(action)
+            .iter()
+            .any(|binding| self.held.contains(binding))
+    }
+
+    /// Releases everything, e.g. when a menu or the chat takes over the keyboard
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_a_key_keeps_the_gamepad_binding() {
+        let mut bindings = Bindings::default();
+        bindings.set(Action::MoveUp, Binding::Key(VirtualKeyCode::I));
+        assert_eq!(
+            bindings.get(Action::MoveUp),
+            [
+                Binding::Gamepad(GamepadButton::DPadUp),
+                Binding::Key(VirtualKeyCode::I)
+            ]
+        );
+        // Mouse buttons replace keys, both are on the keyboard & mouse side
+        bindings.set(Action::MoveUp, Binding::Mouse(MouseButton::Right));
+        assert_eq!(
+            bindings.get(Action::MoveUp),
+            [
+                Binding::Gamepad(GamepadButton::DPadUp),
+                Binding::Mouse(MouseButton::Right)
+            ]
+        );
+    }
+
+    #[test]
+    fn rebinding_a_gamepad_button_keeps_the_keys() {
+        let mut bindings = Bindings::default();
+        bindings.set(Action::Interact, Binding::Gamepad(GamepadButton::East));
+        assert_eq!(
+            bindings.get(Action::Interact),
+            [
+                Binding::Key(VirtualKeyCode::E),
+                Binding::Key(VirtualKeyCode::Space),
+                Binding::Gamepad(GamepadButton::East)
+            ]
+        );
+        // Actions without a gamepad binding gain one
+        bindings.set(Action::OpenChat, Binding::Gamepad(GamepadButton::North));
+        assert_eq!(
+            bindings.get(Action::OpenChat),
+            [
+                Binding::Key(VirtualKeyCode::Return),
+                Binding::Gamepad(GamepadButton::North)
+            ]
+        );
+        assert_eq!(
+            bindings
+                .actions(Binding::Gamepad(GamepadButton::North))
+                .collect::<Vec<Action>>(),
+            [Action::OpenChat]
+        );
+    }
+
+    #[test]
+    fn bindings_saved_without_newer_actions_keep_their_defaults() {
+        let json = r#"{ "MoveUp": [{ "Key": "I" }], "Pause": [] }"#;
+        let bindings: Bindings = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            bindings.get(Action::MoveUp),
+            [Binding::Key(VirtualKeyCode::I)]
+        );
+        // Saved actions keep their saved bindings, even if there are none
+        assert_eq!(bindings.get(Action::Pause), []);
+        assert_eq!(
+            bindings.get(Action::ToggleDebug),
+            Bindings::default().get(Action::ToggleDebug)
+        );
+    }
+
+    #[test]
+    fn bindings_serialize_as_a_map_of_actions() {
+        let bindings = Bindings::default();
+        let json = serde_json::to_value(&bindings).unwrap();
+        assert_eq!(json["OpenChat"], serde_json::json!([{ "Key": "Return" }]));
+        assert_eq!(
+            json["Pause"],
+            serde_json::json!([{ "Key": "Escape" }, { "Gamepad": "Start" }])
+        );
+        assert_eq!(serde_json::from_value::<Bindings>(json).unwrap(), bindings);
+    }
+}
@@ -1,6 +1,30 @@
+mod animation;
+mod assets;
+mod atlas;
+mod autotile;
+mod buffer;
+mod camera;
+mod chat;
 mod client_desktop;
 mod client_wasm;
+mod game;
+mod gamepad;
+mod gamepad_desktop;
+mod gamepad_wasm;
 mod graphics;
+mod input;
+mod loading;
+mod menu;
+mod network;
+mod network_desktop;
+mod network_wasm;
+mod players;
+mod render_target;
+mod settings;
+mod sprite;
+mod text;
+mod touch;
+mod ui;
 
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "wasm32")] {
@@ -0,0 +1,145 @@
+// Only the browser downloads assets before the game starts, the layout is tested on all targets
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+use winit::dpi::{LogicalSize, PhysicalSize};
+
+use super::assets::LoadProgress;
+use super::text::{TextAlign, TextAnchor, TextRenderer, TextSection};
+
+/// Size of the progress bar in logical pixels
+const BAR_WIDTH: f32 = 240.0;
+const BAR_HEIGHT: f32 = 12.0;
+const BAR_BACKGROUND: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+const BAR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const ERROR_COLOR: [f32; 4] = [1.0, 0.45, 0.4, 1.0];
+/// Identity, nothing on the loading screen is anchored to the world
+const NO_WORLD: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Where the progress bar goes, in logical pixels from the top-left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BarLayout {
+    left: f32,
+    top: f32,
+    /// Width of the filled part
+    filled: f32,
+}
+
+/// Centers the bar on the screen & fills it by the share of assets loaded so far
+fn bar_layout(logical: LogicalSize<f32>, progress: &LoadProgress) -> BarLayout {
+    let done = progress.loaded as f32 / progress.total.max(1) as f32;
+    BarLayout {
+        left: ((logical.width - BAR_WIDTH) / 2.0).round(),
+        top: ((logical.height - BAR_HEIGHT) / 2.0).round(),
+        filled: (BAR_WIDTH * done).round(),
+    }
+}
+
+/**
+ * Draws a single frame with a progress bar for the asset downloads, shown before the world can be
+ * drawn. Only the text renderer is used since its shader & font are embedded.
+ */
+pub fn draw_loading_screen(
+    surface: &wgpu::Surface,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    text_renderer: &mut TextRenderer,
+    (size, scale_factor): (PhysicalSize<u32>, f64),
+    progress: &LoadProgress,
+) {
+    let frame = match surface.get_current_texture() {
+        Ok(frame) => frame,
+        Err(err) => {
+            log::warn!("Failed to draw loading screen: {}", err);
+            return;
+        }
+    };
+    let view = frame
+        .texture
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    let logical = size.to_logical::<f32>(scale_factor);
+    let BarLayout { left, top, filled } = bar_layout(logical, progress);
+
+    text_renderer.begin_frame(size, scale_factor, NO_WORLD);
+    let title = format!("Loading {}/{}", progress.loaded, progress.total);
+    text_renderer.queue_text(&TextSection {
+        text: &title,
+        anchor: TextAnchor::Screen {
+            x: logical.width / 2.0,
+            y: top - 8.0,
+        },
+        size: 18.0,
+        color: BAR_COLOR,
+        align: TextAlign::BottomCenter,
+    });
+    text_renderer.queue_rect(left, top, BAR_WIDTH, BAR_HEIGHT, BAR_BACKGROUND);
+    text_renderer.queue_rect(left, top, filled, BAR_HEIGHT, BAR_COLOR);
+    if let Some(error) = progress.error {
+        let (width, _) = text_renderer.measure(error, 14.0);
+        text_renderer.queue_text(&TextSection {
+            text: error,
+            anchor: TextAnchor::Screen {
+                x: ((logical.width - width) / 2.0).round(),
+                y: top + BAR_HEIGHT + 12.0,
+            },
+            size: 14.0,
+            color: ERROR_COLOR,
+            align: TextAlign::TopLeft,
+        });
+    }
+    text_renderer.prepare(device, queue);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Loading screen command encoder"),
+    });
+    {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Loading screen render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        text_renderer.render(&mut rpass);
+    }
+    queue.submit(Some(encoder.finish()));
+    frame.present();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(loaded: usize, total: usize) -> LoadProgress<'static> {
+        LoadProgress {
+            loaded,
+            total,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn centers_the_bar() {
+        let layout = bar_layout(LogicalSize::new(800.0, 600.0), &progress(0, 3));
+        assert_eq!(layout.left, 280.0);
+        assert_eq!(layout.top, 294.0);
+        assert_eq!(layout.filled, 0.0);
+    }
+
+    #[test]
+    fn fills_the_bar_by_the_assets_loaded() {
+        let screen = LogicalSize::new(800.0, 600.0);
+        assert_eq!(bar_layout(screen, &progress(1, 3)).filled, 80.0);
+        assert_eq!(bar_layout(screen, &progress(3, 3)).filled, BAR_WIDTH);
+        // Nothing to download
+        assert_eq!(bar_layout(screen, &progress(0, 0)).filled, 0.0);
+    }
+}
@@ -0,0 +1,389 @@
+use super::input::{Action, Binding, Bindings};
+use super::network::{ConnectOptions, DEFAULT_SERVER};
+use super::settings::{Settings, MAX_UI_SCALE, MIN_UI_SCALE};
+use super::text::Color;
+use super::ui::{PanelAnchor, UiFrame};
+
+/// Matches the server side limits
+const MAX_NAME_LENGTH: usize = 16;
+const MAX_FIELD_LENGTH: usize = 128;
+
+const MENU_WIDTH: f32 = 320.0;
+const HUD_WIDTH: f32 = 220.0;
+const BACKDROP_COLOR: Color = [0.0, 0.0, 0.0, 0.4];
+const ERROR_COLOR: Color = [1.0, 0.45, 0.4, 1.0];
+const HINT_COLOR: Color = [0.7, 0.7, 0.75, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen {
+    MainMenu,
+    Playing,
+    Paused,
+    Settings,
+}
+
+/// What the player asked for through the menus, carried out by the game state
+#[derive(Debug)]
+pub enum MenuAction {
+    Connect(ConnectOptions),
+    PlayOffline,
+    Disconnect,
+    SaveSettings,
+    Quit,
+}
+
+/// Status line shown in the HUD
+pub struct HudInfo<'a> {
+    pub online: bool,
+    pub own_name: Option<&'a str>,
+    pub player_count: usize,
+}
+
+/**
+ * The menu screens and the HUD shown while playing.
+ */
+#[derive(Debug)]
+pub struct Menu {
+    screen: Screen,
+    /// Screen to go back to when leaving the settings
+    settings_parent: Screen,
+    server: String,
+    name: String,
+    password: String,
+    display_name: String,
+    /// Shown on the main menu, e.g. why the last connection closed
+    error: Option<String>,
+    /// Action waiting for the next key or mouse button press to be bound to it
+    rebinding: Option<Action>,
+}
+
+impl Menu {
+    /**
+     * Starts on the main menu, filled in with the options given on launch (if any).
+     */
+    pub fn new(options: Option<ConnectOptions>) -> Self {
+        let (server, name, password, display_name) = match options {
+            Some(options) => (
+                options.server,
+                options.name,
+                options.password,
+                options.display_name.unwrap_or_default(),
+            ),
+            None => (
+                DEFAULT_SERVER.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ),
+        };
+        Menu {
+            screen: Screen::MainMenu,
+            settings_parent: Screen::MainMenu,
+            server,
+            name,
+            password,
+            display_name,
+            error: None,
+            rebinding: None,
+        }
+    }
+
+    /// Whether a menu is shown, the game does not react to input then
+    pub fn is_open(&self) -> bool {
+        self.screen != Screen::Playing
+    }
+
+    pub fn pause(&mut self) {
+        self.screen = Screen::Paused;
+    }
+
+    /**
+     * Goes back to the previous screen, e.g. when the pause key is pressed.
+     * Leaving the settings asks for them to be saved.
+     */
+    pub fn back(&mut self) -> Option<MenuAction> {
+        let (screen, action) = match self.screen {
+            Screen::Paused => (Screen::Playing, None),
+            Screen::Settings => (self.settings_parent, Some(MenuAction::SaveSettings)),
+            screen => (screen, None),
+        };
+        self.screen = screen;
+        self.rebinding = None;
+        action
+    }
+
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+
+    /// Binds the action waiting for input, rebinding is cancelled if no binding is given
+    pub fn finish_rebinding(&mut self, bindings: &mut Bindings, binding: Option<Binding>) {
+        if let (Some(action), Some(binding)) = (self.rebinding.take(), binding) {
+            bindings.set(action, binding);
+        }
+    }
+
+    pub fn show_main_menu(&mut self, error: Option<String>) {
+        self.screen = Screen::MainMenu;
+        self.error = error;
+    }
+
+    /**
+     * Declares the widgets of the current screen, returns the action the player picked.
+     */
+    pub fn draw(
+        &mut self,
+        ui: &mut UiFrame,
+        settings: &mut Settings,
+        hud: &HudInfo,
+    ) -> Option<MenuAction> {
+        match self.screen {
+            Screen::MainMenu => self.draw_main_menu(ui),
+            Screen::Playing => {
+                draw_hud(ui, hud);
+                None
+            }
+            Screen::Paused => {
+                ui.backdrop(BACKDROP_COLOR);
+                self.draw_pause_menu(ui, hud)
+            }
+            Screen::Settings => {
+                ui.backdrop(BACKDROP_COLOR);
+                self.draw_settings(ui, settings)
+            }
+        }
+    }
+
+    fn draw_main_menu(&mut self, ui: &mut UiFrame) -> Option<MenuAction> {
+        let mut action = None;
+        ui.begin_panel(
+            "main_menu",
+            PanelAnchor::Center,
+            MENU_WIDTH,
+            Some("Endless"),
+        );
+        let mut submitted = ui.text_input(
+            "server",
+            "Server address",
+            &mut self.server,
+            MAX_FIELD_LENGTH,
+        );
+        submitted |= ui.text_input("name", "Account name", &mut self.name, MAX_NAME_LENGTH);
+        submitted |=
+            ui.password_input("password", "Password", &mut self.password, MAX_FIELD_LENGTH);
+        submitted |= ui.text_input(
+            "display_name",
+            "Display name (optional)",
+            &mut self.display_name,
+            MAX_NAME_LENGTH,
+        );
+        if let Some(error) = &self.error {
+            ui.colored_label(error, ERROR_COLOR);
+        }
+        let connect = ui.button("connect", "Connect") || submitted;
+        let register = ui.button("register", "Register new account");
+        if connect || register {
+            action = match self.connect_options() {
+                Some(options) => Some(MenuAction::Connect(ConnectOptions {
+                    register,
+                    ..options
+                })),
+                None => {
+                    self.error = Some("Enter an account name to connect".to_string());
+                    None
+                }
+            };
+        }
+        if ui.button("offline", "Play offline") {
+            action = Some(MenuAction::PlayOffline);
+        }
+        if ui.button("main_settings", "Settings") {
+            self.open_settings();
+        }
+        if !cfg!(target_arch = "wasm32") && ui.button("main_quit", "Quit") {
+            action = Some(MenuAction::Quit);
+        }
+        ui.end_panel();
+        if action.is_some() {
+            self.error = None;
+            self.screen = Screen::Playing;
+        }
+        action
+    }
+
+    fn draw_pause_menu(&mut self, ui: &mut UiFrame, hud: &HudInfo) -> Option<MenuAction> {
+        let mut action = None;
+        ui.begin_panel(
+            "pause_menu",
+            PanelAnchor::Center,
+            MENU_WIDTH,
+            Some("Paused"),
+        );
+        if ui.button("resume", "Resume") {
+            self.screen = Screen::Playing;
+        }
+        if ui.button("pause_settings", "Settings") {
+            self.open_settings();
+        }
+        let leave = if hud.online {
+            "Disconnect"
+        } else {
+            "Main menu"
+        };
+        if ui.button("leave", leave) {
+            action = Some(MenuAction::Disconnect);
+        }
+        if !cfg!(target_arch = "wasm32") && ui.button("pause_quit", "Quit") {
+            action = Some(MenuAction::Quit);
+        }
+        ui.end_panel();
+        action
+    }
+
+    fn draw_settings(&mut self, ui: &mut UiFrame, settings: &mut Settings) -> Option<MenuAction> {
+        let mut action = None;
+        ui.begin_panel(
+            "settings",
+            PanelAnchor::Center,
+            MENU_WIDTH,
+            Some("Settings"),
+        );
+        ui.slider(
+            "ui_scale",
+            "UI scale",
+            &mut settings.ui_scale,
+            MIN_UI_SCALE,
+            MAX_UI_SCALE,
+            0.05,
+        );
+        ui.colored_label("Controls (click to rebind)", HINT_COLOR);
+        for bound_action in Action::ALL {
+            let text = if self.rebinding == Some(bound_action) {
+                format!("{}: press a key...", bound_action.label())
+            } else {
+                let keys: Vec<String> = settings
+                    .bindings
+                    .get(bound_action)
+                    .iter()
+                    .map(Binding::label)
+                    .collect();
+                format!("{}: {}", bound_action.label(), keys.join(", "))
+            };
+            if ui.button(bound_action.label(), &text) {
+                self.rebinding = Some(bound_action);
+            }
+        }
+        if ui.button("reset_controls", "Reset controls") {
+            settings.bindings = Bindings::default();
+            self.rebinding = None;
+        }
+        if ui.button("settings_back", "Back") {
+            action = self.back();
+        }
+        ui.end_panel();
+        action
+    }
+
+    fn open_settings(&mut self) {
+        self.settings_parent = self.screen;
+        self.screen = Screen::Settings;
+    }
+
+    fn connect_options(&self) -> Option<ConnectOptions> {
+        ConnectOptions::new(
+            Some(self.server.trim().to_string()).filter(|server| !server.is_empty()),
+            Some(self.name.trim().to_string()),
+            Some(self.password.clone()),
+            Some(self.display_name.trim().to_string()),
+        )
+    }
+}
+
+fn draw_hud(ui: &mut UiFrame, hud: &HudInfo) {
+    ui.begin_panel("hud", PanelAnchor::TopRight, HUD_WIDTH, None);
+    match (hud.online, hud.own_name) {
+        (false, _) => ui.label("Offline"),
+        (true, None) => ui.label("Connecting..."),
+        (true, Some(name)) => {
+            ui.label(&format!("Playing as {}", name));
+            ui.label(&format!("{} online", hud.player_count));
+        }
+    }
+    ui.colored_label("Esc: menu", HINT_COLOR);
+    ui.end_panel();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::TextLayout;
+    use crate::ui::Ui;
+    use winit::event::VirtualKeyCode;
+
+    const OFFLINE: HudInfo = HudInfo {
+        online: false,
+        own_name: None,
+        player_count: 1,
+    };
+
+    /// Presses the keys & draws a frame of the menu, returns the action picked
+    fn press(menu: &mut Menu, ui: &mut Ui, keys: &[VirtualKeyCode]) -> Option<MenuAction> {
+        keys.iter().for_each(|key| ui.handle_key_press(*key));
+        let mut layout = TextLayout::new();
+        let mut settings = Settings::default();
+        let mut frame = ui.begin_frame(&mut layout, (800.0, 600.0), (0.0, 0.0));
+        let action = menu.draw(&mut frame, &mut settings, &OFFLINE);
+        frame.end_frame();
+        action
+    }
+
+    #[test]
+    fn connects_with_enter_once_a_name_is_given() {
+        let mut menu = Menu::new(None);
+        let mut ui = Ui::default();
+        // Down focuses the server address, Enter in any field connects
+        assert!(press(&mut menu, &mut ui, &[VirtualKeyCode::Down]).is_none());
+        assert!(press(&mut menu, &mut ui, &[VirtualKeyCode::Return]).is_none());
+        assert!(menu.error.is_some());
+        assert!(menu.is_open());
+
+        menu.name = "alice".to_string();
+        match press(&mut menu, &mut ui, &[VirtualKeyCode::Return]) {
+            Some(MenuAction::Connect(options)) => {
+                assert_eq!(options.name, "alice");
+                assert!(!options.register);
+            }
+            action => panic!("Expected to connect, got {:?}", action),
+        }
+        assert!(!menu.is_open());
+        assert!(menu.error.is_none());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn keyboard_selection_wraps_to_the_last_button() {
+        let mut menu = Menu::new(None);
+        let mut ui = Ui::default();
+        press(&mut menu, &mut ui, &[VirtualKeyCode::Down]);
+        // From the first widget up to the last one, quit
+        assert!(press(&mut menu, &mut ui, &[VirtualKeyCode::Up]).is_none());
+        assert!(matches!(
+            press(&mut menu, &mut ui, &[VirtualKeyCode::Return]),
+            Some(MenuAction::Quit)
+        ));
+    }
+
+    #[test]
+    fn goes_back_to_the_previous_screen() {
+        let mut menu = Menu::new(None);
+        assert!(menu.back().is_none());
+        assert_eq!(menu.screen, Screen::MainMenu);
+
+        menu.pause();
+        menu.open_settings();
+        assert!(matches!(menu.back(), Some(MenuAction::SaveSettings)));
+        assert_eq!(menu.screen, Screen::Paused);
+        assert!(menu.back().is_none());
+        assert!(!menu.is_open());
+    }
+}
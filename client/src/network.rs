@@ -0,0 +1,147 @@
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+        pub use super::network_wasm::Connection;
+    } else {
+        pub use super::network_desktop::Connection;
+    }
+}
+
+pub const DEFAULT_SERVER: &str = "ws://127.0.0.1:3001";
+
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub server: String,
+    pub name: String,
+    pub password: String,
+    /// Name shown to other players, the server uses the account name if not set
+    pub display_name: Option<String>,
+    /// Creates a new account instead of logging in to an existing one
+    pub register: bool,
+}
+
+impl ConnectOptions {
+    /**
+     * Returns options to connect with if a name was given, otherwise the game is played offline.
+     */
+    pub fn new(
+        server: Option<String>,
+        name: Option<String>,
+        password: Option<String>,
+        display_name: Option<String>,
+    ) -> Option<Self> {
+        name.filter(|name| !name.is_empty())
+            .map(|name| ConnectOptions {
+                server: server
+                    .unwrap_or_else(|| DEFAULT_SERVER.to_string())
+                    .trim_end_matches('/')
+                    .to_string(),
+                name,
+                password: password.unwrap_or_default(),
+                display_name: display_name.filter(|display_name| !display_name.is_empty()),
+                register: false,
+            })
+    }
+
+    pub(crate) fn login_url(&self) -> String {
+        format!("{}/login", self.server)
+    }
+
+    pub(crate) fn game_url(&self, token: &str) -> String {
+        match &self.display_name {
+            // The server only allows letters, digits & underscores, anything else is encoded so it
+            // is rejected with a reason instead of breaking the url
+            Some(display_name) => format!(
+                "{}/?token={}&name={}",
+                self.server,
+                token,
+                percent_encode(display_name)
+            ),
+            None => format!("{}/?token={}", self.server, token),
+        }
+    }
+
+    /// Either a `login` or a `register` message for the login endpoint
+    pub(crate) fn login_message(&self) -> String {
+        let request = if self.register { "register" } else { "login" };
+        format!("{}|{}|{}", request, self.name, self.password)
+    }
+}
+
+/// Encodes everything but unreserved characters, for use in a url query
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum NetworkEvent {
+    /// The game connection was opened with a valid session
+    Connected { player_id: u16 },
+    /// Any message sent by the server on the game connection
+    Message(String),
+    /// The login failed or the game connection was closed, contains the reason
+    Disconnected(String),
+}
+
+#[derive(Debug)]
+pub(crate) enum LoginReply {
+    Token { token: String, player_id: u16 },
+    Error(String),
+}
+
+impl LoginReply {
+    /// Parses `token|session_token|player_id` or `error|reason` replies of the login endpoint
+    pub(crate) fn parse(txt: &str) -> Self {
+        let data: Vec<&str> = txt.splitn(3, '|').collect();
+        match data[..] {
+            ["token", token, player_id] => match str::parse::<u16>(player_id) {
+                Ok(player_id) => LoginReply::Token {
+                    token: token.to_string(),
+                    player_id,
+                },
+                Err(_err) => LoginReply::Error(format!("Invalid player id: {}", player_id)),
+            },
+            ["error", reason] => LoginReply::Error(reason.to_string()),
+            _ => LoginReply::Error(format!("Unexpected login reply: {}", txt)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(display_name: &str) -> ConnectOptions {
+        ConnectOptions::new(
+            Some("ws://localhost:3001".to_string()),
+            Some("alice".to_string()),
+            Some("correct horse".to_string()),
+            Some(display_name.to_string()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn encodes_the_display_name_in_the_game_url() {
+        assert_eq!(
+            options("").game_url("abc"),
+            "ws://localhost:3001/?token=abc"
+        );
+        assert_eq!(
+            options("Knight_42").game_url("abc"),
+            "ws://localhost:3001/?token=abc&name=Knight_42"
+        );
+        assert_eq!(
+            options("a&b=c #d").game_url("abc"),
+            "ws://localhost:3001/?token=abc&name=a%26b%3Dc%20%23d"
+        );
+        assert_eq!(percent_encode("é+%"), "%C3%A9%2B%25");
+    }
+}
@@ -0,0 +1,137 @@
+#![cfg(not(target_arch = "wasm32"))]
+use instant::Duration;
+use std::{
+    io::{self, ErrorKind},
+    net::TcpStream,
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+use super::network::{ConnectOptions, LoginReply, NetworkEvent};
+
+/// How long a read on the game connection blocks before outgoing messages are sent again
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/**
+ * Websocket connection to the game server.
+ *
+ * Tungstenite sockets are blocking, so the connection runs on its own thread and
+ * exchanges messages with the game loop through channels.
+ */
+pub struct Connection {
+    outgoing: Sender<String>,
+    incoming: Receiver<NetworkEvent>,
+}
+
+impl Connection {
+    pub fn connect(options: ConnectOptions) -> Self {
+        let (outgoing, outgoing_rx) = mpsc::channel();
+        let (incoming_tx, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            let reason = run(&options, &outgoing_rx, &incoming_tx).unwrap_or_else(|err| err);
+            log::info!("Disconnected from {}: {}", options.server, reason);
+            // The game loop might already be gone, nothing left to notify then
+            let _ = incoming_tx.send(NetworkEvent::Disconnected(reason));
+        });
+        Connection { outgoing, incoming }
+    }
+
+    pub fn send(&self, message: String) {
+        self.outgoing
+            .send(message)
+            .unwrap_or_else(|err| log::warn!("Failed to send message: {:?}", err));
+    }
+
+    pub fn poll(&mut self) -> Vec<NetworkEvent> {
+        self.incoming.try_iter().collect()
+    }
+}
+
+/**
+ * Logs in and keeps the game connection running until it is closed, returns the reason it closed.
+ */
+fn run(
+    options: &ConnectOptions,
+    outgoing: &Receiver<String>,
+    incoming: &Sender<NetworkEvent>,
+) -> Result<String, String> {
+    let (token, player_id) = login(options)?;
+    let (mut socket, _response) =
+        tungstenite::connect(options.game_url(&token)).map_err(|err| err.to_string())?;
+    set_read_timeout(&mut socket, READ_TIMEOUT).map_err(|err| err.to_string())?;
+    log::info!("Connected to {} as player {}", options.server, player_id);
+    send_event(incoming, NetworkEvent::Connected { player_id })?;
+    loop {
+        loop {
+            match outgoing.try_recv() {
+                Ok(message) => socket
+                    .write_message(Message::Text(message))
+                    .map_err(|err| err.to_string())?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // The game loop dropped the connection
+                    let _ = socket.close(None);
+                    return Ok("Client initiated disconnect".to_string());
+                }
+            }
+        }
+        match socket.read_message() {
+            Ok(Message::Text(txt)) => send_event(incoming, NetworkEvent::Message(txt))?,
+            Ok(Message::Close(frame)) => {
+                return Ok(frame.map_or_else(String::new, |frame| frame.reason.to_string()))
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {}
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+}
+
+/// Sets the timeout on the tcp stream below the (possibly TLS) websocket
+fn set_read_timeout(socket: &mut Socket, timeout: Duration) -> io::Result<()> {
+    match socket.get_mut() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(timeout)),
+        MaybeTlsStream::Rustls(stream) => stream.get_mut().set_read_timeout(Some(timeout)),
+        _ => Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Unsupported websocket stream",
+        )),
+    }
+}
+
+fn send_event(incoming: &Sender<NetworkEvent>, event: NetworkEvent) -> Result<(), String> {
+    incoming
+        .send(event)
+        .map_err(|_err| "Game loop stopped".to_string())
+}
+
+/**
+ * Logs in to an existing account, or registers a new one if the player asked for that.
+ */
+fn login(options: &ConnectOptions) -> Result<(String, u16), String> {
+    let (mut socket, _response) =
+        tungstenite::connect(options.login_url()).map_err(|err| err.to_string())?;
+    socket
+        .write_message(Message::Text(options.login_message()))
+        .map_err(|err| err.to_string())?;
+    let reply = read_text(&mut socket).map(|txt| LoginReply::parse(&txt));
+    let _ = socket.close(None);
+    match reply? {
+        LoginReply::Token { token, player_id } => Ok((token, player_id)),
+        LoginReply::Error(reason) => Err(reason),
+    }
+}
+
+fn read_text(socket: &mut Socket) -> Result<String, String> {
+    loop {
+        match socket.read_message().map_err(|err| err.to_string())? {
+            Message::Text(txt) => return Ok(txt),
+            Message::Close(_frame) => return Err("Login connection closed".to_string()),
+            _ => {}
+        }
+    }
+}
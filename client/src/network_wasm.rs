@@ -0,0 +1,208 @@
+#![cfg(target_arch = "wasm32")]
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    rc::{Rc, Weak},
+};
+use wasm_bindgen::prelude::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{CloseEvent, Event, MessageEvent, WebSocket};
+
+use super::network::{ConnectOptions, LoginReply, NetworkEvent};
+
+type Events = Rc<RefCell<VecDeque<NetworkEvent>>>;
+type GameSocket = Rc<RefCell<Option<WebSocket>>>;
+
+/**
+ * Websocket connection to the game server.
+ *
+ * Browser websockets are event based, every callback pushes its result into a queue which
+ * the game loop empties with `poll`.
+ */
+pub struct Connection {
+    /// Set once logged in. Callbacks only hold a weak reference, so no game connection is
+    /// opened after the connection was dropped.
+    socket: GameSocket,
+    login_socket: Option<WebSocket>,
+    events: Events,
+}
+
+impl Connection {
+    pub fn connect(options: ConnectOptions) -> Self {
+        let socket = Rc::new(RefCell::new(None));
+        let events = Rc::new(RefCell::new(VecDeque::new()));
+        let login_socket = login(options, Rc::downgrade(&socket), events.clone());
+        Connection {
+            socket,
+            login_socket,
+            events,
+        }
+    }
+
+    pub fn send(&self, message: String) {
+        match &*self.socket.borrow() {
+            Some(socket) if socket.ready_state() == WebSocket::OPEN => socket
+                .send_with_str(&message)
+                .unwrap_or_else(|err| log::warn!("Failed to send message: {:?}", err)),
+            _ => log::warn!("Dropped message, not connected: {}", message),
+        }
+    }
+
+    pub fn poll(&mut self) -> Vec<NetworkEvent> {
+        self.events.borrow_mut().drain(..).collect()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(socket) = &self.login_socket {
+            let _ = socket.close();
+        }
+        if let Some(socket) = &*self.socket.borrow() {
+            let _ = socket.close();
+        }
+    }
+}
+
+fn open(url: &str) -> Option<WebSocket> {
+    WebSocket::new(url)
+        .map_err(|err| log::error!("Failed to open websocket {}: {:?}", url, err))
+        .ok()
+}
+
+/**
+ * Logs in to an existing account, or registers a new one if the player asked for that.
+ * Once a session token is received the game connection is opened.
+ */
+fn login(
+    options: ConnectOptions,
+    game_socket: Weak<RefCell<Option<WebSocket>>>,
+    events: Events,
+) -> Option<WebSocket> {
+    let login_socket = match open(&options.login_url()) {
+        Some(socket) => socket,
+        None => {
+            events.borrow_mut().push_back(NetworkEvent::Disconnected(
+                "Failed to open login connection".into(),
+            ));
+            return None;
+        }
+    };
+    // Set once the login was answered, closing the login socket afterwards is expected
+    let answered = Rc::new(Cell::new(false));
+
+    let onopen = {
+        let login_socket = login_socket.clone();
+        let message = options.login_message();
+        Closure::wrap(Box::new(move || {
+            login_socket
+                .send_with_str(&message)
+                .unwrap_or_else(|err| log::warn!("Failed to send login: {:?}", err));
+        }) as Box<dyn FnMut()>)
+    };
+    login_socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+
+    let onmessage = {
+        let login_socket = login_socket.clone();
+        let answered = answered.clone();
+        let events = events.clone();
+        Closure::wrap(Box::new(move |event: MessageEvent| {
+            answered.set(true);
+            let _ = login_socket.close();
+            let reply = LoginReply::parse(&event.data().as_string().unwrap_or_default());
+            match (reply, game_socket.upgrade()) {
+                (LoginReply::Token { token, player_id }, Some(game_socket)) => {
+                    open_game(&options.game_url(&token), player_id, &game_socket, &events);
+                }
+                (LoginReply::Error(reason), Some(_game_socket)) => {
+                    events
+                        .borrow_mut()
+                        .push_back(NetworkEvent::Disconnected(reason));
+                }
+                // The connection was dropped while logging in
+                (_reply, None) => {}
+            }
+        }) as Box<dyn FnMut(MessageEvent)>)
+    };
+    login_socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    // Browsers only report that a connection failed, not why. An error is followed by a close,
+    // whichever comes first ends the login.
+    let fail = move |reason: String| {
+        if !answered.replace(true) {
+            events
+                .borrow_mut()
+                .push_back(NetworkEvent::Disconnected(reason));
+        }
+    };
+    let onerror = {
+        let fail = fail.clone();
+        Closure::wrap(Box::new(move |_event: Event| {
+            fail("Failed to connect to the server".into());
+        }) as Box<dyn FnMut(Event)>)
+    };
+    login_socket.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+    onerror.forget();
+
+    let onclose = Closure::wrap(Box::new(move |event: CloseEvent| {
+        let reason = event.reason();
+        if reason.is_empty() {
+            fail("Login connection closed".into());
+        } else {
+            fail(reason);
+        }
+    }) as Box<dyn FnMut(CloseEvent)>);
+    login_socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+
+    Some(login_socket)
+}
+
+fn open_game(url: &str, player_id: u16, game_socket: &GameSocket, events: &Events) {
+    let socket = match open(url) {
+        Some(socket) => socket,
+        None => {
+            events.borrow_mut().push_back(NetworkEvent::Disconnected(
+                "Failed to open game connection".into(),
+            ));
+            return;
+        }
+    };
+
+    let onopen = {
+        let events = events.clone();
+        Closure::wrap(Box::new(move || {
+            events
+                .borrow_mut()
+                .push_back(NetworkEvent::Connected { player_id });
+        }) as Box<dyn FnMut()>)
+    };
+    socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+
+    let onmessage = {
+        let events = events.clone();
+        Closure::wrap(Box::new(move |event: MessageEvent| {
+            if let Some(txt) = event.data().as_string() {
+                events.borrow_mut().push_back(NetworkEvent::Message(txt));
+            }
+        }) as Box<dyn FnMut(MessageEvent)>)
+    };
+    socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    let onclose = {
+        let events = events.clone();
+        Closure::wrap(Box::new(move |event: CloseEvent| {
+            events
+                .borrow_mut()
+                .push_back(NetworkEvent::Disconnected(event.reason()));
+        }) as Box<dyn FnMut(CloseEvent)>)
+    };
+    socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+
+    *game_socket.borrow_mut() = Some(socket);
+}
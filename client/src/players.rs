@@ -0,0 +1,226 @@
+//! Players as last reported by the server, kept up to date by the `state` messages. Every player
+//! is an entity with a `NetworkId`, the local player's entity gets its id once connected but keeps
+//! its own (predicted) position, which the server's state only corrects (see `GameState::reconcile`).
+
+use std::collections::HashMap;
+
+use endless_game_shared::{
+    components::{Name, NetworkId, PlayerControlled, Sprite, Transform},
+    ecs::{Entities, Entity},
+};
+
+use super::camera::Camera;
+use super::game::{AuthoritativeState, PLAYER_SPRITE};
+use super::text::{Color, TextAlign, TextAnchor, TextRenderer, TextSection};
+
+/// Name tag layout in logical pixels, independent of the square size so names stay readable when zoomed
+const NAME_TAG_SIZE: f32 = 14.0;
+const NAME_TAG_PADDING: f32 = 3.0;
+/// Space between the top of the player square and the bottom of the name tag
+const NAME_TAG_GAP: f32 = 4.0;
+
+const NAME_COLOR: Color = [1.0, 1.0, 1.0, 1.0];
+const OWN_NAME_COLOR: Color = [1.0, 0.85, 0.3, 1.0];
+const NAME_BACKGROUND_COLOR: Color = [0.0, 0.0, 0.0, 0.55];
+
+/// A player entry of a state message
+#[derive(Debug, Clone, PartialEq)]
+struct PlayerState {
+    name: String,
+    x: f64,
+    y: f64,
+    velocity: (f64, f64),
+    /// Tick of the player's own client this state matches
+    tick: Option<u64>,
+}
+
+/// What a state message changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateUpdate {
+    /// Whether any player joined, left, moved or was renamed, not counting the local player's position
+    pub changed: bool,
+    /// The local player's state on the server, if it reported the player
+    pub own: Option<AuthoritativeState>,
+}
+
+/// Gives the local player the id the server knows it by
+pub fn set_own_id(entities: &mut Entities, own_id: u16) {
+    if let Some(player) = entities.find::<PlayerControlled>() {
+        entities.insert(player, NetworkId(own_id));
+    }
+}
+
+/// Forgets all players, e.g. after the connection was lost
+pub fn clear(entities: &mut Entities) {
+    let players: Vec<Entity> = entities
+        .iter::<NetworkId>()
+        .map(|(entity, _id)| entity)
+        .collect();
+    for player in players {
+        if entities.has::<PlayerControlled>(player) {
+            entities.remove::<NetworkId>(player);
+            entities.remove::<Name>(player);
+        } else {
+            entities.despawn(player);
+        }
+    }
+}
+
+/// Display name the server uses for the local player
+pub fn own_name(entities: &Entities) -> Option<&str> {
+    let player = entities.find::<PlayerControlled>()?;
+    entities.get::<Name>(player).map(|name| name.0.as_str())
+}
+
+/// All players the server reports, including the local one
+pub fn count(entities: &Entities) -> usize {
+    entities.iter::<NetworkId>().count()
+}
+
+/**
+ * Updates the players from a `state|tick|id,x,y,vx,vy,client_tick,name;...` server message,
+ * spawning & despawning the entities of players that joined or left.
+ * Returns None if the message is not a state message. The local player is not moved, its state is
+ * returned to reconcile the prediction with instead.
+ */
+pub fn handle_server_message(entities: &mut Entities, message: &str) -> Option<StateUpdate> {
+    let data: Vec<&str> = message.splitn(3, '|').collect();
+    let players = match data[..] {
+        ["state", _tick, players] => players,
+        _ => return None,
+    };
+    let mut next_players = HashMap::new();
+    for player in players.split(';').filter(|player| !player.is_empty()) {
+        match parse_player(player) {
+            Some((id, player)) => {
+                next_players.insert(id, player);
+            }
+            None => log::warn!("Invalid player state: {}", player),
+        }
+    }
+
+    let mut changed = false;
+    let mut own_state = None;
+    let known: Vec<(Entity, u16)> = entities
+        .iter::<NetworkId>()
+        .map(|(entity, id)| (entity, id.0))
+        .collect();
+    for (entity, id) in known {
+        let own = entities.has::<PlayerControlled>(entity);
+        let player = match next_players.remove(&id) {
+            Some(player) => player,
+            // The local player stays, it just is not named until the server reports it again
+            None if own => {
+                changed |= entities.remove::<Name>(entity).is_some();
+                continue;
+            }
+            None => {
+                changed |= entities.despawn(entity);
+                continue;
+            }
+        };
+        if entities.get::<Name>(entity).map(|name| name.0.as_str()) != Some(&player.name) {
+            entities.insert(entity, Name(player.name));
+            changed = true;
+        }
+        let transform = Transform::new(player.x, player.y);
+        if own {
+            own_state = Some(AuthoritativeState {
+                tick: player.tick,
+                position: transform.position(),
+                velocity: player.velocity,
+            });
+        } else if entities.get::<Transform>(entity) != Some(&transform) {
+            entities.insert(entity, transform);
+            changed = true;
+        }
+    }
+    for (id, player) in next_players {
+        let entity = entities.spawn();
+        entities.insert(entity, NetworkId(id));
+        entities.insert(entity, Name(player.name));
+        entities.insert(entity, Transform::new(player.x, player.y));
+        entities.insert(entity, Sprite(PLAYER_SPRITE.to_string()));
+        changed = true;
+    }
+    Some(StateUpdate {
+        changed,
+        own: own_state,
+    })
+}
+
+/**
+ * Queues a name tag centered above every named player, the local one in its own color.
+ * `ui_scale` converts the camera's logical pixels into ui pixels.
+ */
+pub fn draw_name_tags(
+    entities: &Entities,
+    text_renderer: &mut TextRenderer,
+    camera: &Camera,
+    ui_scale: f64,
+) {
+    for (entity, name, transform) in entities.iter2::<Name, Transform>() {
+        // Players are drawn as a 1x1 square starting at their position, anchor to the middle of its top edge
+        let (anchor_x, anchor_y) = camera.world_to_screen((transform.x + 0.5, transform.y + 1.0));
+        let color = if entities.has::<PlayerControlled>(entity) {
+            OWN_NAME_COLOR
+        } else {
+            NAME_COLOR
+        };
+        draw_name_tag(
+            text_renderer,
+            &name.0,
+            ((anchor_x / ui_scale) as f32, (anchor_y / ui_scale) as f32),
+            color,
+        );
+    }
+}
+
+/// Parses a single `id,x,y,vx,vy,client_tick,name` player entry, the client tick may be empty
+fn parse_player(player: &str) -> Option<(u16, PlayerState)> {
+    let data: Vec<&str> = player.splitn(7, ',').collect();
+    match data[..] {
+        [id, x, y, vx, vy, tick, name] => Some((
+            id.parse().ok()?,
+            PlayerState {
+                name: name.to_string(),
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+                velocity: (vx.parse().ok()?, vy.parse().ok()?),
+                tick: match tick {
+                    "" => None,
+                    tick => Some(tick.parse().ok()?),
+                },
+            },
+        )),
+        _ => None,
+    }
+}
+
+/// Queues a name tag centered above the anchor, in ui pixels from the top-left corner of the window
+fn draw_name_tag(
+    text_renderer: &mut TextRenderer,
+    name: &str,
+    (anchor_x, anchor_y): (f32, f32),
+    color: Color,
+) {
+    let bottom = anchor_y - NAME_TAG_GAP;
+    let (width, height) = text_renderer.measure(name, NAME_TAG_SIZE);
+    text_renderer.queue_rect(
+        anchor_x - width / 2.0 - NAME_TAG_PADDING,
+        bottom - height - 2.0 * NAME_TAG_PADDING,
+        width + 2.0 * NAME_TAG_PADDING,
+        height + 2.0 * NAME_TAG_PADDING,
+        NAME_BACKGROUND_COLOR,
+    );
+    text_renderer.queue_text(&TextSection {
+        text: name,
+        anchor: TextAnchor::Screen {
+            x: anchor_x,
+            y: bottom - NAME_TAG_PADDING,
+        },
+        size: NAME_TAG_SIZE,
+        color,
+        align: TextAlign::BottomCenter,
+    });
+}
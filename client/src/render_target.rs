@@ -0,0 +1,162 @@
+/// Format of offscreen textures, so read back pixels are already in png order
+#[cfg(not(target_arch = "wasm32"))]
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/**
+ * Where frames are drawn to: the window, or a texture that is read back instead of shown so the
+ * game can be drawn without a window (e.g. for screenshot tests on machines without a gpu).
+ */
+pub enum RenderTarget {
+    Surface(wgpu::Surface),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg_attr(not(test), allow(dead_code))] // Only the screenshot tests draw without a window
+    Offscreen {
+        texture: wgpu::Texture,
+        size: wgpu::Extent3d,
+    },
+}
+
+/// A frame being drawn, shown by `present`
+pub struct Frame {
+    /// Nothing is presented for offscreen targets
+    surface_texture: Option<wgpu::SurfaceTexture>,
+    pub view: wgpu::TextureView,
+}
+
+impl Frame {
+    pub fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
+}
+
+impl RenderTarget {
+    /// A texture the size & format of the configuration, see `OFFSCREEN_FORMAT`
+    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn offscreen(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        RenderTarget::Offscreen {
+            texture: init_offscreen_texture(device, size, config.format),
+            size,
+        }
+    }
+
+    /// Applies a new size, surfaces are reconfigured & offscreen textures recreated
+    pub fn configure(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        match self {
+            RenderTarget::Surface(surface) => surface.configure(device, config),
+            #[cfg(not(target_arch = "wasm32"))]
+            RenderTarget::Offscreen { .. } => *self = RenderTarget::offscreen(device, config),
+        }
+    }
+
+    pub fn next_frame(&self) -> Frame {
+        let (surface_texture, view) = match self {
+            RenderTarget::Surface(surface) => {
+                let surface_texture = surface
+                    .get_current_texture()
+                    .expect("Failed to acquire next swap chain texture");
+                let view = surface_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(surface_texture), view)
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            RenderTarget::Offscreen { texture, .. } => (
+                None,
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        };
+        Frame {
+            surface_texture,
+            view,
+        }
+    }
+
+    /**
+     * Copies the last frame drawn to an offscreen target back from the gpu, blocking until it is
+     * done. Surfaces can not be read back, so they return None.
+     */
+    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn read_pixels(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Option<image::RgbaImage> {
+        let (texture, size) = match self {
+            RenderTarget::Surface(_) => return None,
+            RenderTarget::Offscreen { texture, size } => (texture, *size),
+        };
+        let (width, height) = (size.width, size.height);
+        // Rows in the buffer must be padded to a multiple of 256 bytes
+        let row_bytes = width * 4;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = row_bytes.div_ceil(alignment) * alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen readback buffer"),
+            size: (padded_row_bytes * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            // The receiver waits below, so it can not be gone
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .expect("Readback buffer was dropped before it was mapped")
+            .unwrap_or_else(|err| panic!("Failed to read back offscreen texture: {}", err));
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(padded_row_bytes as usize)
+            .flat_map(|row| row[..row_bytes as usize].iter().copied())
+            .collect();
+        buffer.unmap();
+        image::RgbaImage::from_raw(width, height, pixels)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn init_offscreen_texture(
+    device: &wgpu::Device,
+    size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen render texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        // Drawn to, then copied to a buffer to read it back
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    })
+}
@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+
+use super::input::Bindings;
+
+/// Range of the ui scale slider
+pub const MIN_UI_SCALE: f32 = 0.75;
+pub const MAX_UI_SCALE: f32 = 2.0;
+
+/**
+ * Player preferences changed from the settings menu, stored as json in the user's config
+ * directory on desktop and in local storage on web.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Multiplies the window scale factor for all text & menus
+    pub ui_scale: f32,
+    pub bindings: Bindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            ui_scale: 1.0,
+            bindings: Bindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the saved settings, falls back to the defaults if there are none or they are invalid
+    pub fn load() -> Self {
+        storage::read()
+            .and_then(|json| Settings::from_json(&json))
+            .unwrap_or_default()
+    }
+
+    /// Missing fields keep their defaults & values out of range are clamped, None if the json is invalid
+    fn from_json(json: &str) -> Option<Self> {
+        serde_json::from_str::<Settings>(json)
+            .map_err(|err| log::warn!("Ignoring invalid settings: {}", err))
+            .ok()
+            .map(|settings| Settings {
+                ui_scale: settings.ui_scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE),
+                ..settings
+            })
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => storage::write(&json),
+            Err(err) => log::warn!("Failed to serialize settings: {}", err),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod storage {
+    use std::{fs, path::PathBuf};
+
+    fn path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_default()
+            .join("endless")
+            .join("settings.json")
+    }
+
+    pub fn read() -> Option<String> {
+        fs::read_to_string(path()).ok()
+    }
+
+    pub fn write(json: &str) {
+        let path = path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .unwrap_or_else(|err| log::warn!("Failed to create {:?}: {}", dir, err));
+        }
+        fs::write(&path, json)
+            .unwrap_or_else(|err| log::warn!("Failed to save settings to {:?}: {}", path, err));
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod storage {
+    const KEY: &str = "endless_settings";
+
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok().flatten()
+    }
+
+    pub fn read() -> Option<String> {
+        local_storage()?.get_item(KEY).ok().flatten()
+    }
+
+    pub fn write(json: &str) {
+        match local_storage() {
+            Some(storage) => storage
+                .set_item(KEY, json)
+                .unwrap_or_else(|err| log::warn!("Failed to save settings: {:?}", err)),
+            None => log::warn!("Local storage is not available, settings are not saved"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Action, Binding};
+    use winit::event::VirtualKeyCode;
+
+    #[test]
+    fn saved_settings_load_unchanged() {
+        let mut settings = Settings {
+            ui_scale: 1.5,
+            ..Settings::default()
+        };
+        settings
+            .bindings
+            .set(Action::Interact, Binding::Key(VirtualKeyCode::F));
+        let json = serde_json::to_string_pretty(&settings).unwrap();
+        assert_eq!(Settings::from_json(&json), Some(settings));
+    }
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        assert_eq!(Settings::from_json("{}"), Some(Settings::default()));
+        let settings = Settings::from_json(r#"{ "ui_scale": 1.25 }"#).unwrap();
+        assert_eq!(settings.ui_scale, 1.25);
+        assert_eq!(settings.bindings, Bindings::default());
+    }
+
+    #[test]
+    fn invalid_settings_are_ignored_or_clamped() {
+        assert_eq!(Settings::from_json("not json"), None);
+        assert_eq!(Settings::from_json(r#"{ "ui_scale": "big" }"#), None);
+        let settings = Settings::from_json(r#"{ "ui_scale": 10.0 }"#).unwrap();
+        assert_eq!(settings.ui_scale, MAX_UI_SCALE);
+        let settings = Settings::from_json(r#"{ "ui_scale": 0.1 }"#).unwrap();
+        assert_eq!(settings.ui_scale, MIN_UI_SCALE);
+    }
+}
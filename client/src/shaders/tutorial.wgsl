@@ -1,38 +0,0 @@
-// Vertex shader
-
-// group() = corresponds to binding (1st) parameter in set_bind_group in render
-// binding() = corresponds to bind_group_layout entry binding value in layout creation
-@group(1) @binding(0)
-var<uniform> view_proj: mat4x4<f32>;
-
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
-};
-
-@vertex
-fn vs_main(
-    model: VertexInput,
-) -> VertexOutput {
-    var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.clip_position = view_proj * vec4<f32>(model.position, 1.0);
-    return out;
-}
-
-// Fragment shader
-
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>; // texture view
-@group(0) @binding(1)
-var s_diffuse: sampler; // texture sampler
-
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
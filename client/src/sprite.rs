@@ -0,0 +1,324 @@
+use std::cmp::Ordering;
+use wgpu::util::DeviceExt;
+
+use super::buffer::GrowableBuffer;
+
+pub type Tint = [f32; 4];
+
+pub const NO_TINT: Tint = [1.0, 1.0, 1.0, 1.0];
+
+/**
+ * Render layers from bottom to top. Objects & characters are sorted together by the bottom edge
+ * of their sprites, so a character standing behind an object is hidden by it.
+ */
+#[allow(dead_code)] // Nothing is drawn on the decal, overlay & ui layers yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Ground,
+    /// Flat details on top of the ground, e.g. paths or footprints
+    Decals,
+    Objects,
+    Characters,
+    /// Above everything in the world, e.g. selection markers
+    Overlays,
+    /// Above overlays, for world anchored interface elements
+    Ui,
+}
+
+impl Layer {
+    /// Layers that are y-sorted with each other share a group
+    fn group(self) -> u8 {
+        match self {
+            Layer::Ground => 0,
+            Layer::Decals => 1,
+            Layer::Objects | Layer::Characters => 2,
+            Layer::Overlays => 3,
+            Layer::Ui => 4,
+        }
+    }
+
+    fn is_y_sorted(self) -> bool {
+        matches!(self, Layer::Objects | Layer::Characters)
+    }
+}
+
+/**
+ * A textured rectangle in the world.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    /// Bottom-left corner in world squares
+    pub position: [f32; 2],
+    /// Width & height in world squares
+    pub size: [f32; 2],
+    /// Part of the texture to show as [u_min, v_min, u_max, v_max], v points down.
+    /// Mirrored sprites have their min & max swapped.
+    pub atlas_rect: [f32; 4],
+    /// Multiplied with the texture color
+    pub tint: Tint,
+    pub layer: Layer,
+}
+
+/**
+ * Sprites are drawn by layer, y-sorted sprites further up the screen first. Objects are drawn
+ * before characters at the same height, anything else in the order it was added.
+ */
+fn draw_order(a: &Sprite, b: &Sprite) -> Ordering {
+    a.layer
+        .group()
+        .cmp(&b.layer.group())
+        .then_with(|| {
+            if a.layer.is_y_sorted() && b.layer.is_y_sorted() {
+                b.position[1].total_cmp(&a.position[1])
+            } else {
+                Ordering::Equal
+            }
+        })
+        .then_with(|| a.layer.cmp(&b.layer))
+}
+
+/// Per sprite data read by the vertex shader for every corner of the quad
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteInstance {
+    position: [f32; 2],
+    size: [f32; 2],
+    atlas_rect: [f32; 4],
+    tint: [f32; 4],
+}
+
+impl SpriteInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        1 => Float32x2,
+        2 => Float32x2,
+        3 => Float32x4,
+        4 => Float32x4
+    ];
+}
+
+/// Corner of the unit quad every sprite is drawn with
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct QuadVertex {
+    position: [f32; 2],
+}
+
+impl QuadVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x2];
+}
+
+const QUAD_VERTICES: [QuadVertex; 4] = [
+    QuadVertex {
+        position: [0.0, 0.0],
+    },
+    QuadVertex {
+        position: [1.0, 0.0],
+    },
+    QuadVertex {
+        position: [1.0, 1.0],
+    },
+    QuadVertex {
+        position: [0.0, 1.0],
+    },
+];
+/// Two counter-clockwise triangles
+const QUAD_INDICES: [u16; 6] = [0, 1, 3, 1, 2, 3];
+
+/**
+ * Collects sprites and draws all of them with a single instanced draw call.
+ *
+ * Every sprite is one instance of a shared unit quad, so a frame only uploads 48 bytes per sprite
+ * and there is no limit on the number of sprites other than gpu memory.
+ */
+pub struct SpriteBatch {
+    sprites: Vec<Sprite>,
+    quad_vertices: wgpu::Buffer,
+    quad_indices: wgpu::Buffer,
+    instances: GrowableBuffer,
+    instance_count: u32,
+}
+
+impl SpriteBatch {
+    /// Allocates room for the given number of sprites, the batch grows when more are added
+    pub fn new(device: &wgpu::Device, label: &'static str, capacity: u64) -> Self {
+        SpriteBatch {
+            sprites: Vec::new(),
+            quad_vertices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Sprite quad vertex buffer"),
+                contents: bytemuck::cast_slice(&QUAD_VERTICES),
+                usage: wgpu::BufferUsages::VERTEX,
+            }),
+            quad_indices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Sprite quad index buffer"),
+                contents: bytemuck::cast_slice(&QUAD_INDICES),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+            instances: GrowableBuffer::new(
+                device,
+                label,
+                wgpu::BufferUsages::VERTEX,
+                capacity * std::mem::size_of::<SpriteInstance>() as u64,
+            ),
+            instance_count: 0,
+        }
+    }
+
+    /// Vertex buffer layouts for pipelines that draw sprite batches: the quad & the instances
+    pub fn buffer_layouts<'a>() -> [wgpu::VertexBufferLayout<'a>; 2] {
+        [
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<QuadVertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &QuadVertex::ATTRIBS,
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<SpriteInstance>() as wgpu::BufferAddress,
+                // Every quad moves on to the next instance
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &SpriteInstance::ATTRIBS,
+            },
+        ]
+    }
+
+    /// Removes all sprites, the gpu keeps drawing the previous ones until the next `upload`
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn extend(&mut self, sprites: impl IntoIterator<Item = Sprite>) {
+        self.sprites.extend(sprites);
+    }
+
+    /// Sorts the sprites into draw order and writes them to the gpu
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        // Stable, so sprites that are equal in draw order keep the order they were added in
+        self.sprites.sort_by(draw_order);
+        let instances: Vec<SpriteInstance> = self
+            .sprites
+            .iter()
+            .map(|sprite| SpriteInstance {
+                position: sprite.position,
+                size: sprite.size,
+                atlas_rect: sprite.atlas_rect,
+                tint: sprite.tint,
+            })
+            .collect();
+        self.instances.write(device, queue, &instances);
+        self.instance_count = instances.len() as u32;
+    }
+
+    /// Draws all uploaded sprites, the pipeline & bind groups must already be set
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        if self.instance_count == 0 {
+            return;
+        }
+        rpass.set_vertex_buffer(0, self.quad_vertices.slice(..));
+        rpass.set_vertex_buffer(1, self.instances.slice());
+        rpass.set_index_buffer(self.quad_indices.slice(..), wgpu::IndexFormat::Uint16);
+        rpass.draw_indexed(0..QUAD_INDICES.len() as u32, 0, 0..self.instance_count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quad_indices_cover_the_unit_quad_counter_clockwise() {
+        for triangle in QUAD_INDICES.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| QUAD_VERTICES[triangle[i] as usize].position);
+            let cross = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+            assert!(cross > 0.0, "{:?} is not counter-clockwise", triangle);
+        }
+        let mut used = QUAD_INDICES.to_vec();
+        used.sort_unstable();
+        used.dedup();
+        assert_eq!(used, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn instance_attributes_fill_the_instance() {
+        let [_, instances] = SpriteBatch::buffer_layouts();
+        let last = instances.attributes.last().unwrap();
+        assert_eq!(
+            last.offset + last.format.size(),
+            std::mem::size_of::<SpriteInstance>() as u64
+        );
+        assert_eq!(
+            instances.array_stride,
+            std::mem::size_of::<SpriteInstance>() as u64
+        );
+        assert_eq!(instances.step_mode, wgpu::VertexStepMode::Instance);
+    }
+
+    fn sprite(layer: Layer, y: f32) -> Sprite {
+        Sprite {
+            position: [0.0, y],
+            size: [1.0, 1.0],
+            atlas_rect: [0.0, 0.0, 1.0, 1.0],
+            tint: NO_TINT,
+            layer,
+        }
+    }
+
+    fn sorted(mut sprites: Vec<Sprite>) -> Vec<(Layer, f32)> {
+        sprites.sort_by(draw_order);
+        sprites
+            .iter()
+            .map(|sprite| (sprite.layer, sprite.position[1]))
+            .collect()
+    }
+
+    #[test]
+    fn draws_layers_from_bottom_to_top() {
+        let sprites = vec![
+            sprite(Layer::Ui, 0.0),
+            sprite(Layer::Overlays, 0.0),
+            sprite(Layer::Characters, 0.0),
+            sprite(Layer::Decals, -5.0),
+            sprite(Layer::Ground, 5.0),
+        ];
+        assert_eq!(
+            sorted(sprites),
+            [
+                (Layer::Ground, 5.0),
+                (Layer::Decals, -5.0),
+                (Layer::Characters, 0.0),
+                (Layer::Overlays, 0.0),
+                (Layer::Ui, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn y_sorts_objects_with_characters() {
+        let sprites = vec![
+            sprite(Layer::Characters, 1.0),
+            sprite(Layer::Objects, 0.0),
+            sprite(Layer::Characters, 2.0),
+            sprite(Layer::Characters, 0.0),
+            sprite(Layer::Objects, 2.0),
+        ];
+        assert_eq!(
+            sorted(sprites),
+            [
+                (Layer::Objects, 2.0),
+                (Layer::Characters, 2.0),
+                (Layer::Characters, 1.0),
+                (Layer::Objects, 0.0),
+                (Layer::Characters, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_order_sprites_were_added_in_otherwise() {
+        let first = sprite(Layer::Ground, 0.0);
+        // Ground is not y-sorted, so the height does not matter
+        let second = sprite(Layer::Ground, -1.0);
+        let third = sprite(Layer::Ground, 1.0);
+        let mut sprites = vec![first, second, third];
+        sprites.sort_by(draw_order);
+        assert_eq!(sprites, [first, second, third]);
+    }
+}
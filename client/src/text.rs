@@ -0,0 +1,787 @@
+use ab_glyph::{point, Font, FontRef, GlyphId, PxScale, ScaleFont};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use wgpu::util::DeviceExt;
+
+use super::assets::catch_validation_error;
+use super::buffer::GrowableBuffer;
+use winit::dpi::PhysicalSize;
+
+/// Width & height of the glyph atlas texture in pixels
+const ATLAS_SIZE: u32 = 512;
+/// The top-left block of the atlas is fully covered, sampling it draws solid rectangles
+const SOLID_BLOCK_SIZE: u32 = 2;
+/// Empty pixels between glyphs to avoid sampling neighbouring glyphs
+const GLYPH_PADDING: u32 = 1;
+
+pub type Color = [f32; 4];
+
+#[derive(Debug, Clone, Copy)]
+pub enum TextAnchor {
+    /// Logical pixels from the top-left corner of the window
+    Screen { x: f32, y: f32 },
+    /// World coordinates in squares, the text follows the world when the player moves
+    World { x: f32, y: f32 },
+}
+
+/// Which point of the text box is placed on the anchor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextAlign {
+    TopLeft,
+    BottomCenter,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextSection<'a> {
+    pub text: &'a str,
+    pub anchor: TextAnchor,
+    /// Font size in logical pixels, text keeps the same size on screen regardless of the scale factor
+    pub size: f32,
+    pub color: Color,
+    pub align: TextAlign,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextVertex {
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+}
+
+impl TextVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x4];
+
+    fn buffer_layout_description<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TextVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// Location of a rasterized glyph inside the atlas
+#[derive(Debug, Clone, Copy)]
+struct AtlasGlyph {
+    /// Top-left pixel of the glyph in the atlas
+    atlas: [u32; 2],
+    /// Width & height in pixels
+    size: [u32; 2],
+    /// Offset of the top-left pixel from the pen position on the baseline
+    offset: [f32; 2],
+}
+
+/**
+ * CPU copy of the glyph atlas. Glyphs are rasterized on first use per font size and packed
+ * into rows (shelves), the texture is re-uploaded whenever new glyphs were added.
+ */
+struct GlyphAtlas {
+    pixels: Vec<u8>,
+    /// None for glyphs without an outline (e.g. spaces)
+    glyphs: HashMap<(GlyphId, u32), Option<AtlasGlyph>>,
+    cursor: [u32; 2],
+    row_height: u32,
+    dirty: bool,
+    full: bool,
+}
+
+impl GlyphAtlas {
+    fn new() -> Self {
+        let mut atlas = GlyphAtlas {
+            pixels: Vec::new(),
+            glyphs: HashMap::new(),
+            cursor: [0, 0],
+            row_height: 0,
+            dirty: true,
+            full: false,
+        };
+        atlas.clear();
+        atlas
+    }
+
+    fn clear(&mut self) {
+        self.pixels = vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize];
+        for y in 0..SOLID_BLOCK_SIZE {
+            for x in 0..SOLID_BLOCK_SIZE {
+                self.pixels[(y * ATLAS_SIZE + x) as usize] = 255;
+            }
+        }
+        self.glyphs.clear();
+        self.cursor = [SOLID_BLOCK_SIZE + GLYPH_PADDING, 0];
+        self.row_height = SOLID_BLOCK_SIZE;
+        self.dirty = true;
+        self.full = false;
+    }
+
+    /// Finds room for a glyph of the given size, returns its top-left pixel
+    fn allocate(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        if self.cursor[0] + width > ATLAS_SIZE {
+            self.cursor = [0, self.cursor[1] + self.row_height + GLYPH_PADDING];
+            self.row_height = 0;
+        }
+        if self.cursor[1] + height > ATLAS_SIZE || width > ATLAS_SIZE {
+            self.full = true;
+            return None;
+        }
+        let position = self.cursor;
+        self.cursor[0] += width + GLYPH_PADDING;
+        self.row_height = self.row_height.max(height);
+        Some(position)
+    }
+
+    fn glyph(&mut self, font: &FontRef, glyph_id: GlyphId, px: u32) -> Option<AtlasGlyph> {
+        if let Some(glyph) = self.glyphs.get(&(glyph_id, px)) {
+            return *glyph;
+        }
+        let outline =
+            font.outline_glyph(glyph_id.with_scale_and_position(px as f32, point(0.0, 0.0)));
+        let glyph = match outline {
+            Some(outline) => {
+                let bounds = outline.px_bounds();
+                let size = [bounds.width() as u32, bounds.height() as u32];
+                let atlas = self.allocate(size[0], size[1])?;
+                outline.draw(|x, y, coverage| {
+                    if x < size[0] && y < size[1] {
+                        let index = (atlas[1] + y) * ATLAS_SIZE + atlas[0] + x;
+                        self.pixels[index as usize] = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+                    }
+                });
+                self.dirty = true;
+                Some(AtlasGlyph {
+                    atlas,
+                    size,
+                    offset: [bounds.min.x, bounds.min.y],
+                })
+            }
+            None => None,
+        };
+        self.glyphs.insert((glyph_id, px), glyph);
+        glyph
+    }
+}
+
+/**
+ * Lays queued text & rectangles out as quads in physical pixels, rasterizing glyphs into the atlas
+ * as they are first used. Kept apart from the gpu resources, so layout works without a device.
+ */
+pub struct TextLayout {
+    font: FontRef<'static>,
+    atlas: GlyphAtlas,
+    vertices: Vec<TextVertex>,
+    indices: Vec<u32>,
+    size: PhysicalSize<u32>,
+    scale_factor: f32,
+    world_projection: [[f32; 4]; 4],
+}
+
+impl TextLayout {
+    pub fn new() -> Self {
+        TextLayout {
+            font: FontRef::try_from_slice(include_bytes!("fonts/DejaVuSansMono.ttf"))
+                .expect("Failed to load font"),
+            atlas: GlyphAtlas::new(),
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            size: PhysicalSize::new(1, 1),
+            scale_factor: 1.0,
+            world_projection: IDENTITY,
+        }
+    }
+
+    /**
+     * Clears the previous frame's text. The world projection is used to place world anchored text.
+     */
+    fn begin_frame(
+        &mut self,
+        size: PhysicalSize<u32>,
+        scale_factor: f64,
+        world_projection: [[f32; 4]; 4],
+    ) {
+        if self.atlas.full {
+            log::warn!("Glyph atlas is full, clearing it");
+            self.atlas.clear();
+        }
+        self.vertices.clear();
+        self.indices.clear();
+        self.size = size;
+        self.scale_factor = scale_factor as f32;
+        self.world_projection = world_projection;
+    }
+
+    /// Width & height of the text in logical pixels
+    pub fn measure(&self, text: &str, size: f32) -> (f32, f32) {
+        let scaled = self
+            .font
+            .as_scaled(PxScale::from(self.pixel_size(size) as f32));
+        let line_height = scaled.height() + scaled.line_gap();
+        let mut width: f32 = 0.0;
+        let mut lines = 0;
+        for line in text.split('\n') {
+            width = width.max(self.line_width(line, &scaled));
+            lines += 1;
+        }
+        (
+            width / self.scale_factor,
+            lines as f32 * line_height / self.scale_factor,
+        )
+    }
+
+    pub fn queue_text(&mut self, section: &TextSection) {
+        let px = self.pixel_size(section.size);
+        let font = self.font.clone();
+        let scaled = font.as_scaled(PxScale::from(px as f32));
+        let line_height = (scaled.height() + scaled.line_gap()).ceil();
+        let line_count = section.text.split('\n').count() as f32;
+        let [anchor_x, anchor_y] = self.anchor_position(section.anchor);
+        let top = match section.align {
+            TextAlign::TopLeft => anchor_y,
+            TextAlign::BottomCenter => anchor_y - line_count * line_height,
+        };
+        for (line_index, line) in section.text.split('\n').enumerate() {
+            let baseline = (top + line_index as f32 * line_height + scaled.ascent()).round();
+            let mut pen_x = match section.align {
+                TextAlign::TopLeft => anchor_x,
+                TextAlign::BottomCenter => anchor_x - self.line_width(line, &scaled) / 2.0,
+            }
+            .round();
+            for character in line.chars() {
+                let glyph_id = font.glyph_id(character);
+                if let Some(glyph) = self.atlas.glyph(&font, glyph_id, px) {
+                    let x = pen_x + glyph.offset[0];
+                    let y = baseline + glyph.offset[1];
+                    self.push_quad(
+                        [x, y, glyph.size[0] as f32, glyph.size[1] as f32],
+                        glyph.atlas,
+                        glyph.size,
+                        section.color,
+                    );
+                }
+                pen_x += scaled.h_advance(glyph_id);
+            }
+        }
+    }
+
+    /// Queues a solid rectangle, given in logical pixels from the top-left corner of the window
+    pub fn queue_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        let scale = self.scale_factor;
+        self.push_quad(
+            [
+                (x * scale).round(),
+                (y * scale).round(),
+                (width * scale).round(),
+                (height * scale).round(),
+            ],
+            [0, 0],
+            [SOLID_BLOCK_SIZE, SOLID_BLOCK_SIZE],
+            color,
+        );
+    }
+
+    /// Font size in physical pixels, rounded so glyphs can be reused between frames
+    fn pixel_size(&self, size: f32) -> u32 {
+        ((size * self.scale_factor).round() as u32).max(1)
+    }
+
+    fn line_width(&self, line: &str, scaled: &ab_glyph::PxScaleFont<&FontRef>) -> f32 {
+        line.chars()
+            .map(|character| scaled.h_advance(self.font.glyph_id(character)))
+            .sum()
+    }
+
+    /// Position of the anchor in physical pixels from the top-left corner of the window
+    fn anchor_position(&self, anchor: TextAnchor) -> [f32; 2] {
+        match anchor {
+            TextAnchor::Screen { x, y } => [x * self.scale_factor, y * self.scale_factor],
+            TextAnchor::World { x, y } => {
+                let m = &self.world_projection;
+                let clip_x = m[0][0] * x + m[1][0] * y + m[3][0];
+                let clip_y = m[0][1] * x + m[1][1] * y + m[3][1];
+                [
+                    (clip_x + 1.0) / 2.0 * self.size.width as f32,
+                    (1.0 - clip_y) / 2.0 * self.size.height as f32,
+                ]
+            }
+        }
+    }
+
+    /// Adds a quad at [x, y, width, height] (physical pixels) showing the given atlas pixels
+    fn push_quad(&mut self, rect: [f32; 4], atlas: [u32; 2], atlas_size: [u32; 2], color: Color) {
+        let [x, y, width, height] = rect;
+        let u_min = atlas[0] as f32 / ATLAS_SIZE as f32;
+        let v_min = atlas[1] as f32 / ATLAS_SIZE as f32;
+        let u_max = (atlas[0] + atlas_size[0]) as f32 / ATLAS_SIZE as f32;
+        let v_max = (atlas[1] + atlas_size[1]) as f32 / ATLAS_SIZE as f32;
+        let index = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&[
+            TextVertex {
+                position: [x, y],
+                tex_coords: [u_min, v_min],
+                color,
+            },
+            TextVertex {
+                position: [x + width, y],
+                tex_coords: [u_max, v_min],
+                color,
+            },
+            TextVertex {
+                position: [x + width, y + height],
+                tex_coords: [u_max, v_max],
+                color,
+            },
+            TextVertex {
+                position: [x, y + height],
+                tex_coords: [u_min, v_max],
+                color,
+            },
+        ]);
+        self.indices.extend_from_slice(&[
+            index,
+            index + 1,
+            index + 3,
+            index + 1,
+            index + 2,
+            index + 3,
+        ]);
+    }
+}
+
+/**
+ * Draws text (and solid rectangles, e.g. backgrounds behind text) on top of the world.
+ *
+ * Text is queued every frame between `begin_frame` and `prepare`, then drawn by `render` inside
+ * the main render pass. Everything is laid out in physical pixels so glyphs stay sharp.
+ */
+pub struct TextRenderer {
+    layout: TextLayout,
+    atlas_texture: wgpu::Texture,
+    format: wgpu::TextureFormat,
+    pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    glyph_bind_group: wgpu::BindGroup,
+    screen_bind_group: wgpu::BindGroup,
+    screen_buffer: wgpu::Buffer,
+    vertex_buffer: GrowableBuffer,
+    index_buffer: GrowableBuffer,
+    index_count: u32,
+}
+
+impl TextRenderer {
+    /// Draws to targets of the given format with the given wgsl shader source
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, shader: &str) -> Self {
+        let atlas_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Only coverage is stored, the color comes from the vertices
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("Glyph atlas texture"),
+        });
+        let atlas_view = atlas_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let glyph_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("Glyph atlas bind group layout"),
+            });
+        let glyph_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &glyph_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("Glyph atlas bind group"),
+        });
+        let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Text screen projection buffer"),
+            contents: bytemuck::cast_slice(&IDENTITY),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let screen_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Text screen projection bind group layout"),
+            });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &screen_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_buffer.as_entire_binding(),
+            }],
+            label: Some("Text screen projection bind group"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text render pipeline layout"),
+            bind_group_layouts: &[&glyph_bind_group_layout, &screen_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = init_text_pipeline(device, format, &pipeline_layout, shader);
+        TextRenderer {
+            layout: TextLayout::new(),
+            atlas_texture,
+            format,
+            pipeline_layout,
+            render_pipeline,
+            glyph_bind_group,
+            screen_bind_group,
+            screen_buffer,
+            vertex_buffer: GrowableBuffer::new(
+                device,
+                "Text vertex buffer",
+                wgpu::BufferUsages::VERTEX,
+                1024 * std::mem::size_of::<TextVertex>() as u64,
+            ),
+            index_buffer: GrowableBuffer::new(
+                device,
+                "Text index buffer",
+                wgpu::BufferUsages::INDEX,
+                1536 * std::mem::size_of::<u32>() as u64,
+            ),
+            index_count: 0,
+        }
+    }
+
+    /**
+     * Clears the previous frame's text. The world projection is used to place world anchored text.
+     */
+    pub fn begin_frame(
+        &mut self,
+        size: PhysicalSize<u32>,
+        scale_factor: f64,
+        world_projection: [[f32; 4]; 4],
+    ) {
+        self.layout
+            .begin_frame(size, scale_factor, world_projection);
+    }
+
+    /// Width & height of the text in logical pixels
+    pub fn measure(&self, text: &str, size: f32) -> (f32, f32) {
+        self.layout.measure(text, size)
+    }
+
+    pub fn queue_text(&mut self, section: &TextSection) {
+        self.layout.queue_text(section);
+    }
+
+    /// Queues a solid rectangle, given in logical pixels from the top-left corner of the window
+    pub fn queue_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        self.layout.queue_rect(x, y, width, height, color);
+    }
+
+    /// Text & rectangles queued on the layout are drawn by this renderer as well
+    pub fn layout(&mut self) -> &mut TextLayout {
+        &mut self.layout
+    }
+
+    /**
+     * Uploads the glyph atlas (if new glyphs were rasterized), the screen projection & all queued quads.
+     */
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.layout.atlas.dirty {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.atlas_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &self.layout.atlas.pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(ATLAS_SIZE),
+                    rows_per_image: std::num::NonZeroU32::new(ATLAS_SIZE),
+                },
+                wgpu::Extent3d {
+                    width: ATLAS_SIZE,
+                    height: ATLAS_SIZE,
+                    depth_or_array_layers: 1,
+                },
+            );
+            self.layout.atlas.dirty = false;
+        }
+        queue.write_buffer(
+            &self.screen_buffer,
+            0,
+            bytemuck::cast_slice(&screen_projection(&self.layout.size)),
+        );
+        self.index_count = self.layout.indices.len() as u32;
+        if self.layout.indices.is_empty() {
+            return;
+        }
+        self.vertex_buffer
+            .write(device, queue, &self.layout.vertices);
+        self.index_buffer.write(device, queue, &self.layout.indices);
+    }
+
+    pub fn render<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        if self.index_count > 0 {
+            rpass.set_pipeline(&self.render_pipeline);
+            rpass.set_bind_group(0, &self.glyph_bind_group, &[]);
+            rpass.set_bind_group(1, &self.screen_bind_group, &[]);
+            rpass.set_vertex_buffer(0, self.vertex_buffer.slice());
+            rpass.set_index_buffer(self.index_buffer.slice(), wgpu::IndexFormat::Uint32);
+            rpass.draw_indexed(0..self.index_count, 0, 0..1);
+        }
+    }
+
+    /// Rebuilds the pipeline with a new shader, the old one stays in use if the new one is invalid
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader: &str) -> Result<(), String> {
+        self.render_pipeline = catch_validation_error(device, || {
+            init_text_pipeline(device, self.format, &self.pipeline_layout, shader)
+        })?;
+        Ok(())
+    }
+}
+
+const IDENTITY: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/**
+ * Maps physical pixels (top-left: (0,0), bottom-right: (width,height)) to clip space
+ * (top-left: (-1,1), bottom-right: (1,-1)).
+ */
+fn screen_projection(size: &PhysicalSize<u32>) -> [[f32; 4]; 4] {
+    let scale_x = 2.0 / size.width.max(1) as f32;
+    let scale_y = -2.0 / size.height.max(1) as f32;
+    [
+        [scale_x, 0.0, 0.0, 0.0],
+        [0.0, scale_y, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [-1.0, 1.0, 0.0, 1.0],
+    ]
+}
+
+fn init_text_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    pipeline_layout: &wgpu::PipelineLayout,
+    shader: &str,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Text shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(shader)),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Text render pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[TextVertex::buffer_layout_description()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // Screen space flips the y axis, which flips the winding order of the quads
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = [1.0, 1.0, 1.0, 1.0];
+
+    fn layout(scale_factor: f64) -> TextLayout {
+        let mut layout = TextLayout::new();
+        layout.begin_frame(PhysicalSize::new(200, 100), scale_factor, IDENTITY);
+        layout
+    }
+
+    /// Smallest & largest x & y of the queued quads, in physical pixels
+    fn bounds(layout: &TextLayout) -> [f32; 4] {
+        layout.vertices.iter().fold(
+            [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+            |[min_x, min_y, max_x, max_y], vertex| {
+                let [x, y] = vertex.position;
+                [min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)]
+            },
+        )
+    }
+
+    fn queue(layout: &mut TextLayout, text: &str, align: TextAlign) {
+        layout.queue_text(&TextSection {
+            text,
+            anchor: TextAnchor::Screen { x: 50.0, y: 40.0 },
+            size: 16.0,
+            color: WHITE,
+            align,
+        });
+    }
+
+    #[test]
+    fn measures_the_longest_line_and_all_lines() {
+        let layout = layout(1.0);
+        let (char_width, line_height) = layout.measure("a", 16.0);
+        assert!(char_width > 0.0 && line_height > 0.0);
+        // The font is monospaced
+        assert_eq!(layout.measure("abc", 16.0), (3.0 * char_width, line_height));
+        assert_eq!(
+            layout.measure("ab\nabcd\n", 16.0),
+            (4.0 * char_width, 3.0 * line_height)
+        );
+        // Sizes are logical, the same text measures the same on high dpi screens
+        let (width, height) = self::layout(2.0).measure("ab\nabcd", 16.0);
+        assert!((width - 4.0 * char_width).abs() < 0.01, "{}", width);
+        assert!((height - 2.0 * line_height).abs() < 0.01, "{}", height);
+    }
+
+    #[test]
+    fn places_anchors_in_physical_pixels() {
+        let mut layout = layout(2.0);
+        assert_eq!(
+            layout.anchor_position(TextAnchor::Screen { x: 10.0, y: 5.0 }),
+            [20.0, 10.0]
+        );
+        // Halves x & moves it right by half the screen, quarters y
+        layout.world_projection = [
+            [0.5, 0.0, 0.0, 0.0],
+            [0.0, 0.25, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.5, 0.0, 0.0, 1.0],
+        ];
+        assert_eq!(
+            layout.anchor_position(TextAnchor::World { x: 1.0, y: 2.0 }),
+            [200.0, 25.0]
+        );
+        assert_eq!(
+            layout.anchor_position(TextAnchor::World { x: -3.0, y: -4.0 }),
+            [0.0, 100.0]
+        );
+    }
+
+    #[test]
+    fn bottom_center_text_ends_above_the_anchor() {
+        let mut top_left = layout(1.0);
+        queue(&mut top_left, "Hello\nyou", TextAlign::TopLeft);
+        let mut bottom_center = layout(1.0);
+        queue(&mut bottom_center, "Hello\nyou", TextAlign::BottomCenter);
+        let (width, height) = bottom_center.measure("Hello", 16.0);
+
+        let [left, top, right, bottom] = bounds(&top_left);
+        let [center_left, center_top, center_right, center_bottom] = bounds(&bottom_center);
+        assert!(top >= 40.0 && left >= 50.0);
+        assert!((center_left - (left - width / 2.0)).abs() <= 1.0);
+        assert!((center_right - (right - width / 2.0)).abs() <= 1.0);
+        assert!((center_top - (top - 2.0 * height)).abs() <= 2.0);
+        assert!((center_bottom - (bottom - 2.0 * height)).abs() <= 2.0);
+        assert!(center_bottom <= 40.0);
+    }
+
+    #[test]
+    fn screen_projection_maps_the_window_to_clip_space() {
+        let project =
+            |m: &[[f32; 4]; 4], x: f32, y: f32| [m[0][0] * x + m[3][0], m[1][1] * y + m[3][1]];
+        let m = screen_projection(&PhysicalSize::new(200, 100));
+        assert_eq!(project(&m, 0.0, 0.0), [-1.0, 1.0]);
+        assert_eq!(project(&m, 200.0, 100.0), [1.0, -1.0]);
+        assert_eq!(project(&m, 100.0, 50.0), [0.0, 0.0]);
+        // Minimized windows have no size
+        let m = screen_projection(&PhysicalSize::new(0, 0));
+        assert!(m.iter().flatten().all(|value| value.is_finite()));
+    }
+
+    #[test]
+    fn skips_glyphs_while_the_atlas_is_full_and_clears_it_next_frame() {
+        let mut layout = layout(1.0);
+        while layout.atlas.allocate(64, 64).is_some() {}
+        assert!(layout.atlas.full);
+        // Less than 64 pixels are left below the last row, too little for a large glyph
+        let large = TextSection {
+            text: "A",
+            anchor: TextAnchor::Screen { x: 0.0, y: 0.0 },
+            size: 96.0,
+            color: WHITE,
+            align: TextAlign::TopLeft,
+        };
+        layout.queue_text(&large);
+        assert!(layout.vertices.is_empty());
+        // Rectangles use the solid block, they are still drawn
+        layout.queue_rect(0.0, 0.0, 10.0, 10.0, WHITE);
+        assert_eq!(layout.vertices.len(), 4);
+
+        layout.begin_frame(PhysicalSize::new(200, 100), 1.0, IDENTITY);
+        assert!(!layout.atlas.full);
+        layout.queue_text(&large);
+        assert_eq!(layout.vertices.len(), 4);
+        assert_eq!(layout.indices, [0, 1, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_glyphs_wider_than_the_atlas() {
+        let mut atlas = GlyphAtlas::new();
+        assert_eq!(atlas.allocate(ATLAS_SIZE + 1, 1), None);
+        assert!(atlas.full);
+    }
+}
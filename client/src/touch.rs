@@ -0,0 +1,391 @@
+use instant::{Duration, Instant};
+use std::collections::HashMap;
+use winit::event::TouchPhase;
+
+use super::text::TextRenderer;
+
+/// Distance in logical pixels between the joystick center and its edge, where it reaches full speed
+const JOYSTICK_RADIUS: f64 = 64.0;
+/// Touches released sooner than this without moving further than `TAP_DISTANCE` are taps
+const TAP_TIME: Duration = Duration::from_millis(250);
+const TAP_DISTANCE: f64 = 12.0;
+const BASE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.15];
+const KNOB_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.4];
+
+struct Touch {
+    position: (f64, f64),
+    time: Instant,
+    /// Set once the touch moved too far to still be a tap
+    moved: bool,
+    /// Where the finger is now
+    current: (f64, f64),
+}
+
+/// Two fingers moving apart or together to zoom
+struct Pinch {
+    ids: (u64, u64),
+    /// Distance between the fingers at the last move
+    distance: f64,
+}
+
+/// Gestures other than moving the joystick
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TouchGesture {
+    Tap,
+    /// Zoom factor since the last pinch gesture, above 1 when the fingers move apart
+    Pinch(f64),
+}
+
+/// Floating joystick, placed wherever the finger first touched the screen
+struct Joystick {
+    id: u64,
+    center: (f64, f64),
+    knob: (f64, f64),
+}
+
+/**
+ * Virtual joystick for touch screens.
+ *
+ * The first finger on the screen moves the player like a gamepad stick, relative to where it
+ * started. Quick taps with any finger trigger the interact action. A second finger that lands
+ * before the joystick was moved turns both fingers into a pinch to zoom.
+ */
+#[derive(Default)]
+pub struct TouchControls {
+    touches: HashMap<u64, Touch>,
+    joystick: Option<Joystick>,
+    pinch: Option<Pinch>,
+}
+
+impl TouchControls {
+    /**
+     * Updates the joystick with a touch at a position in logical pixels from the top-left corner of
+     * the window, returns the tap or pinch the touch was part of.
+     */
+    pub fn handle_touch(
+        &mut self,
+        id: u64,
+        phase: TouchPhase,
+        position: (f64, f64),
+    ) -> Option<TouchGesture> {
+        match phase {
+            TouchPhase::Started => {
+                self.touches.insert(
+                    id,
+                    Touch {
+                        position,
+                        time: Instant::now(),
+                        moved: false,
+                        current: position,
+                    },
+                );
+                match &self.joystick {
+                    None if self.pinch.is_none() => {
+                        self.joystick = Some(Joystick {
+                            id,
+                            center: position,
+                            knob: position,
+                        })
+                    }
+                    Some(joystick) if self.stick() == (0.0, 0.0) => {
+                        let other = joystick.id;
+                        self.joystick = None;
+                        self.start_pinch(other, id);
+                    }
+                    _ => {}
+                }
+                None
+            }
+            TouchPhase::Moved => {
+                if let Some(touch) = self.touches.get_mut(&id) {
+                    touch.moved |= distance(touch.position, position) > TAP_DISTANCE;
+                    touch.current = position;
+                }
+                if let Some(pinch) = self
+                    .pinch
+                    .as_mut()
+                    .filter(|pinch| pinch.ids.0 == id || pinch.ids.1 == id)
+                {
+                    let fingers = (
+                        self.touches.get(&pinch.ids.0),
+                        self.touches.get(&pinch.ids.1),
+                    );
+                    if let (Some(first), Some(second)) = fingers {
+                        let new_distance = distance(first.current, second.current);
+                        let factor = new_distance / pinch.distance;
+                        pinch.distance = new_distance;
+                        return Some(TouchGesture::Pinch(factor));
+                    }
+                }
+                if let Some(joystick) = self.joystick.as_mut().filter(|joystick| joystick.id == id)
+                {
+                    // Dragging past the edge pulls the joystick along, so turning around responds immediately
+                    let offset = (
+                        position.0 - joystick.center.0,
+                        position.1 - joystick.center.1,
+                    );
+                    let length = distance(joystick.center, position);
+                    if length > JOYSTICK_RADIUS {
+                        let excess = (length - JOYSTICK_RADIUS) / length;
+                        joystick.center.0 += offset.0 * excess;
+                        joystick.center.1 += offset.1 * excess;
+                    }
+                    joystick.knob = position;
+                }
+                None
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if self.joystick.as_ref().map(|joystick| joystick.id) == Some(id) {
+                    self.joystick = None;
+                }
+                if self
+                    .pinch
+                    .as_ref()
+                    .is_some_and(|pinch| pinch.ids.0 == id || pinch.ids.1 == id)
+                {
+                    self.pinch = None;
+                }
+                self.touches
+                    .remove(&id)
+                    .filter(|touch| {
+                        phase == TouchPhase::Ended
+                            && !touch.moved
+                            && touch.time.elapsed() < TAP_TIME
+                    })
+                    .map(|_touch| TouchGesture::Tap)
+            }
+        }
+    }
+
+    fn start_pinch(&mut self, first: u64, second: u64) {
+        let fingers = (self.touches.get(&first), self.touches.get(&second));
+        if let (Some(first_touch), Some(second_touch)) = fingers {
+            let start_distance = distance(first_touch.current, second_touch.current);
+            // Very close fingers would make the zoom jump around
+            if start_distance > TAP_DISTANCE {
+                self.pinch = Some(Pinch {
+                    ids: (first, second),
+                    distance: start_distance,
+                });
+                // Pinching fingers are never taps
+                for id in [first, second] {
+                    if let Some(touch) = self.touches.get_mut(&id) {
+                        touch.moved = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Joystick position, x right & y up with a length of at most 1
+    pub fn stick(&self) -> (f64, f64) {
+        match &self.joystick {
+            Some(joystick) if distance(joystick.center, joystick.knob) > TAP_DISTANCE => (
+                (joystick.knob.0 - joystick.center.0) / JOYSTICK_RADIUS,
+                (joystick.center.1 - joystick.knob.1) / JOYSTICK_RADIUS,
+            ),
+            _ => (0.0, 0.0),
+        }
+    }
+
+    /// Whether a finger is on the screen
+    pub fn is_active(&self) -> bool {
+        !self.touches.is_empty()
+    }
+
+    /// Lets go of all touches, e.g. when a menu opens
+    pub fn clear(&mut self) {
+        self.touches.clear();
+        self.joystick = None;
+        self.pinch = None;
+    }
+
+    /// Draws the joystick while it is held, `ui_scale` converts window to ui pixels
+    pub fn draw(&self, text_renderer: &mut TextRenderer, ui_scale: f64) {
+        if let Some(joystick) = &self.joystick {
+            let base = JOYSTICK_RADIUS * 2.0;
+            let knob = JOYSTICK_RADIUS * 0.75;
+            let mut square = |(x, y): (f64, f64), size: f64, color| {
+                text_renderer.queue_rect(
+                    ((x - size / 2.0) / ui_scale) as f32,
+                    ((y - size / 2.0) / ui_scale) as f32,
+                    (size / ui_scale) as f32,
+                    (size / ui_scale) as f32,
+                    color,
+                )
+            };
+            square(joystick.center, base, BASE_COLOR);
+            square(joystick.knob, knob, KNOB_COLOR);
+        }
+    }
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use web::TouchEvents;
+
+/**
+ * winit only reports pointer events on web, touches are read from the canvas directly and
+ * queued until the next update.
+ */
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{cell::RefCell, rc::Rc};
+    use wasm_bindgen::{prelude::Closure, JsCast};
+    use winit::{event::TouchPhase, platform::web::WindowExtWebSys, window::Window};
+
+    type Queue = Rc<RefCell<Vec<(u64, TouchPhase, (f64, f64))>>>;
+
+    pub struct TouchEvents {
+        queue: Queue,
+        /// Kept alive for as long as the listeners are registered
+        _listeners: Vec<Closure<dyn FnMut(web_sys::TouchEvent)>>,
+    }
+
+    impl TouchEvents {
+        pub fn new(window: &Window) -> Self {
+            let canvas = window.canvas();
+            let queue = Queue::default();
+            let mut listeners = Vec::new();
+            for (name, phase) in [
+                ("touchstart", TouchPhase::Started),
+                ("touchmove", TouchPhase::Moved),
+                ("touchend", TouchPhase::Ended),
+                ("touchcancel", TouchPhase::Cancelled),
+            ] {
+                let listener_queue = queue.clone();
+                let listener_canvas = canvas.clone();
+                let closure = Closure::wrap(Box::new(move |event: web_sys::TouchEvent| {
+                    let bounds = listener_canvas.get_bounding_client_rect();
+                    let touches = event.changed_touches();
+                    for index in 0..touches.length() {
+                        if let Some(touch) = touches.get(index) {
+                            // Touch positions are in css pixels, the same as logical pixels
+                            listener_queue.borrow_mut().push((
+                                touch.identifier() as u64,
+                                phase,
+                                (
+                                    touch.client_x() as f64 - bounds.left(),
+                                    touch.client_y() as f64 - bounds.top(),
+                                ),
+                            ));
+                        }
+                    }
+                })
+                    as Box<dyn FnMut(web_sys::TouchEvent)>);
+                canvas
+                    .add_event_listener_with_callback(name, closure.as_ref().unchecked_ref())
+                    .unwrap_or_else(|err| log::warn!("Failed to add {} listener: {:?}", name, err));
+                listeners.push(closure);
+            }
+            TouchEvents {
+                queue,
+                _listeners: listeners,
+            }
+        }
+
+        /// Touches since the last poll
+        pub fn poll(&mut self) -> Vec<(u64, TouchPhase, (f64, f64))> {
+            self.queue.borrow_mut().drain(..).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_first_finger_moves_the_joystick() {
+        let mut touch = TouchControls::default();
+        touch.handle_touch(1, TouchPhase::Started, (100.0, 100.0));
+        assert!(touch.is_active());
+        // Too small to count as a move yet
+        touch.handle_touch(1, TouchPhase::Moved, (105.0, 100.0));
+        assert_eq!(touch.stick(), (0.0, 0.0));
+        // Screen y points down, stick y up
+        touch.handle_touch(1, TouchPhase::Moved, (100.0, 100.0 - JOYSTICK_RADIUS / 2.0));
+        assert_eq!(touch.stick(), (0.0, 0.5));
+        assert_eq!(
+            touch.handle_touch(1, TouchPhase::Ended, (100.0, 68.0)),
+            None
+        );
+        assert_eq!(touch.stick(), (0.0, 0.0));
+        assert!(!touch.is_active());
+    }
+
+    #[test]
+    fn dragging_past_the_edge_pulls_the_joystick_along() {
+        let mut touch = TouchControls::default();
+        touch.handle_touch(1, TouchPhase::Started, (100.0, 100.0));
+        touch.handle_touch(1, TouchPhase::Moved, (100.0 + 3.0 * JOYSTICK_RADIUS, 100.0));
+        assert_eq!(touch.stick(), (1.0, 0.0));
+        // Turning around responds right away instead of after dragging back to the old center
+        touch.handle_touch(1, TouchPhase::Moved, (100.0 + JOYSTICK_RADIUS, 100.0));
+        assert_eq!(touch.stick(), (-1.0, 0.0));
+    }
+
+    #[test]
+    fn quick_touches_that_stay_in_place_are_taps() {
+        let mut touch = TouchControls::default();
+        touch.handle_touch(1, TouchPhase::Started, (10.0, 10.0));
+        assert_eq!(
+            touch.handle_touch(1, TouchPhase::Ended, (12.0, 10.0)),
+            Some(TouchGesture::Tap)
+        );
+        touch.handle_touch(2, TouchPhase::Started, (10.0, 10.0));
+        touch.handle_touch(2, TouchPhase::Moved, (40.0, 10.0));
+        touch.handle_touch(2, TouchPhase::Moved, (10.0, 10.0));
+        assert_eq!(touch.handle_touch(2, TouchPhase::Ended, (10.0, 10.0)), None);
+        touch.handle_touch(3, TouchPhase::Started, (10.0, 10.0));
+        assert_eq!(
+            touch.handle_touch(3, TouchPhase::Cancelled, (10.0, 10.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn two_fingers_pinch_to_zoom() {
+        let mut touch = TouchControls::default();
+        touch.handle_touch(1, TouchPhase::Started, (100.0, 100.0));
+        touch.handle_touch(2, TouchPhase::Started, (200.0, 100.0));
+        assert_eq!(touch.stick(), (0.0, 0.0));
+        assert_eq!(
+            touch.handle_touch(2, TouchPhase::Moved, (300.0, 100.0)),
+            Some(TouchGesture::Pinch(2.0))
+        );
+        assert_eq!(
+            touch.handle_touch(1, TouchPhase::Moved, (200.0, 100.0)),
+            Some(TouchGesture::Pinch(0.5))
+        );
+        // Neither finger is a tap or moves the player
+        assert_eq!(touch.stick(), (0.0, 0.0));
+        assert_eq!(
+            touch.handle_touch(1, TouchPhase::Ended, (200.0, 100.0)),
+            None
+        );
+        assert_eq!(
+            touch.handle_touch(2, TouchPhase::Ended, (300.0, 100.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn a_second_finger_on_a_moved_joystick_does_not_pinch() {
+        let mut touch = TouchControls::default();
+        touch.handle_touch(1, TouchPhase::Started, (100.0, 100.0));
+        touch.handle_touch(1, TouchPhase::Moved, (100.0 + JOYSTICK_RADIUS, 100.0));
+        touch.handle_touch(2, TouchPhase::Started, (300.0, 300.0));
+        assert_eq!(
+            touch.handle_touch(2, TouchPhase::Moved, (400.0, 300.0)),
+            None
+        );
+        assert_eq!(touch.stick(), (1.0, 0.0));
+        touch.clear();
+        assert!(!touch.is_active());
+        assert_eq!(touch.stick(), (0.0, 0.0));
+    }
+}
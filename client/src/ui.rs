@@ -0,0 +1,676 @@
+use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::hash::{Hash, Hasher};
+
+use winit::event::{ElementState, VirtualKeyCode};
+
+use super::text::{Color, TextAlign, TextAnchor, TextLayout, TextSection};
+
+/// Layout in logical pixels (multiplied by the ui scale)
+const FONT_SIZE: f32 = 16.0;
+const TITLE_SIZE: f32 = 22.0;
+const MARGIN: f32 = 12.0;
+const PADDING: f32 = 16.0;
+const SPACING: f32 = 8.0;
+const WIDGET_HEIGHT: f32 = 32.0;
+const FOCUS_BORDER: f32 = 2.0;
+const SLIDER_TRACK_HEIGHT: f32 = 6.0;
+const SLIDER_HANDLE_WIDTH: f32 = 12.0;
+/// Sliders move by this part of their range per arrow key press
+const SLIDER_KEY_STEP: f32 = 0.05;
+
+const PANEL_COLOR: Color = [0.08, 0.08, 0.12, 0.85];
+const WIDGET_COLOR: Color = [0.2, 0.2, 0.28, 1.0];
+const WIDGET_HOVER_COLOR: Color = [0.28, 0.28, 0.38, 1.0];
+const WIDGET_ACTIVE_COLOR: Color = [0.36, 0.36, 0.5, 1.0];
+const FOCUS_COLOR: Color = [1.0, 0.85, 0.3, 1.0];
+const TEXT_COLOR: Color = [1.0, 1.0, 1.0, 1.0];
+const MUTED_TEXT_COLOR: Color = [0.7, 0.7, 0.75, 1.0];
+
+type WidgetId = u64;
+
+/// Where a panel is placed in the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanelAnchor {
+    Center,
+    TopRight,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+impl Rect {
+    fn contains(&self, (x, y): (f32, f32)) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    fn grow(&self, amount: f32) -> Rect {
+        Rect {
+            x: self.x - amount,
+            y: self.y - amount,
+            width: self.width + 2.0 * amount,
+            height: self.height + 2.0 * amount,
+        }
+    }
+}
+
+/// Input received since the last frame
+#[derive(Debug, Default)]
+struct UiInput {
+    mouse_down: bool,
+    /// Presses & releases are remembered until the next frame so clicks shorter than a frame still count
+    mouse_pressed: bool,
+    mouse_released: bool,
+    characters: Vec<char>,
+    keys: Vec<VirtualKeyCode>,
+}
+
+enum DrawCommand {
+    Rect(Rect, Color),
+    Text {
+        text: String,
+        x: f32,
+        y: f32,
+        size: f32,
+        color: Color,
+    },
+}
+
+/**
+ * Immediate-mode UI: widgets are declared every frame through a `UiFrame`, which hit-tests them against
+ * the cursor, handles keyboard focus and queues them on the text layout. Only focus, the widget held
+ * by the mouse and input received between frames are kept here.
+ */
+#[derive(Debug, Default)]
+pub struct Ui {
+    input: UiInput,
+    focused: Option<WidgetId>,
+    /// Widget the mouse button was pressed on, it keeps the mouse until the button is released
+    active: Option<WidgetId>,
+    /// Panel heights of the last frame, used to place panels before their content is known
+    panel_heights: HashMap<WidgetId, f32>,
+    /// Set when the UI has to be built again to show the result of some input
+    dirty: bool,
+}
+
+impl Ui {
+    pub fn handle_mouse_input(&mut self, state: &ElementState) {
+        self.input.mouse_down = *state == ElementState::Pressed;
+        if self.input.mouse_down {
+            self.input.mouse_pressed = true;
+        } else {
+            self.input.mouse_released = true;
+        }
+        self.dirty = true;
+    }
+
+    /// The cursor position itself is read from the game state, hovering only needs a new frame
+    pub fn handle_cursor_moved(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn handle_key_press(&mut self, keycode: VirtualKeyCode) {
+        self.input.keys.push(keycode);
+        self.dirty = true;
+    }
+
+    pub fn handle_character(&mut self, character: char) {
+        self.input.characters.push(character);
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Drops focus & pending input, e.g. when the UI is hidden
+    pub fn reset(&mut self) {
+        self.input = UiInput::default();
+        self.focused = None;
+        self.active = None;
+        self.dirty = false;
+    }
+
+    /**
+     * Starts a new frame, `size` and `cursor` are given in logical pixels.
+     */
+    pub fn begin_frame<'a>(
+        &'a mut self,
+        layout: &'a mut TextLayout,
+        size: (f32, f32),
+        cursor: (f32, f32),
+    ) -> UiFrame<'a> {
+        let input = std::mem::take(&mut self.input);
+        self.input.mouse_down = input.mouse_down;
+        self.dirty = false;
+        let line_height = layout.measure(" ", FONT_SIZE).1;
+        UiFrame {
+            ui: self,
+            layout,
+            input,
+            size,
+            cursor,
+            line_height,
+            commands: Vec::new(),
+            focus_order: Vec::new(),
+            panel: None,
+        }
+    }
+}
+
+struct Panel {
+    id: WidgetId,
+    rect: Rect,
+    /// Top of the next widget
+    next_y: f32,
+    /// Index of the background command, its height is only known once the panel ends
+    background: usize,
+}
+
+/**
+ * A single UI frame. Widgets are laid out top to bottom inside panels and return what the user did
+ * with them, everything is drawn once the frame ends.
+ */
+pub struct UiFrame<'a> {
+    ui: &'a mut Ui,
+    layout: &'a mut TextLayout,
+    input: UiInput,
+    size: (f32, f32),
+    cursor: (f32, f32),
+    line_height: f32,
+    commands: Vec<DrawCommand>,
+    /// Focusable widgets in the order they were declared, used for keyboard navigation
+    focus_order: Vec<WidgetId>,
+    panel: Option<Panel>,
+}
+
+impl<'a> UiFrame<'a> {
+    /// Covers the whole window, e.g. to dim the world behind a menu
+    pub fn backdrop(&mut self, color: Color) {
+        let (width, height) = self.size;
+        self.commands.push(DrawCommand::Rect(
+            Rect {
+                x: 0.0,
+                y: 0.0,
+                width,
+                height,
+            },
+            color,
+        ));
+    }
+
+    /**
+     * Starts a panel, widgets declared until `end_panel` are stacked inside it.
+     */
+    pub fn begin_panel(&mut self, id: &str, anchor: PanelAnchor, width: f32, title: Option<&str>) {
+        if self.panel.is_some() {
+            panic!("Panel {} started inside another panel", id);
+        }
+        let id = widget_id(id);
+        let height = self.ui.panel_heights.get(&id).copied().unwrap_or(0.0);
+        let (window_width, window_height) = self.size;
+        let (x, y) = match anchor {
+            PanelAnchor::Center => ((window_width - width) / 2.0, (window_height - height) / 2.0),
+            PanelAnchor::TopRight => (window_width - width - MARGIN, MARGIN),
+        };
+        let rect = Rect {
+            x: x.round(),
+            y: y.max(MARGIN).round(),
+            width,
+            height,
+        };
+        self.commands.push(DrawCommand::Rect(rect, PANEL_COLOR));
+        self.panel = Some(Panel {
+            id,
+            rect,
+            next_y: rect.y + PADDING,
+            background: self.commands.len() - 1,
+        });
+        if let Some(title) = title {
+            let title_height = self.layout.measure(title, TITLE_SIZE).1;
+            let area = self.next_rect(title_height);
+            self.push_text(title, area.x, area.y, TITLE_SIZE, TEXT_COLOR);
+        }
+    }
+
+    pub fn end_panel(&mut self) {
+        let panel = self.panel.take().expect("No panel to end");
+        let height = panel.next_y - SPACING + PADDING - panel.rect.y;
+        if let DrawCommand::Rect(rect, _color) = &mut self.commands[panel.background] {
+            rect.height = height;
+        }
+        // Panels are placed using last frame's height, show them at the right place on the next frame
+        if self.ui.panel_heights.insert(panel.id, height) != Some(height) {
+            self.ui.dirty = true;
+        }
+    }
+
+    pub fn label(&mut self, text: &str) {
+        self.colored_label(text, TEXT_COLOR);
+    }
+
+    pub fn colored_label(&mut self, text: &str, color: Color) {
+        let height = self.layout.measure(text, FONT_SIZE).1;
+        let area = self.next_rect(height);
+        self.push_text(text, area.x, area.y, FONT_SIZE, color);
+    }
+
+    /// Returns true when the button was clicked or activated with Enter while focused
+    pub fn button(&mut self, id: &str, text: &str) -> bool {
+        let id = widget_id(id);
+        let area = self.next_rect(WIDGET_HEIGHT);
+        let (hovered, pressed) = self.interact(id, area);
+        let clicked = (hovered && self.input.mouse_released && self.ui.active == Some(id))
+            || (self.is_focused(id)
+                && self.key_pressed(&[VirtualKeyCode::Return, VirtualKeyCode::NumpadEnter]));
+        self.push_widget_background(id, area, hovered, pressed);
+        let (text_width, _height) = self.layout.measure(text, FONT_SIZE);
+        let y = self.centered_text_y(area);
+        self.push_text(
+            text,
+            area.x + (area.width - text_width) / 2.0,
+            y,
+            FONT_SIZE,
+            TEXT_COLOR,
+        );
+        if clicked {
+            self.ui.dirty = true;
+        }
+        clicked
+    }
+
+    /// Single line text input, returns true when Enter is pressed while it has focus
+    pub fn text_input(
+        &mut self,
+        id: &str,
+        label: &str,
+        value: &mut String,
+        max_length: usize,
+    ) -> bool {
+        self.input_field(id, label, value, max_length, false)
+    }
+
+    /// Text input that only shows asterisks
+    pub fn password_input(
+        &mut self,
+        id: &str,
+        label: &str,
+        value: &mut String,
+        max_length: usize,
+    ) -> bool {
+        self.input_field(id, label, value, max_length, true)
+    }
+
+    /**
+     * Horizontal slider between `min` and `max`, snapped to `step`. Dragging with the mouse or the
+     * left & right arrow keys change the value, returns true if it changed.
+     */
+    pub fn slider(
+        &mut self,
+        id: &str,
+        label: &str,
+        value: &mut f32,
+        min: f32,
+        max: f32,
+        step: f32,
+    ) -> bool {
+        self.colored_label(&format!("{}: {:.2}", label, value), MUTED_TEXT_COLOR);
+        let id = widget_id(id);
+        let area = self.next_rect(WIDGET_HEIGHT);
+        let (hovered, pressed) = self.interact(id, area);
+        let previous = *value;
+        if self.ui.active == Some(id) && self.input.mouse_down {
+            let ratio = ((self.cursor.0 - area.x) / area.width).clamp(0.0, 1.0);
+            *value = min + ratio * (max - min);
+        }
+        if self.is_focused(id) {
+            let key_step = (max - min) * SLIDER_KEY_STEP;
+            if self.key_pressed(&[VirtualKeyCode::Left]) {
+                *value -= key_step.max(step);
+            }
+            if self.key_pressed(&[VirtualKeyCode::Right]) {
+                *value += key_step.max(step);
+            }
+        }
+        if step > 0.0 {
+            *value = min + ((*value - min) / step).round() * step;
+        }
+        *value = value.clamp(min, max);
+
+        if self.is_focused(id) {
+            self.commands
+                .push(DrawCommand::Rect(area.grow(FOCUS_BORDER), FOCUS_COLOR));
+        }
+        self.commands.push(DrawCommand::Rect(area, PANEL_COLOR));
+        let track = Rect {
+            x: area.x,
+            y: area.y + (area.height - SLIDER_TRACK_HEIGHT) / 2.0,
+            width: area.width,
+            height: SLIDER_TRACK_HEIGHT,
+        };
+        self.commands.push(DrawCommand::Rect(track, WIDGET_COLOR));
+        let ratio = if max > min {
+            (*value - min) / (max - min)
+        } else {
+            0.0
+        };
+        let handle = Rect {
+            x: area.x + ratio * (area.width - SLIDER_HANDLE_WIDTH),
+            y: area.y,
+            width: SLIDER_HANDLE_WIDTH,
+            height: area.height,
+        };
+        let handle_color = if hovered || pressed {
+            FOCUS_COLOR
+        } else {
+            TEXT_COLOR
+        };
+        self.commands.push(DrawCommand::Rect(handle, handle_color));
+        let changed = *value != previous;
+        if changed {
+            self.ui.dirty = true;
+        }
+        changed
+    }
+
+    /**
+     * Handles focus changes from the keyboard & mouse and queues everything on the text layout.
+     */
+    pub fn end_frame(mut self) {
+        if self.panel.is_some() {
+            self.end_panel();
+        }
+        // Tab & the up/down arrows move the focus between widgets
+        let mut focus_step = 0;
+        for keycode in &self.input.keys {
+            match keycode {
+                VirtualKeyCode::Tab | VirtualKeyCode::Down => focus_step += 1,
+                VirtualKeyCode::Up => focus_step -= 1,
+                _ => {}
+            }
+        }
+        if focus_step != 0 && !self.focus_order.is_empty() {
+            let count = self.focus_order.len() as i32;
+            let current = self
+                .ui
+                .focused
+                .and_then(|focused| self.focus_order.iter().position(|id| *id == focused))
+                .map_or(if focus_step > 0 { -1 } else { count }, |index| {
+                    index as i32
+                });
+            let next = (current + focus_step).rem_euclid(count);
+            self.ui.focused = Some(self.focus_order[next as usize]);
+            self.ui.dirty = true;
+        }
+        // Clicking outside of any widget or hiding the focused widget drops the focus
+        if self.input.mouse_pressed && self.ui.active.is_none() {
+            self.ui.focused = None;
+        }
+        if let Some(focused) = self.ui.focused {
+            if !self.focus_order.contains(&focused) {
+                self.ui.focused = None;
+            }
+        }
+        if !self.input.mouse_down {
+            self.ui.active = None;
+        }
+
+        for command in &self.commands {
+            match command {
+                DrawCommand::Rect(rect, color) => {
+                    self.layout
+                        .queue_rect(rect.x, rect.y, rect.width, rect.height, *color)
+                }
+                DrawCommand::Text {
+                    text,
+                    x,
+                    y,
+                    size,
+                    color,
+                } => self.layout.queue_text(&TextSection {
+                    text,
+                    anchor: TextAnchor::Screen { x: *x, y: *y },
+                    size: *size,
+                    color: *color,
+                    align: TextAlign::TopLeft,
+                }),
+            }
+        }
+    }
+
+    fn input_field(
+        &mut self,
+        id: &str,
+        label: &str,
+        value: &mut String,
+        max_length: usize,
+        masked: bool,
+    ) -> bool {
+        self.colored_label(label, MUTED_TEXT_COLOR);
+        let id = widget_id(id);
+        let area = self.next_rect(WIDGET_HEIGHT);
+        let (hovered, pressed) = self.interact(id, area);
+        let focused = self.is_focused(id);
+        if focused {
+            for character in &self.input.characters {
+                match character {
+                    // Backspace
+                    '\u{8}' => {
+                        value.pop();
+                    }
+                    c if c.is_control() => {}
+                    c if value.chars().count() < max_length => value.push(*c),
+                    _ => {}
+                }
+            }
+            if !self.input.characters.is_empty() {
+                self.ui.dirty = true;
+            }
+        }
+        let submitted =
+            focused && self.key_pressed(&[VirtualKeyCode::Return, VirtualKeyCode::NumpadEnter]);
+
+        self.push_widget_background(id, area, hovered, pressed);
+        let shown: String = if masked {
+            "*".repeat(value.chars().count())
+        } else {
+            value.clone()
+        };
+        // Only show the end of the text if it does not fit
+        let (char_width, _height) = self.layout.measure(" ", FONT_SIZE);
+        let visible_chars = (((area.width - 2.0 * SPACING) / char_width) as usize).max(2) - 1;
+        let chars: Vec<char> = shown.chars().collect();
+        let start = chars.len().saturating_sub(visible_chars);
+        let mut visible: String = chars[start..].iter().collect();
+        if focused {
+            visible.push('_');
+        }
+        let y = self.centered_text_y(area);
+        self.push_text(&visible, area.x + SPACING, y, FONT_SIZE, TEXT_COLOR);
+        submitted
+    }
+
+    /**
+     * Registers a focusable widget, returns whether the cursor is over it and whether it is held down.
+     * Pressing the mouse on a widget makes it active & focused.
+     */
+    fn interact(&mut self, id: WidgetId, area: Rect) -> (bool, bool) {
+        self.focus_order.push(id);
+        let hovered = area.contains(self.cursor);
+        if hovered && self.input.mouse_pressed {
+            self.ui.active = Some(id);
+            self.ui.focused = Some(id);
+        }
+        (hovered, self.ui.active == Some(id) && self.input.mouse_down)
+    }
+
+    fn is_focused(&self, id: WidgetId) -> bool {
+        self.ui.focused == Some(id)
+    }
+
+    fn key_pressed(&self, keycodes: &[VirtualKeyCode]) -> bool {
+        self.input.keys.iter().any(|key| keycodes.contains(key))
+    }
+
+    /// Takes the next row of the given height in the current panel
+    fn next_rect(&mut self, height: f32) -> Rect {
+        let panel = self
+            .panel
+            .as_mut()
+            .expect("Widgets must be declared inside a panel");
+        let rect = Rect {
+            x: panel.rect.x + PADDING,
+            y: panel.next_y,
+            width: panel.rect.width - 2.0 * PADDING,
+            height,
+        };
+        panel.next_y += height + SPACING;
+        rect
+    }
+
+    fn centered_text_y(&self, area: Rect) -> f32 {
+        area.y + (area.height - self.line_height) / 2.0
+    }
+
+    fn push_widget_background(&mut self, id: WidgetId, area: Rect, hovered: bool, pressed: bool) {
+        if self.is_focused(id) {
+            self.commands
+                .push(DrawCommand::Rect(area.grow(FOCUS_BORDER), FOCUS_COLOR));
+        }
+        self.commands
+            .push(DrawCommand::Rect(area, widget_color(hovered, pressed)));
+    }
+
+    fn push_text(&mut self, text: &str, x: f32, y: f32, size: f32, color: Color) {
+        self.commands.push(DrawCommand::Text {
+            text: text.to_string(),
+            x,
+            y,
+            size,
+            color,
+        });
+    }
+}
+
+fn widget_color(hovered: bool, pressed: bool) -> Color {
+    if pressed {
+        WIDGET_ACTIVE_COLOR
+    } else if hovered {
+        WIDGET_HOVER_COLOR
+    } else {
+        WIDGET_COLOR
+    }
+}
+
+fn widget_id(id: &str) -> WidgetId {
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: (f32, f32) = (800.0, 600.0);
+    const PANEL_WIDTH: f32 = 200.0;
+    const BUTTONS: [&str; 3] = ["first", "second", "third"];
+    /// Inside the first button of the top-right panel, the second one starts 40 pixels lower
+    const FIRST_BUTTON: (f32, f32) = (WINDOW.0 - PANEL_WIDTH - MARGIN + PADDING + 4.0, 30.0);
+    const OUTSIDE: (f32, f32) = (10.0, 10.0);
+
+    /// Declares a panel with three buttons, returns which of them were clicked
+    fn frame(ui: &mut Ui, layout: &mut TextLayout, cursor: (f32, f32)) -> [bool; 3] {
+        let mut frame = ui.begin_frame(layout, WINDOW, cursor);
+        frame.begin_panel("panel", PanelAnchor::TopRight, PANEL_WIDTH, None);
+        let clicked = BUTTONS.map(|id| frame.button(id, id));
+        frame.end_frame();
+        clicked
+    }
+
+    fn focused(ui: &Ui) -> Option<&'static str> {
+        BUTTONS
+            .into_iter()
+            .find(|id| ui.focused == Some(widget_id(id)))
+    }
+
+    #[test]
+    fn rect_contains_its_top_left_edges_only() {
+        let rect = Rect {
+            x: 10.0,
+            y: 20.0,
+            width: 30.0,
+            height: 40.0,
+        };
+        assert!(rect.contains((10.0, 20.0)));
+        assert!(rect.contains((39.9, 59.9)));
+        assert!(!rect.contains((40.0, 30.0)));
+        assert!(!rect.contains((20.0, 60.0)));
+        assert!(!rect.contains((9.9, 30.0)));
+        assert!(rect.grow(1.0).contains((9.5, 19.5)));
+    }
+
+    #[test]
+    fn keyboard_focus_wraps_around() {
+        let mut ui = Ui::default();
+        let mut layout = TextLayout::new();
+        let mut press = |ui: &mut Ui, keycode| {
+            ui.handle_key_press(keycode);
+            frame(ui, &mut layout, OUTSIDE)
+        };
+        press(&mut ui, VirtualKeyCode::Up);
+        assert_eq!(focused(&ui), Some("third"));
+        press(&mut ui, VirtualKeyCode::Down);
+        assert_eq!(focused(&ui), Some("first"));
+        press(&mut ui, VirtualKeyCode::Up);
+        assert_eq!(focused(&ui), Some("third"));
+        press(&mut ui, VirtualKeyCode::Tab);
+        assert_eq!(focused(&ui), Some("first"));
+        press(&mut ui, VirtualKeyCode::Tab);
+        assert_eq!(focused(&ui), Some("second"));
+        assert_eq!(press(&mut ui, VirtualKeyCode::Return), [false, true, false]);
+    }
+
+    #[test]
+    fn clicks_buttons_pressed_and_released_inside() {
+        let mut ui = Ui::default();
+        let mut layout = TextLayout::new();
+        ui.handle_mouse_input(&ElementState::Pressed);
+        assert_eq!(frame(&mut ui, &mut layout, FIRST_BUTTON), [false; 3]);
+        assert_eq!(focused(&ui), Some("first"));
+        ui.handle_mouse_input(&ElementState::Released);
+        assert_eq!(
+            frame(&mut ui, &mut layout, FIRST_BUTTON),
+            [true, false, false]
+        );
+
+        // A press & release within one frame still counts
+        let second = (FIRST_BUTTON.0, FIRST_BUTTON.1 + WIDGET_HEIGHT + SPACING);
+        ui.handle_mouse_input(&ElementState::Pressed);
+        ui.handle_mouse_input(&ElementState::Released);
+        assert_eq!(frame(&mut ui, &mut layout, second), [false, true, false]);
+    }
+
+    #[test]
+    fn ignores_clicks_outside_of_buttons() {
+        let mut ui = Ui::default();
+        let mut layout = TextLayout::new();
+        // Released after moving off the button
+        ui.handle_mouse_input(&ElementState::Pressed);
+        frame(&mut ui, &mut layout, FIRST_BUTTON);
+        ui.handle_mouse_input(&ElementState::Released);
+        assert_eq!(frame(&mut ui, &mut layout, OUTSIDE), [false; 3]);
+        assert_eq!(focused(&ui), Some("first"));
+
+        // Pressed outside of every widget drops the focus, releasing on a button does not click it
+        ui.handle_mouse_input(&ElementState::Pressed);
+        frame(&mut ui, &mut layout, OUTSIDE);
+        assert_eq!(focused(&ui), None);
+        ui.handle_mouse_input(&ElementState::Released);
+        assert_eq!(frame(&mut ui, &mut layout, FIRST_BUTTON), [false; 3]);
+    }
+}
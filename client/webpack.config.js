@@ -16,7 +16,13 @@ module.exports = {
     port: 3000,
   },
   plugins: [
-    new CopyPlugin({ patterns: [path.resolve(__dirname, 'static')] }),
+    new CopyPlugin({
+      patterns: [
+        path.resolve(__dirname, 'static'),
+        // Downloaded by the client at startup instead of being compiled into the wasm binary
+        { from: path.resolve(__dirname, 'assets'), to: 'assets' },
+      ],
+    }),
     new WasmPackPlugin({ crateDirectory: __dirname }),
   ],
   module: {
@@ -0,0 +1,525 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand_core::{OsRng, RngCore};
+
+/// How long a session token stays valid after logging in
+const SESSION_DURATION: Duration = Duration::from_secs(60 * 60 * 24);
+/// Amount of random bytes in a session token (hex encoded, so the token string is twice as long)
+const TOKEN_BYTES: usize = 32;
+const MIN_NAME_LENGTH: usize = 3;
+const MAX_NAME_LENGTH: usize = 16;
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    InvalidName,
+    InvalidPassword,
+    NameTaken,
+    InvalidCredentials,
+    TooManyAccounts,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            AuthError::InvalidName => "Names must be 3 to 16 letters, digits or underscores",
+            AuthError::InvalidPassword => "Passwords must be at least 8 characters",
+            AuthError::NameTaken => "Name is already taken",
+            AuthError::InvalidCredentials => "Invalid name or password",
+            AuthError::TooManyAccounts => "No more accounts can be created",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Debug)]
+struct Account {
+    player_id: u16,
+    password_hash: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub name: String,
+    pub player_id: u16,
+    expires: Instant,
+}
+
+/**
+ * Keeps track of all accounts and the session tokens handed out to them.
+ *
+ * Accounts are persisted in a plain text file with one `name:player_id:password_hash` line per account.
+ * Password hashes are salted argon2 hashes in the PHC string format, so they never contain a `:`.
+ * Sessions only live in memory, restarting the server logs everybody out.
+ */
+#[derive(Debug)]
+pub struct Auth {
+    path: PathBuf,
+    accounts: Mutex<HashMap<String, Account>>,
+    sessions: Mutex<HashMap<String, Session>>,
+    /// Display names in use by connected players (lowercase), with their player id & connection count
+    display_names: Mutex<HashMap<String, (u16, usize)>>,
+}
+
+impl Auth {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut accounts = HashMap::new();
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.is_empty()) {
+                    let data: Vec<&str> = line.splitn(3, ':').collect();
+                    match (
+                        data.first(),
+                        data.get(1).map(|id| str::parse::<u16>(id)),
+                        data.get(2),
+                    ) {
+                        (Some(name), Some(Ok(player_id)), Some(password_hash)) => {
+                            accounts.insert(
+                                name.to_string(),
+                                Account {
+                                    player_id,
+                                    password_hash: password_hash.to_string(),
+                                },
+                            );
+                        }
+                        _ => log::warn!("Skipping malformed account line in {:?}", path),
+                    }
+                }
+            }
+            Err(err) => log::info!("No accounts loaded from {:?}: {:?}", path, err),
+        }
+        log::info!("Loaded {} accounts", accounts.len());
+        Auth {
+            path,
+            accounts: Mutex::new(accounts),
+            sessions: Mutex::new(HashMap::new()),
+            display_names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /**
+     * Creates a new account and logs it in, returns the new session token.
+     *
+     * Hashing is slow on purpose, it runs on a blocking thread without holding the account lock.
+     */
+    pub async fn register(
+        &self,
+        name: &str,
+        password: &str,
+    ) -> Result<(String, Session), AuthError> {
+        if !is_valid_name(name) {
+            return Err(AuthError::InvalidName);
+        }
+        if password.len() < MIN_PASSWORD_LENGTH {
+            return Err(AuthError::InvalidPassword);
+        }
+        if is_name_taken(&self.lock_accounts(), name) {
+            return Err(AuthError::NameTaken);
+        }
+        let password = password.to_string();
+        let password_hash = tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .unwrap_or_else(|err| panic!("Failed to hash password: {:?}", err))
+                .to_string()
+        })
+        .await
+        .unwrap_or_else(|err| panic!("Failed to join password hashing task: {:?}", err));
+        let mut accounts = self.lock_accounts();
+        // The name may have been registered by another connection while hashing
+        if is_name_taken(&accounts, name) {
+            return Err(AuthError::NameTaken);
+        }
+        let player_id = accounts
+            .values()
+            .map(|account| account.player_id)
+            .max()
+            .map_or(Some(0), |id| id.checked_add(1))
+            .ok_or(AuthError::TooManyAccounts)?;
+        // Append to the file so existing accounts are never rewritten
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}:{}:{}", name, player_id, password_hash))
+            .unwrap_or_else(|err| log::error!("Failed to persist account {}: {:?}", name, err));
+        accounts.insert(
+            name.to_string(),
+            Account {
+                player_id,
+                password_hash,
+            },
+        );
+        drop(accounts);
+        log::info!("Registered account {} with player id {}", name, player_id);
+        Ok(self.start_session(name, player_id))
+    }
+
+    /**
+     * Verifies the password of an existing account, returns a new session token if it matches.
+     *
+     * Like hashing, verifying runs on a blocking thread with a copy of the stored hash.
+     */
+    pub async fn login(&self, name: &str, password: &str) -> Result<(String, Session), AuthError> {
+        let (player_id, password_hash) = {
+            let accounts = self.lock_accounts();
+            let account = accounts.get(name).ok_or(AuthError::InvalidCredentials)?;
+            (account.player_id, account.password_hash.clone())
+        };
+        let password = password.to_string();
+        let verified = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&password_hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+        })
+        .await
+        .unwrap_or_else(|err| panic!("Failed to join password verification task: {:?}", err));
+        if !verified {
+            return Err(AuthError::InvalidCredentials);
+        }
+        Ok(self.start_session(name, player_id))
+    }
+
+    /**
+     * Returns the session belonging to the token if it exists and has not expired yet.
+     */
+    pub fn validate(&self, token: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().expect("Session store lock poisoned");
+        let now = Instant::now();
+        sessions.retain(|_token, session| session.expires > now);
+        sessions.get(token).cloned()
+    }
+
+    /**
+     * Reserves a display name for a connection of the given player, returns false if it is not available.
+     *
+     * Names are compared ignoring case. A name can only be used by one player at a time and never
+     * by anyone but the owner of the account with that name, so chat messages can not be faked.
+     * Connections of the same player share their names, each claim must be released again.
+     */
+    pub fn claim_display_name(&self, name: &str, player_id: u16) -> bool {
+        let key = name.to_lowercase();
+        let owned_by_other = self
+            .lock_accounts()
+            .iter()
+            .any(|(account, owner)| account.to_lowercase() == key && owner.player_id != player_id);
+        if owned_by_other {
+            return false;
+        }
+        let mut display_names = self
+            .display_names
+            .lock()
+            .expect("Display name lock poisoned");
+        match display_names.get_mut(&key) {
+            Some((owner, _connections)) if *owner != player_id => false,
+            Some((_owner, connections)) => {
+                *connections += 1;
+                true
+            }
+            None => {
+                display_names.insert(key, (player_id, 1));
+                true
+            }
+        }
+    }
+
+    /// Releases a display name claimed with `claim_display_name` once the connection closes
+    pub fn release_display_name(&self, name: &str) {
+        let key = name.to_lowercase();
+        let mut display_names = self
+            .display_names
+            .lock()
+            .expect("Display name lock poisoned");
+        if let Some((_owner, connections)) = display_names.get_mut(&key) {
+            *connections -= 1;
+            if *connections == 0 {
+                display_names.remove(&key);
+            }
+        }
+    }
+
+    fn lock_accounts(&self) -> MutexGuard<'_, HashMap<String, Account>> {
+        self.accounts.lock().expect("Account store lock poisoned")
+    }
+
+    fn start_session(&self, name: &str, player_id: u16) -> (String, Session) {
+        let token = generate_token();
+        let session = Session {
+            name: name.to_string(),
+            player_id,
+            expires: Instant::now() + SESSION_DURATION,
+        };
+        self.sessions
+            .lock()
+            .expect("Session store lock poisoned")
+            .insert(token.clone(), session.clone());
+        (token, session)
+    }
+}
+
+/// Account & display names: 3 to 16 ascii letters, digits or underscores
+pub fn is_valid_name(name: &str) -> bool {
+    (MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Names are taken ignoring case, like display names, so every account can use its own name to play
+fn is_name_taken(accounts: &HashMap<String, Account>, name: &str) -> bool {
+    accounts
+        .keys()
+        .any(|account| account.eq_ignore_ascii_case(name))
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/**
+ * Finds the session token of a websocket handshake request.
+ *
+ * Browsers cannot set headers on websocket requests so the token can be passed as a `token` query parameter,
+ * other clients can use an `Authorization: Bearer <token>` header instead.
+ */
+pub fn token_from_request(query: Option<&str>, authorization: Option<&str>) -> Option<String> {
+    query_param(query, "token")
+        .or_else(|| {
+            authorization
+                .and_then(|header| header.strip_prefix("Bearer "))
+                .map(str::to_string)
+        })
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// Finds the percent-decoded value of a query string parameter
+pub fn query_param(query: Option<&str>, key: &str) -> Option<String> {
+    query?.split('&').find_map(|param| {
+        param
+            .split_once('=')
+            .filter(|(param_key, _value)| percent_decode(param_key).as_deref() == Some(key))
+            .and_then(|(_key, value)| percent_decode(value))
+    })
+}
+
+/**
+ * Decodes `%XX` escapes & `+` (a space in query strings) the way browsers encode them.
+ * Malformed escapes are kept as they are, None is returned if the result is not valid utf-8.
+ */
+fn percent_decode(encoded: &str) -> Option<String> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (None, b'+') => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (None, byte) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Account store backed by a fresh file in the temp directory, removed again when dropped
+    struct TestAuth {
+        auth: Auth,
+        path: PathBuf,
+    }
+
+    impl TestAuth {
+        fn new(test: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "endless_game_accounts_{}_{}.txt",
+                test,
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            TestAuth {
+                auth: Auth::load(&path),
+                path,
+            }
+        }
+    }
+
+    impl Drop for TestAuth {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_accounts_can_log_in() {
+        let test = TestAuth::new("login");
+        let (token, session) = test.auth.register("alice", "correct horse").await.unwrap();
+        assert_eq!(session.name, "alice");
+        assert_eq!(session.player_id, 0);
+        assert_eq!(test.auth.validate(&token).unwrap().player_id, 0);
+
+        let (_token, session) = test.auth.register("bob", "battery staple").await.unwrap();
+        assert_eq!(session.player_id, 1);
+
+        let (token, session) = test.auth.login("alice", "correct horse").await.unwrap();
+        assert_eq!(session.player_id, 0);
+        assert_eq!(test.auth.validate(&token).unwrap().name, "alice");
+
+        // Accounts survive restarts, sessions do not
+        let reloaded = Auth::load(&test.path);
+        assert!(reloaded.validate(&token).is_none());
+        let (_token, session) = reloaded.login("bob", "battery staple").await.unwrap();
+        assert_eq!(session.player_id, 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_passwords_and_unknown_accounts() {
+        let test = TestAuth::new("wrong_password");
+        test.auth.register("alice", "correct horse").await.unwrap();
+        assert_eq!(
+            test.auth.login("alice", "wrong horse").await.unwrap_err(),
+            AuthError::InvalidCredentials
+        );
+        assert_eq!(
+            test.auth
+                .login("mallory", "correct horse")
+                .await
+                .unwrap_err(),
+            AuthError::InvalidCredentials
+        );
+        assert!(test.auth.validate("not a token").is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_and_taken_registrations() {
+        let test = TestAuth::new("register");
+        assert_eq!(
+            test.auth.register("a!", "correct horse").await.unwrap_err(),
+            AuthError::InvalidName
+        );
+        assert_eq!(
+            test.auth.register("alice", "short").await.unwrap_err(),
+            AuthError::InvalidPassword
+        );
+        test.auth.register("alice", "correct horse").await.unwrap();
+        assert_eq!(
+            test.auth
+                .register("alice", "other horse")
+                .await
+                .unwrap_err(),
+            AuthError::NameTaken
+        );
+        assert_eq!(
+            test.auth
+                .register("ALICE", "other horse")
+                .await
+                .unwrap_err(),
+            AuthError::NameTaken
+        );
+    }
+
+    #[test]
+    fn validates_names() {
+        assert!(is_valid_name("abc"));
+        assert!(is_valid_name("Player_42"));
+        assert!(is_valid_name("sixteen_chars_xx"));
+        assert!(!is_valid_name("ab"));
+        assert!(!is_valid_name("seventeen_chars_x"));
+        assert!(!is_valid_name("with space"));
+        assert!(!is_valid_name("colon:name"));
+        assert!(!is_valid_name("ünicode"));
+    }
+
+    #[test]
+    fn finds_tokens_in_query_or_header() {
+        assert_eq!(
+            token_from_request(Some("name=bob&token=abc"), None).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            token_from_request(None, Some("Bearer abc")).as_deref(),
+            Some("abc")
+        );
+        // The query parameter wins over the header
+        assert_eq!(
+            token_from_request(Some("token=abc"), Some("Bearer def")).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(token_from_request(Some("token="), None), None);
+        assert_eq!(
+            token_from_request(Some("name=bob"), Some("Basic abc")),
+            None
+        );
+        assert_eq!(token_from_request(None, None), None);
+    }
+
+    #[test]
+    fn decodes_query_params() {
+        let query = Some("token=a%2Fb&name=Big+Bob&emoji=%F0%9F%99%82&bad=100%&hex=%zz");
+        assert_eq!(query_param(query, "token").as_deref(), Some("a/b"));
+        assert_eq!(query_param(query, "name").as_deref(), Some("Big Bob"));
+        assert_eq!(query_param(query, "emoji").as_deref(), Some("\u{1f642}"));
+        assert_eq!(query_param(query, "bad").as_deref(), Some("100%"));
+        assert_eq!(query_param(query, "hex").as_deref(), Some("%zz"));
+        assert_eq!(
+            query_param(Some("na%6De=bob"), "name").as_deref(),
+            Some("bob")
+        );
+        assert_eq!(query_param(Some("name=%FF"), "name"), None);
+        assert_eq!(query_param(query, "missing"), None);
+        assert_eq!(
+            token_from_request(Some("token=%20abc%20"), None).as_deref(),
+            Some("abc")
+        );
+    }
+
+    #[tokio::test]
+    async fn display_names_are_unique() {
+        let test = TestAuth::new("display_names");
+        test.auth.register("alice", "correct horse").await.unwrap();
+        test.auth.register("bob", "battery staple").await.unwrap();
+        // Account names belong to their account, ignoring case
+        assert!(!test.auth.claim_display_name("Alice", 1));
+        assert!(test.auth.claim_display_name("ALICE", 0));
+        // Names in use can only be shared by connections of the same player
+        assert!(test.auth.claim_display_name("Knight", 0));
+        assert!(!test.auth.claim_display_name("knight", 1));
+        assert!(test.auth.claim_display_name("knight", 0));
+        test.auth.release_display_name("Knight");
+        assert!(!test.auth.claim_display_name("knight", 1));
+        test.auth.release_display_name("knight");
+        assert!(test.auth.claim_display_name("knight", 1));
+    }
+}
@@ -0,0 +1,74 @@
+//! Re-runs a replay recorded by the server (see `ENDLESS_REPLAY_FILE`) & prints the state of every
+//! player at a tick, the end of the recording by default:
+//!
+//! ```sh
+//! cargo run --bin replay -- replay.bin [tick]
+//! ```
+
+use std::{env, fs::File, process};
+
+use endless_game_shared::{
+    components::{Name, NetworkId, Transform},
+    movement::Movement,
+    replay::Replay,
+};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, tick) = match &args[..] {
+        [path] => (path, None),
+        [path, tick] => match tick.parse::<u64>() {
+            Ok(tick) => (path, Some(tick)),
+            Err(_err) => exit_with_usage(),
+        },
+        _ => exit_with_usage(),
+    };
+    let replay = File::open(path)
+        .map_err(|err| err.to_string())
+        .and_then(Replay::read)
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to load replay {}: {}", path, err);
+            process::exit(1);
+        });
+    println!(
+        "Recorded ticks {} to {} ({:.1} seconds)",
+        replay.start_tick(),
+        replay.end_tick(),
+        replay.duration_seconds()
+    );
+    let tick = tick.unwrap_or_else(|| replay.end_tick());
+    let playback = replay.run(tick);
+    match playback.desync {
+        Some(desync) => println!(
+            "Desync at tick {}: the replayed state differs from the recorded one",
+            desync
+        ),
+        None => println!("All recorded checksums up to tick {} match", tick),
+    }
+
+    let entities = &playback.entities;
+    println!("Players at tick {}:", tick);
+    for (player, id, transform) in entities.iter2::<NetworkId, Transform>() {
+        let name = entities.get::<Name>(player).map_or("", |name| &name.0);
+        let movement = entities
+            .get::<Movement>(player)
+            .copied()
+            .unwrap_or_default();
+        println!(
+            "{:>5} {:<16} x: {:.4} y: {:.4} velocity: {:.4},{:.4} input: {},{}",
+            id.0,
+            name,
+            transform.x,
+            transform.y,
+            movement.velocity.0,
+            movement.velocity.1,
+            movement.input.0,
+            movement.input.1
+        );
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("Usage: replay <replay file> [tick]");
+    process::exit(2);
+}
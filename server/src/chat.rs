@@ -0,0 +1,268 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Maximum amount of characters in a single chat message
+const MAX_MESSAGE_LENGTH: usize = 200;
+/// Maximum amount of messages a player can send within RATE_LIMIT_WINDOW
+const RATE_LIMIT_MESSAGES: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+/// Players within this distance (in squares) of the sender receive local messages
+pub const LOCAL_CHAT_RADIUS: f64 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatChannel {
+    /// Everybody on the server
+    Global,
+    /// Players within LOCAL_CHAT_RADIUS of the sender
+    Local,
+}
+
+impl ChatChannel {
+    pub fn parse(channel: &str) -> Option<Self> {
+        match channel {
+            "global" => Some(ChatChannel::Global),
+            "local" => Some(ChatChannel::Local),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatChannel::Global => "global",
+            ChatChannel::Local => "local",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChatError {
+    Empty,
+    TooLong,
+    RateLimited,
+    Filtered,
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ChatError::Empty => "Message is empty",
+            ChatError::TooLong => "Message is too long",
+            ChatError::RateLimited => "You are sending messages too quickly",
+            ChatError::Filtered => "Message was blocked",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/**
+ * Hook to inspect chat messages before they are broadcast.
+ * Returns the (possibly rewritten) message or None to block it entirely.
+ */
+pub trait ChatFilter: Send + Sync {
+    fn filter(&self, text: String) -> Option<String>;
+}
+
+/**
+ * Removes control characters (newlines, escape codes...) which could mess up the chat log of other players.
+ */
+pub struct ControlCharFilter;
+
+impl ChatFilter for ControlCharFilter {
+    fn filter(&self, text: String) -> Option<String> {
+        Some(text.chars().filter(|c| !c.is_control()).collect())
+    }
+}
+
+/**
+ * Masks every word found in a word list with asterisks, ignoring case.
+ */
+pub struct WordFilter {
+    words: HashSet<String>,
+}
+
+impl WordFilter {
+    /// Loads a word list with one word per line, a missing file results in an empty list
+    pub fn load(path: &Path) -> Self {
+        let words: HashSet<String> = fs::read_to_string(path)
+            .map(|contents| {
+                contents
+                    .lines()
+                    .map(|word| word.trim().to_lowercase())
+                    .filter(|word| !word.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(|err| {
+                log::info!("No chat word filter loaded from {:?}: {:?}", path, err);
+                HashSet::new()
+            });
+        WordFilter { words }
+    }
+}
+
+impl ChatFilter for WordFilter {
+    fn filter(&self, text: String) -> Option<String> {
+        if self.words.is_empty() {
+            return Some(text);
+        }
+        let filtered = text
+            .split(' ')
+            .map(|word| {
+                let bare = word
+                    .trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase();
+                if self.words.contains(&bare) {
+                    "*".repeat(word.chars().count())
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        Some(filtered)
+    }
+}
+
+/**
+ * Validates chat messages: length limits, rate limits & filter hooks.
+ *
+ * Rate limits are tracked per account and shared by all of its connections,
+ * so reconnecting or logging in twice does not allow sending more messages.
+ */
+pub struct ChatValidator {
+    filters: Vec<Box<dyn ChatFilter>>,
+    /// Send times of the messages within RATE_LIMIT_WINDOW, by player id
+    sent: Mutex<HashMap<u16, VecDeque<Instant>>>,
+}
+
+impl ChatValidator {
+    pub fn new(filters: Vec<Box<dyn ChatFilter>>) -> Self {
+        ChatValidator {
+            filters,
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn filters(&self) -> &[Box<dyn ChatFilter>] {
+        &self.filters
+    }
+
+    pub fn validate(&self, player_id: u16, text: &str) -> Result<String, ChatError> {
+        self.validate_at(player_id, text, Instant::now())
+    }
+
+    fn validate_at(&self, player_id: u16, text: &str, now: Instant) -> Result<String, ChatError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ChatError::Empty);
+        }
+        if text.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err(ChatError::TooLong);
+        }
+        let mut sent = self.sent.lock().expect("Chat rate limit lock poisoned");
+        // Forget players that have not sent anything recently
+        sent.retain(|_player_id, sent| {
+            while matches!(sent.front(), Some(sent) if now.duration_since(*sent) > RATE_LIMIT_WINDOW)
+            {
+                sent.pop_front();
+            }
+            !sent.is_empty()
+        });
+        let sent = sent
+            .entry(player_id)
+            .or_insert_with(|| VecDeque::with_capacity(RATE_LIMIT_MESSAGES));
+        if sent.len() >= RATE_LIMIT_MESSAGES {
+            return Err(ChatError::RateLimited);
+        }
+        let text = self
+            .filters
+            .iter()
+            .try_fold(text.to_string(), |text, filter| filter.filter(text))
+            .filter(|text| !text.trim().is_empty())
+            .ok_or(ChatError::Filtered)?;
+        sent.push_back(now);
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word_filter(words: &[&str]) -> WordFilter {
+        WordFilter {
+            words: words.iter().map(|word| word.to_string()).collect(),
+        }
+    }
+
+    fn validator() -> ChatValidator {
+        ChatValidator::new(vec![
+            Box::new(ControlCharFilter),
+            Box::new(word_filter(&["darn"])),
+        ])
+    }
+
+    #[test]
+    fn masks_filtered_words_ignoring_case_and_punctuation() {
+        let filter = word_filter(&["darn"]);
+        assert_eq!(
+            filter.filter("Darn it, darn!".to_string()).as_deref(),
+            Some("**** it, *****")
+        );
+        assert_eq!(
+            filter.filter("darning is fine".to_string()).as_deref(),
+            Some("darning is fine")
+        );
+        let empty = WordFilter::load(Path::new("missing_chat_filter_file.txt"));
+        assert_eq!(empty.filter("darn".to_string()).as_deref(), Some("darn"));
+    }
+
+    #[test]
+    fn validates_length_and_filters() {
+        let validator = validator();
+        assert_eq!(validator.validate(0, "  hello  "), Ok("hello".to_string()));
+        assert_eq!(validator.validate(0, "   "), Err(ChatError::Empty));
+        assert_eq!(
+            validator.validate(0, &"a".repeat(MAX_MESSAGE_LENGTH + 1)),
+            Err(ChatError::TooLong)
+        );
+        assert_eq!(
+            validator.validate(0, "hi\u{1b}[2Jthere"),
+            Ok("hi[2Jthere".to_string())
+        );
+        // Messages only made of control characters are blocked
+        assert_eq!(validator.validate(0, "a\u{7}").as_deref(), Ok("a"));
+        assert_eq!(
+            validator.validate(1, "\u{7}\u{7}"),
+            Err(ChatError::Filtered)
+        );
+    }
+
+    #[test]
+    fn rate_limits_each_player_across_connections() {
+        let validator = validator();
+        let start = Instant::now();
+        for _ in 0..RATE_LIMIT_MESSAGES {
+            assert!(validator.validate_at(0, "hello", start).is_ok());
+        }
+        assert_eq!(
+            validator.validate_at(0, "hello", start),
+            Err(ChatError::RateLimited)
+        );
+        // Other players are not affected
+        assert!(validator.validate_at(1, "hello", start).is_ok());
+        // Rejected messages do not count towards the limit
+        let later = start + RATE_LIMIT_WINDOW + Duration::from_millis(1);
+        for _ in 0..RATE_LIMIT_MESSAGES {
+            assert!(validator.validate_at(0, "hello", later).is_ok());
+        }
+        assert_eq!(
+            validator.validate_at(0, "hello", later),
+            Err(ChatError::RateLimited)
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::{env, path::PathBuf};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:3001";
+const DEFAULT_ACCOUNTS_FILE: &str = "accounts.txt";
+const DEFAULT_CHAT_FILTER_FILE: &str = "chat_filter.txt";
+
+#[derive(Debug)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/**
+ * Server configuration, read from environment variables (like the RUST_LOG level):
+ *
+ * - ENDLESS_ADDRESS: address the listener binds to (default 127.0.0.1:3001)
+ * - ENDLESS_ACCOUNTS_FILE: file the account store is persisted in (default accounts.txt)
+ * - ENDLESS_CHAT_FILTER_FILE: words masked in chat, one per line (default chat_filter.txt)
+ * - ENDLESS_TLS_CERT & ENDLESS_TLS_KEY: PEM certificate chain & private key, serves wss:// when both are set
+ * - ENDLESS_REPLAY_FILE: records every player input to this file when set, see the `replay` binary
+ */
+#[derive(Debug)]
+pub struct Config {
+    pub address: String,
+    pub accounts_file: PathBuf,
+    pub chat_filter_file: PathBuf,
+    pub tls: Option<TlsConfig>,
+    pub replay_file: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let tls = match (
+            env::var_os("ENDLESS_TLS_CERT"),
+            env::var_os("ENDLESS_TLS_KEY"),
+        ) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                cert: cert.into(),
+                key: key.into(),
+            }),
+            (None, None) => None,
+            _ => panic!("ENDLESS_TLS_CERT and ENDLESS_TLS_KEY must be set together"),
+        };
+        Config {
+            address: env::var("ENDLESS_ADDRESS").unwrap_or_else(|_err| DEFAULT_ADDRESS.into()),
+            accounts_file: env::var_os("ENDLESS_ACCOUNTS_FILE")
+                .map_or_else(|| DEFAULT_ACCOUNTS_FILE.into(), PathBuf::from),
+            chat_filter_file: env::var_os("ENDLESS_CHAT_FILTER_FILE")
+                .map_or_else(|| DEFAULT_CHAT_FILTER_FILE.into(), PathBuf::from),
+            tls,
+            replay_file: env::var_os("ENDLESS_REPLAY_FILE").map(PathBuf::from),
+        }
+    }
+}
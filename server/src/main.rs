@@ -1,87 +1,298 @@
-use std::{collections::HashMap, time::Duration};
+mod auth;
+mod chat;
+mod config;
+mod tls;
 
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use auth::Auth;
+use chat::{ChatChannel, ChatFilter, ChatValidator, ControlCharFilter, WordFilter};
+use config::Config;
+use endless_game_shared::{
+    components::{Name, NetworkId, Transform},
+    ecs::Entities,
+    movement::Movement,
+    replay::{InputEvent, Recorder},
+    simulation::{self, TICKS_PER_SECOND, TICK_DURATION},
+    world::World,
+};
 use futures_util::{SinkExt, StreamExt};
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::TcpListener,
     sync::{broadcast, mpsc},
-    time::sleep,
+    time::{self, sleep, MissedTickBehavior},
 };
-use tokio_tungstenite::tungstenite::{
-    self,
-    protocol::{frame::coding::CloseCode, CloseFrame},
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        handshake::server::{Request, Response},
+        protocol::{frame::coding::CloseCode, CloseFrame},
+    },
+    WebSocketStream,
 };
 
+/// Players & world objects, players are the entities with a `NetworkId`
 #[derive(Debug)]
-struct PlayerPosition {
-    x: f64,
-    y: f64,
+struct GameState {
+    entities: Entities,
+    /// Simulation ticks run so far
+    tick: u64,
+    /// Records every input applied to the entities, if a replay file is configured
+    recorder: Option<Recorder<BufWriter<File>>>,
+    /// Client & server tick of the last input of each player, see `client_tick`
+    input_ticks: HashMap<u16, (u64, u64)>,
+    /// Open game connections of each player, an account can be connected more than once
+    connections: HashMap<u16, usize>,
 }
 
-#[derive(Debug)]
-struct Player {
-    position: PlayerPosition,
+impl GameState {
+    fn new(replay_file: Option<&Path>) -> Self {
+        let mut entities = Entities::default();
+        World::default().spawn_objects(&mut entities);
+        let recorder = replay_file.map(|path| {
+            log::info!("Recording replay to {:?}", path);
+            File::create(path)
+                .and_then(|file| Recorder::new(BufWriter::new(file), 0, &entities))
+                .unwrap_or_else(|err| panic!("Failed to create replay file {:?}: {}", path, err))
+        });
+        GameState {
+            entities,
+            tick: 0,
+            recorder,
+            input_ticks: HashMap::new(),
+            connections: HashMap::new(),
+        }
+    }
+
+    /// Spawns the player of a new connection, replacing the player if the account is already connected
+    fn join(&mut self, player_id: u16, name: String) {
+        *self.connections.entry(player_id).or_default() += 1;
+        // A new connection counts its ticks from scratch
+        self.input_ticks.remove(&player_id);
+        self.apply(InputEvent::Join { player_id, name });
+    }
+
+    /// Despawns the player once the last connection of its account closed
+    fn leave(&mut self, player_id: u16) {
+        match self.connections.get_mut(&player_id) {
+            Some(connections) if *connections > 1 => *connections -= 1,
+            _ => {
+                self.connections.remove(&player_id);
+                self.input_ticks.remove(&player_id);
+                self.apply(InputEvent::Leave { player_id });
+            }
+        }
+    }
+
+    /**
+     * Applies the input a client started predicting with at its own `client_tick`. Both count
+     * ticks at the same rate, so every later server tick maps to a tick of that client.
+     */
+    fn apply_client_input(&mut self, player_id: u16, client_tick: u64, input: (f64, f64)) {
+        self.input_ticks.insert(player_id, (client_tick, self.tick));
+        self.apply(InputEvent::Move { player_id, input });
+    }
+
+    /// The client tick the current state matches for the player, None until it sent input
+    fn client_tick(&self, player_id: u16) -> Option<u64> {
+        self.input_ticks
+            .get(&player_id)
+            .map(|(client_tick, server_tick)| client_tick + (self.tick - server_tick))
+    }
+
+    /// Applies the input to the entities & records it
+    fn apply(&mut self, event: InputEvent) {
+        if let Some(recorder) = &mut self.recorder {
+            let result = recorder.record(self.tick, &event);
+            self.stop_recording_on_error(result);
+        }
+        event.apply(&mut self.entities);
+    }
+
+    /// Runs one simulation tick, the state is recorded once per second so replays can check theirs
+    fn step(&mut self) {
+        if self.tick.is_multiple_of(TICKS_PER_SECOND as u64) {
+            if let Some(recorder) = &mut self.recorder {
+                // Flushed as well, the server is usually stopped without a chance to do so
+                let result = recorder
+                    .record_checksum(self.tick, &self.entities)
+                    .and_then(|()| recorder.flush());
+                self.stop_recording_on_error(result);
+            }
+        }
+        simulation::step(&mut self.entities);
+        self.tick += 1;
+    }
+
+    fn stop_recording_on_error(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            log::error!("Failed to write replay, recording stopped: {}", err);
+            self.recorder = None;
+        }
+    }
 }
 
 #[derive(Debug)]
-struct GameState<'a> {
-    players: &'a mut HashMap<u16, Player>,
+enum GameEvent {
+    Join {
+        player_id: u16,
+        name: String,
+    },
+    Move {
+        player_id: u16,
+        /// Client tick the input was first predicted with
+        tick: u64,
+        input: (f64, f64),
+    },
+    Chat {
+        player_id: u16,
+        name: String,
+        channel: ChatChannel,
+        text: String,
+    },
+    Leave {
+        player_id: u16,
+    },
 }
 
-#[derive(Debug)]
-struct GameEvent {
-    player_id: u16,
-    move_direction: String,
+/// Messages sent by game clients: `move|tick|x,y` or `chat|channel|text`
+#[derive(Debug, PartialEq)]
+enum ClientMessage<'a> {
+    /// Client tick & input direction, see `endless_game_shared::movement::clamp_input`
+    Move(u64, f64, f64),
+    Chat(ChatChannel, &'a str),
+}
+
+impl<'a> ClientMessage<'a> {
+    fn parse(txt: &'a str) -> Option<Self> {
+        let data: Vec<&str> = txt.splitn(3, '|').collect();
+        match data[..] {
+            ["move", tick, input] => {
+                let tick = tick.parse().ok()?;
+                let (x, y) = input.split_once(',')?;
+                // Rust parses "NaN" & "inf" as well, those are invalid input
+                let (x, y): (f64, f64) = (x.parse().ok()?, y.parse().ok()?);
+                (x.is_finite() && y.is_finite()).then_some(ClientMessage::Move(tick, x, y))
+            }
+            ["chat", channel, text] => {
+                ChatChannel::parse(channel).map(|channel| ClientMessage::Chat(channel, text))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Downstream {
+    /// Players that should receive the message, None sends it to everybody
+    recipients: Option<Vec<u16>>,
+    message: String,
+}
+
+impl Downstream {
+    fn is_for(&self, player_id: u16) -> bool {
+        self.recipients
+            .as_ref()
+            .is_none_or(|recipients| recipients.contains(&player_id))
+    }
 }
 
 const UPDATES_PER_SECOND: u8 = 30;
+/// Simulation ticks run before every state update, the same fixed ticks the clients predict with
+const TICKS_PER_UPDATE: u32 = TICKS_PER_SECOND / UPDATES_PER_SECOND as u32;
+/// Websocket path used to register & log in, all other paths are game connections
+const LOGIN_PATH: &str = "/login";
+/// Close code sent when a game connection does not provide a session token
+const CLOSE_MISSING_TOKEN: u16 = 4000;
+/// Close code sent when a game connection provides an unknown or expired session token
+const CLOSE_INVALID_TOKEN: u16 = 4001;
+/// Close code sent when a game connection asks for a display name that is not allowed
+const CLOSE_INVALID_NAME: u16 = 4002;
+/// Close code sent when a game connection asks for a display name used by another player
+const CLOSE_NAME_TAKEN: u16 = 4003;
+
+/// Information read from the websocket handshake request
+#[derive(Debug, Default)]
+struct Handshake {
+    path: String,
+    token: Option<String>,
+    /// Name shown to other players, the account name is used if none is given
+    display_name: Option<String>,
+}
 
 #[tokio::main]
 async fn main() {
     // Initialise logging
     env_logger::init();
+    let config = Config::from_env();
 
     // Used to send update pings to clients
-    let (downstream_tx, _rx) = broadcast::channel::<String>(1024);
+    let (downstream_tx, _rx) = broadcast::channel::<Downstream>(1024);
     // Used to send update events to central thread
     let (upsteam_tx, mut upstream_rx) = mpsc::channel::<GameEvent>(512);
 
     // Central thread maintains active game state in memory
     let timer_tx = downstream_tx.clone();
+    let replay_file = config.replay_file.clone();
     tokio::spawn(async move {
         sleep(Duration::from_millis(1000)).await;
-        let game_state = GameState {
-            players: &mut HashMap::new(),
-        };
-        let mut count: u128 = 0;
+        // Players are moved here with the same collision rules as on the client, input can not walk through obstacles
+        let mut game_state = GameState::new(replay_file.as_deref());
+        // Created once so events in between do not delay ticks. Late ticks run right away, the
+        // server has to simulate as many ticks as the clients that predict with the same rate.
+        let mut ticks = time::interval(TICK_DURATION);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
         log::info!("Starting timer...");
         loop {
             tokio::select! {
-                _ = sleep(Duration::from_millis((1000.0 / (UPDATES_PER_SECOND as f32)) as u64)) => {
-                    log::debug!("Sending ping: {}", count);
-                    timer_tx
-                        .send(format!("{}={:?}", count, game_state))
-                        .unwrap_or_else(|err| panic!("Failed to send message count: {} {:?}", count, err));
-                    count += 1;
+                _ = ticks.tick() => {
+                    game_state.step();
+                    if game_state.tick.is_multiple_of(TICKS_PER_UPDATE as u64) {
+                        log::debug!("Sending state of tick {}", game_state.tick);
+                        // Sending only fails when nobody is connected
+                        let _ = timer_tx.send(Downstream { recipients: None, message: state_message(&game_state) });
+                    }
                 }
-                Some(GameEvent { player_id, move_direction }) = upstream_rx.recv() => {
-                    if let Some(player) = game_state.players.get(&player_id) {
-                        let mut x = player.position.x;
-                        let mut y = player.position.y;
-                        match &*move_direction {
-                            "left" => x -= 0.1,
-                            "right" => x += 0.1,
-                            "down" => y -= 0.1,
-                            "up" => y += 0.1,
-                            _ => {},
+                Some(event) = upstream_rx.recv() => {
+                    match event {
+                        GameEvent::Join { player_id, name } => game_state.join(player_id, name),
+                        GameEvent::Move { player_id, tick, input } => game_state.apply_client_input(player_id, tick, input),
+                        GameEvent::Chat { player_id, name, channel, text } => {
+                            let recipients = match channel {
+                                ChatChannel::Global => None,
+                                ChatChannel::Local => NetworkId(player_id)
+                                    .find(&game_state.entities)
+                                    .and_then(|sender| game_state.entities.get::<Transform>(sender))
+                                    .map(|sender| {
+                                        game_state
+                                            .entities
+                                            .iter2::<NetworkId, Transform>()
+                                            .filter(|(_entity, _id, player)| {
+                                                let dx = player.x - sender.x;
+                                                let dy = player.y - sender.y;
+                                                (dx * dx + dy * dy).sqrt() <= chat::LOCAL_CHAT_RADIUS
+                                            })
+                                            .map(|(_entity, id, _player)| id.0)
+                                            .collect()
+                                    }),
+                            };
+                            let message = format!("chat|{}|{}|{}", channel.as_str(), name, text);
+                            // Sending only fails when nobody is connected
+                            let _ = timer_tx.send(Downstream { recipients, message });
                         }
-                        let new_player_position = PlayerPosition { x, y };
-                        game_state.players.insert(player_id, Player { position: new_player_position });
-                    } else {
-                        let new_player_position = PlayerPosition { x: 0.0, y: 0.0 };
-                        game_state.players.insert(player_id, Player { position: new_player_position });
-                        // game_state.players.insert(player, Player { position: PlayerPosition { x: 0.0, y: 0.0 } });
+                        GameEvent::Leave { player_id } => game_state.leave(player_id),
                     }
-                    log::info!("New game state: {:?}", game_state);
+                    // Too much to log on every event, record a replay instead (see `ENDLESS_REPLAY_FILE`)
+                    log::debug!("New game state: {:?}", game_state.entities);
                 }
             }
         }
@@ -89,50 +300,372 @@ async fn main() {
 
     log::info!("Setting up tcp listener...");
 
-    let server = TcpListener::bind("127.0.0.1:3001")
+    let server = TcpListener::bind(&config.address)
         .await
         .unwrap_or_else(|err| panic!("Failed to bind tcp listener: {:?}", err));
+    let tls_acceptor = config.tls.as_ref().map(|tls| {
+        log::info!("Serving wss:// with certificate {:?}", tls.cert);
+        tls::load_acceptor(&tls.cert, &tls.key)
+    });
+    log::info!("Listening on {}", config.address);
+
+    let auth = Arc::new(Auth::load(&config.accounts_file));
+    let chat_validator = Arc::new(ChatValidator::new(vec![
+        Box::new(ControlCharFilter),
+        Box::new(WordFilter::load(&config.chat_filter_file)),
+    ]));
 
     while let Ok((stream, addr)) = server.accept().await {
-        let mut rx = downstream_tx.clone().subscribe();
+        let rx = downstream_tx.subscribe();
         let tx = upsteam_tx.clone();
+        let auth = auth.clone();
+        let chat_validator = chat_validator.clone();
+        let tls_acceptor = tls_acceptor.clone();
         tokio::spawn(async move {
-            let mut websocket = tokio_tungstenite::accept_async(stream)
-                .await
-                .unwrap_or_else(|err| panic!("Failled to accept websocket: {:?}", err));
-            log::info!("Started connection {:?}", addr);
-            loop {
-                tokio::select! {
-                    Some(Ok(msg)) = websocket.next() => {
-                        if let Ok(txt) = msg.to_text() {
-                            let txt_string = txt.to_string();
-                            if txt.is_empty() {
-                                websocket.close(Some(CloseFrame {
-                                    code: CloseCode::Normal,
-                                    reason: "Client initiated disconnect".into()
-                                }))
-                                .await
-                                .unwrap_or_else(|err| log::info!("Disconnected {:?} {:?}", addr, err));
-                                break;
-                            } else {
-                                let data: Vec<&str> = txt_string.split('|').collect();
-                                if let Ok(player_id) = str::parse::<u16>(data[0]) {
-                                    tx.send(GameEvent { player_id, move_direction: data[1].to_string()})
-                                    .await
-                                    .unwrap_or_else(|err| panic!("Failed to send gae event {:?}", err));
+            match tls_acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        handle_connection(stream, addr, auth, chat_validator, tx, rx).await
+                    }
+                    Err(err) => log::warn!("Failed TLS handshake {:?}: {:?}", addr, err),
+                },
+                None => handle_connection(stream, addr, auth, chat_validator, tx, rx).await,
+            }
+        });
+    }
+}
+
+/**
+ * Accepts the websocket of a plain or TLS stream and routes it to the login or game handler.
+ */
+async fn handle_connection<S>(
+    stream: S,
+    addr: SocketAddr,
+    auth: Arc<Auth>,
+    chat_validator: Arc<ChatValidator>,
+    tx: mpsc::Sender<GameEvent>,
+    rx: broadcast::Receiver<Downstream>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Read the path, token & name of the handshake request before accepting the websocket
+    let mut handshake = Handshake::default();
+    // The callback signature is dictated by tungstenite, we never return its large error variant
+    #[allow(clippy::result_large_err)]
+    let read_request = |request: &Request, response: Response| {
+        handshake.path = request.uri().path().to_string();
+        handshake.token = auth::token_from_request(
+            request.uri().query(),
+            request
+                .headers()
+                .get("Authorization")
+                .and_then(|header| header.to_str().ok()),
+        );
+        handshake.display_name = auth::query_param(request.uri().query(), "name");
+        Ok(response)
+    };
+    let websocket = tokio_tungstenite::accept_hdr_async(stream, read_request).await;
+    match websocket {
+        Ok(websocket) if handshake.path == LOGIN_PATH => handle_login(websocket, addr, &auth).await,
+        Ok(websocket) => {
+            handle_game(websocket, addr, handshake, &auth, &chat_validator, tx, rx).await
+        }
+        Err(err) => log::warn!("Failed to accept websocket {:?}: {:?}", addr, err),
+    }
+}
+
+/**
+ * Login connections accept `register|name|password` and `login|name|password` messages.
+ * Each message is answered with either `token|session_token|player_id` or `error|reason`.
+ */
+async fn handle_login<S>(mut websocket: WebSocketStream<S>, addr: SocketAddr, auth: &Auth)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    log::info!("Started login connection {:?}", addr);
+    while let Some(Ok(msg)) = websocket.next().await {
+        let txt = match msg.to_text() {
+            Ok(txt) if !txt.is_empty() => txt,
+            _ => break,
+        };
+        let data: Vec<&str> = txt.splitn(3, '|').collect();
+        let result = match data[..] {
+            ["register", name, password] => auth.register(name, password).await,
+            ["login", name, password] => auth.login(name, password).await,
+            _ => {
+                log::info!("Received invalid login msg from {:?}", addr);
+                continue;
+            }
+        };
+        let reply = match result {
+            Ok((token, session)) => {
+                log::info!("Logged in {} from {:?}", session.name, addr);
+                format!("token|{}|{}", token, session.player_id)
+            }
+            Err(err) => format!("error|{}", err),
+        };
+        if let Err(err) = websocket.send(tungstenite::Message::Text(reply)).await {
+            log::warn!("Failed to send login reply: {:?}", err);
+            break;
+        }
+    }
+    log::info!("Closed login connection {:?}", addr);
+}
+
+async fn handle_game<S>(
+    mut websocket: WebSocketStream<S>,
+    addr: SocketAddr,
+    handshake: Handshake,
+    auth: &Auth,
+    chat_validator: &ChatValidator,
+    tx: mpsc::Sender<GameEvent>,
+    mut rx: broadcast::Receiver<Downstream>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let session = handshake.token.as_deref().map(|token| auth.validate(token));
+    let display_name = match &session {
+        Some(Some(session)) => handshake
+            .display_name
+            .clone()
+            .unwrap_or_else(|| session.name.clone()),
+        _ => String::new(),
+    };
+    let session = match session {
+        Some(Some(session)) if is_allowed_display_name(&display_name, chat_validator.filters()) => {
+            session
+        }
+        rejection => {
+            let (code, reason) = match rejection {
+                None => (CLOSE_MISSING_TOKEN, "Missing session token"),
+                Some(None) => (CLOSE_INVALID_TOKEN, "Invalid or expired session token"),
+                Some(Some(_session)) => (CLOSE_INVALID_NAME, "Invalid display name"),
+            };
+            reject(&mut websocket, addr, code, reason).await;
+            return;
+        }
+    };
+    let player_id = session.player_id;
+    if !auth.claim_display_name(&display_name, player_id) {
+        reject(
+            &mut websocket,
+            addr,
+            CLOSE_NAME_TAKEN,
+            "Display name is already taken",
+        )
+        .await;
+        return;
+    }
+    log::info!(
+        "Started connection {:?} for {} as {}",
+        addr,
+        session.name,
+        display_name
+    );
+    tx.send(GameEvent::Join {
+        player_id,
+        name: display_name.clone(),
+    })
+    .await
+    .unwrap_or_else(|err| panic!("Failed to send game event {:?}", err));
+    loop {
+        tokio::select! {
+            msg = websocket.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                };
+                if let Ok(txt) = msg.to_text() {
+                    if txt.is_empty() {
+                        websocket.close(Some(CloseFrame {
+                            code: CloseCode::Normal,
+                            reason: "Client initiated disconnect".into()
+                        }))
+                        .await
+                        .unwrap_or_else(|err| log::info!("Disconnected {:?} {:?}", addr, err));
+                        break;
+                    } else {
+                        // The player id comes from the session, clients can only act as their own player
+                        let event = match ClientMessage::parse(txt) {
+                            Some(ClientMessage::Move(tick, x, y)) => Some(GameEvent::Move { player_id, tick, input: (x, y) }),
+                            Some(ClientMessage::Chat(channel, text)) => match chat_validator.validate(player_id, text) {
+                                Ok(text) => Some(GameEvent::Chat { player_id, name: display_name.clone(), channel, text }),
+                                Err(err) => {
+                                    websocket
+                                        .send(tungstenite::Message::Text(format!("chat_error|{}", err)))
+                                        .await
+                                        .unwrap_or_else(|err| log::warn!("Failed to send message: {:?}", err));
+                                    None
                                 }
+                            },
+                            None => {
+                                log::info!("Received invalid msg from {:?}", addr);
+                                None
                             }
+                        };
+                        if let Some(event) = event {
+                            tx.send(event)
+                                .await
+                                .unwrap_or_else(|err| panic!("Failed to send game event {:?}", err));
                         }
-                        log::info!("Received msg {} from {:?}", msg, addr);
-                    },
-                    Ok(game_state) = rx.recv() => {
-                        websocket
-                            .send(tungstenite::Message::Text(game_state))
-                            .await
-                            .unwrap_or_else(|err| log::warn!("Failed to send message: {:?}", err));
                     }
                 }
+                log::info!("Received msg {} from {:?}", msg, addr);
+            },
+            Ok(downstream) = rx.recv() => {
+                if !downstream.is_for(player_id) {
+                    continue;
+                }
+                websocket
+                    .send(tungstenite::Message::Text(downstream.message))
+                    .await
+                    .unwrap_or_else(|err| log::warn!("Failed to send message: {:?}", err));
             }
+        }
+    }
+    tx.send(GameEvent::Leave { player_id })
+        .await
+        .unwrap_or_else(|err| panic!("Failed to send game event {:?}", err));
+    auth.release_display_name(&display_name);
+    log::info!("Closed connection {:?} for {}", addr, session.name);
+}
+
+/// Closes a game connection before a player was spawned for it
+async fn reject<S>(websocket: &mut WebSocketStream<S>, addr: SocketAddr, code: u16, reason: &str)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    log::info!("Rejected connection {:?}: {}", addr, reason);
+    websocket
+        .close(Some(CloseFrame {
+            code: CloseCode::Library(code),
+            reason: reason.to_string().into(),
+        }))
+        .await
+        .unwrap_or_else(|err| log::info!("Disconnected {:?} {:?}", addr, err));
+}
+
+/**
+ * Display names follow the account name rules and may not be changed by any of the chat filters.
+ */
+fn is_allowed_display_name(name: &str, chat_filters: &[Box<dyn ChatFilter>]) -> bool {
+    auth::is_valid_name(name)
+        && chat_filters
+            .iter()
+            .try_fold(name.to_string(), |name, filter| filter.filter(name))
+            .is_some_and(|filtered| filtered == name)
+}
+
+/**
+ * Formats the state of all players as `state|tick|id,x,y,name;id,x,y,name;...`.
+ * Display names cannot contain any of the separators.
+ */
+fn state_message(game_state: &GameState) -> String {
+    let entities = &game_state.entities;
+    let players: Vec<String> = entities
+        .iter2::<NetworkId, Transform>()
+        .filter_map(|(entity, id, transform)| {
+            let name = entities.get::<Name>(entity)?;
+            let velocity = entities
+                .get::<Movement>(entity)
+                .map_or((0.0, 0.0), |movement| movement.velocity);
+            let client_tick = game_state
+                .client_tick(id.0)
+                .map_or_else(String::new, |tick| tick.to_string());
+            Some(format!(
+                "{},{},{},{},{},{},{}",
+                id.0, transform.x, transform.y, velocity.0, velocity.1, client_tick, name.0
+            ))
+        })
+        .collect();
+    format!("state|{}|{}", game_state.tick, players.join(";"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_move_messages() {
+        assert_eq!(
+            ClientMessage::parse("move|12|0.5,-1"),
+            Some(ClientMessage::Move(12, 0.5, -1.0))
+        );
+        assert_eq!(
+            ClientMessage::parse("move|0|0,0"),
+            Some(ClientMessage::Move(0, 0.0, 0.0))
+        );
+        for invalid in [
+            "move|0|NaN,0",
+            "move|0|0,nan",
+            "move|0|inf,0",
+            "move|0|0,-infinity",
+            "move|0|1e309,0",
+            "move|0|0.5",
+            "move|0|a,b",
+            "move|-1|0,0",
+            "move|0.5,0",
+            "move|",
+            "move",
+        ] {
+            assert_eq!(ClientMessage::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_chat_messages() {
+        assert_eq!(
+            ClientMessage::parse("chat|global|hello | world"),
+            Some(ClientMessage::Chat(ChatChannel::Global, "hello | world"))
+        );
+        assert_eq!(
+            ClientMessage::parse("chat|local|hi"),
+            Some(ClientMessage::Chat(ChatChannel::Local, "hi"))
+        );
+        assert_eq!(ClientMessage::parse("chat|team|hi"), None);
+        assert_eq!(ClientMessage::parse("chat|global"), None);
+        assert_eq!(ClientMessage::parse("jump|1"), None);
+    }
+
+    #[test]
+    fn reports_the_client_tick_of_each_player() {
+        let mut game_state = GameState::new(None);
+        game_state.apply(InputEvent::Join {
+            player_id: 3,
+            name: "bob".to_string(),
         });
+        game_state.step();
+        assert_eq!(game_state.client_tick(3), None);
+        assert_eq!(state_message(&game_state), "state|1|3,0,0,0,0,,bob");
+
+        // The client predicted this input from its tick 100, the server applies it at tick 1
+        game_state.apply_client_input(3, 100, (1.0, 0.0));
+        for _ in 0..4 {
+            game_state.step();
+        }
+        assert_eq!(game_state.client_tick(3), Some(104));
+        let message = state_message(&game_state);
+        assert!(message.starts_with("state|5|3,"), "{}", message);
+        assert!(message.ends_with(",104,bob"), "{}", message);
+    }
+
+    #[test]
+    fn keeps_the_player_until_its_last_connection_leaves() {
+        let mut game_state = GameState::new(None);
+        game_state.join(3, "bob".to_string());
+        game_state.join(3, "bob".to_string());
+        game_state.leave(3);
+        assert!(NetworkId(3).find(&game_state.entities).is_some());
+
+        // The remaining connection still controls the player
+        game_state.apply_client_input(3, 10, (1.0, 0.0));
+        game_state.step();
+        assert_eq!(game_state.client_tick(3), Some(11));
+        let player = NetworkId(3).find(&game_state.entities).unwrap();
+        assert!(game_state.entities.get::<Transform>(player).unwrap().x > 0.0);
+
+        game_state.leave(3);
+        assert!(NetworkId(3).find(&game_state.entities).is_none());
+        assert_eq!(game_state.client_tick(3), None);
     }
 }
@@ -0,0 +1,53 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+
+/**
+ * Creates a TLS acceptor from a PEM encoded certificate chain and private key.
+ *
+ * The key file may contain a PKCS#8, RSA or EC private key, the first key found is used.
+ * Panics if the files cannot be read since the server cannot start securely without them.
+ */
+pub fn load_acceptor(cert_path: &Path, key_path: &Path) -> TlsAcceptor {
+    let certs = read_certs(cert_path);
+    let key = read_private_key(key_path);
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .unwrap_or_else(|err| panic!("Invalid TLS certificate or key: {:?}", err));
+    TlsAcceptor::from(Arc::new(config))
+}
+
+fn open(path: &Path) -> BufReader<File> {
+    BufReader::new(
+        File::open(path).unwrap_or_else(|err| panic!("Failed to open {:?}: {:?}", path, err)),
+    )
+}
+
+fn read_certs(path: &Path) -> Vec<Certificate> {
+    let certs = rustls_pemfile::certs(&mut open(path))
+        .unwrap_or_else(|err| panic!("Failed to read certificates from {:?}: {:?}", path, err));
+    if certs.is_empty() {
+        panic!("No certificates found in {:?}", path);
+    }
+    certs.into_iter().map(Certificate).collect()
+}
+
+fn read_private_key(path: &Path) -> PrivateKey {
+    let mut reader = open(path);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .unwrap_or_else(|err| panic!("Failed to read private key from {:?}: {:?}", path, err))
+        {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return PrivateKey(key),
+            Some(_) => {}
+            None => panic!("No private key found in {:?}", path),
+        }
+    }
+}
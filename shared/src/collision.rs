@@ -0,0 +1,161 @@
+/// Collision shape in squares, x right & y up from its bottom-left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub const fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn offset(&self, (x, y): (f64, f64)) -> Rect {
+        Rect::new(self.x + x, self.y + y, self.width, self.height)
+    }
+
+    /// Rects that only touch along an edge do not overlap, so bodies can slide along each other
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+}
+
+/// Longest distance moved at once, shorter than any obstacle so fast bodies can not skip over one
+const MAX_STEP: f64 = 0.25;
+/// Bodies stopped at an edge may end up this far inside the obstacle through float rounding,
+/// they still count as in front of it
+const EDGE_TOLERANCE: f64 = 1e-9;
+
+/**
+ * Moves a body with the given collider (relative to its position) by `delta`, returns its new position.
+ *
+ * Both axes are resolved separately: a body blocked on one axis keeps moving along the other,
+ * which makes it slide along the edges of obstacles. Bodies already inside an obstacle can still
+ * move out of it.
+ */
+pub fn move_and_slide(
+    position: (f64, f64),
+    collider: &Rect,
+    delta: (f64, f64),
+    obstacles: &[Rect],
+) -> (f64, f64) {
+    let steps = (delta.0.abs().max(delta.1.abs()) / MAX_STEP)
+        .ceil()
+        .max(1.0);
+    let step = (delta.0 / steps, delta.1 / steps);
+    let mut position = position;
+    for _ in 0..steps as u32 {
+        position.0 = move_axis(position, collider, (step.0, 0.0), obstacles).0;
+        position.1 = move_axis(position, collider, (0.0, step.1), obstacles).1;
+    }
+    position
+}
+
+/// Moves along a single axis, `delta` is 0 for the other one
+fn move_axis(
+    position: (f64, f64),
+    collider: &Rect,
+    delta: (f64, f64),
+    obstacles: &[Rect],
+) -> (f64, f64) {
+    if delta == (0.0, 0.0) {
+        return position;
+    }
+    let current = collider.offset(position);
+    let mut target = (position.0 + delta.0, position.1 + delta.1);
+    for obstacle in obstacles {
+        if !collider.offset(target).overlaps(obstacle) {
+            continue;
+        }
+        // Only obstacles ahead stop the body, right at their edge. Bodies deep inside one (e.g.
+        // spawned there) are not in front of any of its edges, so they can still leave it.
+        if delta.0 > 0.0 && current.x + current.width <= obstacle.x + EDGE_TOLERANCE {
+            target.0 = target.0.min(obstacle.x - collider.x - collider.width);
+        } else if delta.0 < 0.0 && current.x >= obstacle.x + obstacle.width - EDGE_TOLERANCE {
+            target.0 = target.0.max(obstacle.x + obstacle.width - collider.x);
+        } else if delta.1 > 0.0 && current.y + current.height <= obstacle.y + EDGE_TOLERANCE {
+            target.1 = target.1.min(obstacle.y - collider.y - collider.height);
+        } else if delta.1 < 0.0 && current.y >= obstacle.y + obstacle.height - EDGE_TOLERANCE {
+            target.1 = target.1.max(obstacle.y + obstacle.height - collider.y);
+        }
+    }
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collider of players, see `world::PLAYER_COLLIDER`
+    const BODY: Rect = Rect::new(0.2, 0.0, 0.6, 0.8);
+    const TREE: Rect = Rect::new(-4.0, -1.0, 1.0, 1.0);
+
+    /// Moves in many small steps, like every tick of the simulation does
+    fn walk(position: (f64, f64), step: (f64, f64), steps: u32, obstacles: &[Rect]) -> (f64, f64) {
+        (0..steps).fold(position, |position, _| {
+            move_and_slide(position, &BODY, step, obstacles)
+        })
+    }
+
+    #[test]
+    fn stops_at_obstacles_hit_head_on() {
+        let (x, y) = move_and_slide((-6.0, -0.9), &BODY, (3.0, 0.0), &[TREE]);
+        assert!((x - (TREE.x - BODY.x - BODY.width)).abs() < 1e-9);
+        assert_eq!(y, -0.9);
+
+        let (x, y) = move_and_slide((-3.5, -3.0), &BODY, (0.0, 3.0), &[TREE]);
+        assert_eq!(x, -3.5);
+        assert!((y - (TREE.y - BODY.height)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn slides_along_obstacles() {
+        // Blocked on x by the tree, still moving up along its side
+        let start = (TREE.x - BODY.x - BODY.width, -1.0);
+        let (x, y) = move_and_slide(start, &BODY, (0.5, 0.5), &[TREE]);
+        assert!((x - start.0).abs() < 1e-9);
+        assert_eq!(y, -0.5);
+    }
+
+    #[test]
+    fn repeated_small_steps_never_pass_an_edge() {
+        // Full speed for 5 seconds at 60 ticks per second, from both sides & on both axes
+        let edge = TREE.x - BODY.x - BODY.width;
+        let (x, _y) = walk((-6.0, -0.9), (4.0 / 60.0, 0.0), 300, &[TREE]);
+        assert!(x <= edge + 1e-9, "walked through the tree to {}", x);
+        let (x, _y) = walk((0.0, -0.9), (-4.0 / 60.0, 0.0), 300, &[TREE]);
+        assert!(
+            x >= TREE.x + TREE.width - BODY.x - 1e-9,
+            "walked through the tree to {}",
+            x
+        );
+        let (_x, y) = walk((-3.5, -4.0), (0.0, 4.0 / 60.0), 300, &[TREE]);
+        assert!(
+            y <= TREE.y - BODY.height + 1e-9,
+            "walked through the tree to {}",
+            y
+        );
+        let (_x, y) = walk((-3.5, 2.0), (0.0, -4.0 / 60.0), 300, &[TREE]);
+        assert!(
+            y >= TREE.y + TREE.height - 1e-9,
+            "walked through the tree to {}",
+            y
+        );
+    }
+
+    #[test]
+    fn bodies_inside_an_obstacle_can_leave_it() {
+        let (x, _y) = walk((-3.5, -1.0), (0.1, 0.0), 20, &[TREE]);
+        assert!((x - -1.5).abs() < 1e-9);
+    }
+}
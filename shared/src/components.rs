@@ -0,0 +1,52 @@
+//! Components the client & server both attach to entities. `movement::Movement` is one as well,
+//! entities with it are moved by `world::move_entities`.
+
+use super::collision::Rect;
+use super::ecs::{Entities, Entity};
+
+/// Position in squares of the bottom-left corner, x right & y up
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Transform {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Transform {
+    pub fn new(x: f64, y: f64) -> Self {
+        Transform { x, y }
+    }
+
+    pub fn position(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+}
+
+/// Atlas sprite the client draws the entity with, the server only passes it along
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sprite(pub String);
+
+/// Solid shape relative to the transform, moving entities can not walk into the colliders of still ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collider(pub Rect);
+
+/// Id the server knows the entity by, players use their account's player id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetworkId(pub u16);
+
+impl NetworkId {
+    /// The entity with this id, if there is one
+    pub fn find(self, entities: &Entities) -> Option<Entity> {
+        entities
+            .iter::<NetworkId>()
+            .find(|(_entity, id)| **id == self)
+            .map(|(entity, _id)| entity)
+    }
+}
+
+/// Marks the player moved by the local input, only the client has one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerControlled;
+
+/// Display name, e.g. shown above players
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Name(pub String);
@@ -0,0 +1,255 @@
+//! A small entity-component store: entities are ids, components any `'static` type attached to them.
+//! Systems are plain functions over `Entities`, see `world::move_entities`.
+
+use std::any::{type_name, Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Handle to an entity, stale handles of despawned entities no longer find anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+    index: u32,
+    /// Increased every time the index is reused
+    generation: u32,
+}
+
+/// Anything attached to entities, `Debug` so the whole state can be logged & `Send` so the server
+/// can keep it in its game loop task
+pub trait Component: Any + Send + fmt::Debug {}
+
+impl<T: Any + Send + fmt::Debug> Component for T {}
+
+/// Type erased `Storage<C>`, so storages of all component types fit in one map
+trait AnyStorage: Send + fmt::Debug {
+    fn remove_entity(&mut self, entity: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Components of one type, sorted by entity so iteration order is the same everywhere
+struct Storage<C>(BTreeMap<Entity, C>);
+
+impl<C: Component> fmt::Debug for Storage<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.0.iter()).finish()
+    }
+}
+
+impl<C: Component> AnyStorage for Storage<C> {
+    fn remove_entity(&mut self, entity: Entity) {
+        self.0.remove(&entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/**
+ * All entities & their components. Components are looked up by type, an entity has at most one
+ * component of each type.
+ */
+#[derive(Default)]
+pub struct Entities {
+    /// Current generation of every index, odd while the index is in use
+    generations: Vec<u32>,
+    /// Indices of despawned entities, reused first
+    free: Vec<u32>,
+    storages: HashMap<TypeId, (&'static str, Box<dyn AnyStorage>)>,
+}
+
+impl fmt::Debug for Entities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Sorted by type name, the map order would change between runs
+        let mut storages: Vec<_> = self.storages.values().collect();
+        storages.sort_by_key(|(name, _storage)| *name);
+        f.debug_map()
+            .entries(storages.into_iter().map(|(name, storage)| (name, storage)))
+            .finish()
+    }
+}
+
+impl Entities {
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                let generation = &mut self.generations[index as usize];
+                *generation += 1;
+                Entity {
+                    index,
+                    generation: *generation,
+                }
+            }
+            None => {
+                self.generations.push(1);
+                Entity {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 1,
+                }
+            }
+        }
+    }
+
+    /// Removes the entity & all its components, returns false if it was already gone
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        for (_name, storage) in self.storages.values_mut() {
+            storage.remove_entity(entity);
+        }
+        self.generations[entity.index as usize] += 1;
+        self.free.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.generations.get(entity.index as usize) == Some(&entity.generation)
+    }
+
+    /// Attaches the component, replacing the one of the same type. Ignored for despawned entities.
+    pub fn insert<C: Component>(&mut self, entity: Entity, component: C) {
+        if !self.is_alive(entity) {
+            return;
+        }
+        self.storages
+            .entry(TypeId::of::<C>())
+            .or_insert_with(|| (type_name::<C>(), Box::new(Storage::<C>(BTreeMap::new()))))
+            .1
+            .as_any_mut()
+            .downcast_mut::<Storage<C>>()
+            .expect("Storages are keyed by their component type")
+            .0
+            .insert(entity, component);
+    }
+
+    pub fn remove<C: Component>(&mut self, entity: Entity) -> Option<C> {
+        self.storage_mut::<C>()?.0.remove(&entity)
+    }
+
+    pub fn get<C: Component>(&self, entity: Entity) -> Option<&C> {
+        self.storage::<C>()?.0.get(&entity)
+    }
+
+    pub fn get_mut<C: Component>(&mut self, entity: Entity) -> Option<&mut C> {
+        self.storage_mut::<C>()?.0.get_mut(&entity)
+    }
+
+    pub fn has<C: Component>(&self, entity: Entity) -> bool {
+        self.get::<C>(entity).is_some()
+    }
+
+    /// Every entity with the component, ordered by entity so it is the same on every run
+    pub fn iter<C: Component>(&self) -> impl Iterator<Item = (Entity, &C)> {
+        self.storage::<C>().into_iter().flat_map(|storage| {
+            storage
+                .0
+                .iter()
+                .map(|(entity, component)| (*entity, component))
+        })
+    }
+
+    pub fn iter_mut<C: Component>(&mut self) -> impl Iterator<Item = (Entity, &mut C)> {
+        self.storage_mut::<C>().into_iter().flat_map(|storage| {
+            storage
+                .0
+                .iter_mut()
+                .map(|(entity, component)| (*entity, component))
+        })
+    }
+
+    /// Every entity with both components
+    pub fn iter2<A: Component, B: Component>(&self) -> impl Iterator<Item = (Entity, &A, &B)> {
+        self.iter::<A>()
+            .filter_map(|(entity, a)| self.get::<B>(entity).map(|b| (entity, a, b)))
+    }
+
+    /// The first entity with the component, e.g. for markers only one entity has
+    pub fn find<C: Component>(&self) -> Option<Entity> {
+        self.iter::<C>().next().map(|(entity, _component)| entity)
+    }
+
+    fn storage<C: Component>(&self) -> Option<&Storage<C>> {
+        self.storages
+            .get(&TypeId::of::<C>())
+            .and_then(|(_name, storage)| storage.as_any().downcast_ref())
+    }
+
+    fn storage_mut<C: Component>(&mut self) -> Option<&mut Storage<C>> {
+        self.storages
+            .get_mut(&TypeId::of::<C>())
+            .and_then(|(_name, storage)| storage.as_any_mut().downcast_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+    #[derive(Debug, PartialEq)]
+    struct Label(&'static str);
+
+    #[test]
+    fn despawned_entities_lose_their_components_and_handles() {
+        let mut entities = Entities::default();
+        let first = entities.spawn();
+        entities.insert(first, Position(1));
+        assert!(entities.despawn(first));
+        assert!(!entities.despawn(first));
+        assert!(!entities.is_alive(first));
+        assert_eq!(entities.get::<Position>(first), None);
+
+        // The index is reused, the stale handle does not find the new entity
+        let second = entities.spawn();
+        assert_ne!(first, second);
+        entities.insert(second, Position(2));
+        assert_eq!(entities.get::<Position>(first), None);
+        assert_eq!(entities.get::<Position>(second), Some(&Position(2)));
+
+        // Inserting into a despawned entity does nothing
+        entities.insert(first, Label("stale"));
+        assert_eq!(entities.iter::<Label>().count(), 0);
+    }
+
+    #[test]
+    fn components_are_replaced_removed_and_changed_in_place() {
+        let mut entities = Entities::default();
+        let entity = entities.spawn();
+        entities.insert(entity, Position(1));
+        entities.insert(entity, Position(2));
+        entities.get_mut::<Position>(entity).unwrap().0 += 1;
+        assert_eq!(entities.get::<Position>(entity), Some(&Position(3)));
+        assert!(!entities.has::<Label>(entity));
+        assert_eq!(entities.remove::<Position>(entity), Some(Position(3)));
+        assert!(!entities.has::<Position>(entity));
+    }
+
+    #[test]
+    fn iterates_entities_with_all_components_in_order() {
+        let mut entities = Entities::default();
+        let [a, b, c] = [(); 3].map(|()| entities.spawn());
+        entities.insert(c, Position(3));
+        entities.insert(a, Position(1));
+        entities.insert(b, Label("b"));
+        entities.insert(c, Label("c"));
+        let positions: Vec<_> = entities.iter::<Position>().map(|(e, p)| (e, p.0)).collect();
+        assert_eq!(positions, vec![(a, 1), (c, 3)]);
+        let both: Vec<_> = entities
+            .iter2::<Position, Label>()
+            .map(|(e, _p, l)| (e, l.0))
+            .collect();
+        assert_eq!(both, vec![(c, "c")]);
+        assert_eq!(entities.find::<Label>(), Some(b));
+
+        for (_entity, position) in entities.iter_mut::<Position>() {
+            position.0 *= 10;
+        }
+        assert_eq!(entities.get::<Position>(c), Some(&Position(30)));
+    }
+}
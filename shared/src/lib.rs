@@ -0,0 +1,10 @@
+//! Game rules that the client & server both simulate, kept free of platform specific dependencies
+//! so it builds for desktop, wasm and the server alike.
+
+pub mod collision;
+pub mod components;
+pub mod ecs;
+pub mod movement;
+pub mod replay;
+pub mod simulation;
+pub mod world;
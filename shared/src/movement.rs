@@ -0,0 +1,185 @@
+/**
+ * Movement of a player from an input direction, the same on the client & the server.
+ *
+ * Every input method (keys, gamepad & touch sticks, the cursor) is turned into a 2D vector with
+ * a length of at most 1, where 1 is full speed in any direction. Velocity then moves towards
+ * that vector times the max speed, either instantly or limited by acceleration & friction.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementConfig {
+    /// Speed in squares per second at full input
+    pub max_speed: f64,
+    /// Speed gained per second while there is input, None reaches the target speed instantly
+    pub acceleration: Option<f64>,
+    /// Speed lost per second without input, None stops instantly
+    pub friction: Option<f64>,
+}
+
+pub const PLAYER_MOVEMENT: MovementConfig = MovementConfig {
+    max_speed: 4.0,
+    acceleration: Some(40.0),
+    friction: Some(30.0),
+};
+
+/// Input components are whole multiples of one over this, see `clamp_input`
+const INPUT_RESOLUTION: f64 = 256.0;
+
+/**
+ * Scales a direction down to a length of at most 1, shorter directions keep their length so
+ * analog input can move slower than full speed.
+ *
+ * Components are rounded towards zero to multiples of 1/256, which floats hold exactly: an input
+ * sent to the server in full precision arrives bit for bit & clamping it again there leaves it
+ * unchanged, so both simulate the exact same movement.
+ */
+pub fn clamp_input((x, y): (f64, f64)) -> (f64, f64) {
+    if !x.is_finite() || !y.is_finite() {
+        return (0.0, 0.0);
+    }
+    let length = (x * x + y * y).sqrt();
+    let (x, y) = if length > 1.0 {
+        (x / length, y / length)
+    } else {
+        (x, y)
+    };
+    // Rounding towards zero only makes the direction shorter, it stays within the length of 1
+    (
+        (x * INPUT_RESOLUTION).trunc() / INPUT_RESOLUTION,
+        (y * INPUT_RESOLUTION).trunc() / INPUT_RESOLUTION,
+    )
+}
+
+/// Turns digital input (e.g. held keys) into a direction of length 1, or 0 if nothing is held
+pub fn digital_input(x: i8, y: i8) -> (f64, f64) {
+    clamp_input((x.signum() as f64 * 2.0, y.signum() as f64 * 2.0))
+}
+
+/**
+ * Velocity and input of a moving body, advanced with `step`.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Movement {
+    /// Squares per second, x right & y up
+    pub velocity: (f64, f64),
+    /// Input direction, see `clamp_input`
+    pub input: (f64, f64),
+}
+
+impl Movement {
+    /// Sets the input direction, anything longer than 1 is clamped
+    pub fn set_input(&mut self, input: (f64, f64)) {
+        self.input = clamp_input(input);
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.velocity != (0.0, 0.0)
+    }
+
+    /**
+     * Advances the velocity by `delta_time` seconds and returns how far the body moved in that time.
+     */
+    pub fn step(&mut self, config: &MovementConfig, delta_time: f64) -> (f64, f64) {
+        let target = (
+            self.input.0 * config.max_speed,
+            self.input.1 * config.max_speed,
+        );
+        let rate = if self.input == (0.0, 0.0) {
+            config.friction
+        } else {
+            config.acceleration
+        };
+        self.velocity = match rate {
+            Some(rate) => approach(self.velocity, target, rate * delta_time),
+            None => target,
+        };
+        (self.velocity.0 * delta_time, self.velocity.1 * delta_time)
+    }
+}
+
+/// Moves `from` towards `to` by at most `max_change`
+fn approach(from: (f64, f64), to: (f64, f64), max_change: f64) -> (f64, f64) {
+    let difference = (to.0 - from.0, to.1 - from.1);
+    let distance = (difference.0 * difference.0 + difference.1 * difference.1).sqrt();
+    if distance <= max_change {
+        to
+    } else {
+        let scale = max_change / distance;
+        (from.0 + difference.0 * scale, from.1 + difference.1 * scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSTANT: MovementConfig = MovementConfig {
+        max_speed: 4.0,
+        acceleration: None,
+        friction: None,
+    };
+
+    fn length((x, y): (f64, f64)) -> f64 {
+        (x * x + y * y).sqrt()
+    }
+
+    #[test]
+    fn clamps_input_to_full_speed() {
+        assert_eq!(clamp_input((0.5, -0.25)), (0.5, -0.25));
+        assert_eq!(clamp_input((3.0, 0.0)), (1.0, 0.0));
+        let (x, y) = clamp_input((10.0, 10.0));
+        assert_eq!(x, y);
+        assert!(length((x, y)) <= 1.0);
+        for invalid in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(clamp_input((invalid, 0.5)), (0.0, 0.0));
+            assert_eq!(clamp_input((0.5, invalid)), (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn rounds_input_towards_zero_to_the_resolution() {
+        let (x, y) = clamp_input((0.3, -0.77));
+        assert_eq!((x, y), (76.0 / 256.0, -197.0 / 256.0));
+        // Clamping again (as the server does) changes nothing
+        assert_eq!(clamp_input((x, y)), (x, y));
+        assert_eq!(clamp_input((0.001, -0.001)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn digital_input_moves_at_full_speed_in_every_direction() {
+        assert_eq!(digital_input(0, 0), (0.0, 0.0));
+        assert_eq!(digital_input(1, 0), (1.0, 0.0));
+        assert_eq!(digital_input(0, -1), (0.0, -1.0));
+        let diagonal = digital_input(-1, 1);
+        assert_eq!(diagonal.0, -diagonal.1);
+        assert!(length(diagonal) <= 1.0 && length(diagonal) > 0.99);
+    }
+
+    #[test]
+    fn accelerates_to_max_speed_and_slows_down_by_friction() {
+        let mut movement = Movement::default();
+        movement.set_input((1.0, 0.0));
+        // 40 squares per second² reach 4 squares per second after a tenth of a second
+        let delta = movement.step(&PLAYER_MOVEMENT, 0.05);
+        assert_eq!(movement.velocity, (2.0, 0.0));
+        assert_eq!(delta, (0.1, 0.0));
+        movement.step(&PLAYER_MOVEMENT, 0.05);
+        movement.step(&PLAYER_MOVEMENT, 0.05);
+        assert_eq!(movement.velocity, (4.0, 0.0));
+
+        movement.set_input((0.0, 0.0));
+        movement.step(&PLAYER_MOVEMENT, 0.1);
+        assert!((movement.velocity.0 - 1.0).abs() < 1e-9);
+        movement.step(&PLAYER_MOVEMENT, 0.1);
+        assert!(!movement.is_moving());
+    }
+
+    #[test]
+    fn configs_without_rates_change_speed_instantly() {
+        let mut movement = Movement::default();
+        movement.set_input((0.0, -0.5));
+        assert_eq!(movement.step(&INSTANT, 0.5), (0.0, -1.0));
+        movement.set_input((0.0, 0.0));
+        assert_eq!(movement.step(&INSTANT, 0.5), (0.0, 0.0));
+        assert!(!movement.is_moving());
+    }
+}
@@ -0,0 +1,532 @@
+//! Player input recorded with the tick it was applied at, so a game can be simulated again &
+//! reaches the exact same state at every tick (see `simulation`), e.g. to debug desyncs.
+//!
+//! Replay files are binary & little endian: the magic bytes `ENDR`, a version byte, the tick
+//! recording started at (u64) & a snapshot of every player at that tick (u16 count, then per
+//! player its id, name, position, velocity & input). Entries follow until the end of the file,
+//! each starting with the number of ticks since the previous entry as a LEB128 varint & a kind
+//! byte: `join` (id, name), `move` (id, input), `leave` (id) or `checksum` (u64). Names are a
+//! length byte & UTF-8, inputs two i16 in 1/256 steps & everything else f64 bits.
+
+use std::io::{self, Read, Write};
+
+use super::components::{Collider, Name, NetworkId, Transform};
+use super::ecs::{Entities, Entity};
+use super::movement::{clamp_input, Movement};
+use super::simulation::{self, TICKS_PER_SECOND};
+use super::world::{World, PLAYER_COLLIDER, SPAWN_POSITION};
+
+const MAGIC: &[u8; 4] = b"ENDR";
+const VERSION: u8 = 1;
+/// Input components are whole multiples of 1/256, see `movement::clamp_input`
+const INPUT_SCALE: f64 = 256.0;
+
+const KIND_JOIN: u8 = 0;
+const KIND_MOVE: u8 = 1;
+const KIND_LEAVE: u8 = 2;
+const KIND_CHECKSUM: u8 = 3;
+
+/// Everything players do that changes the simulation, applied the same way live & in replays
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    Join { player_id: u16, name: String },
+    Move { player_id: u16, input: (f64, f64) },
+    Leave { player_id: u16 },
+}
+
+impl InputEvent {
+    pub fn apply(&self, entities: &mut Entities) {
+        match self {
+            InputEvent::Join { player_id, name } => {
+                // The same account joining again replaces its player, ids stay unique
+                if let Some(player) = NetworkId(*player_id).find(entities) {
+                    entities.despawn(player);
+                }
+                spawn_player(entities, *player_id, name.clone());
+            }
+            InputEvent::Move { player_id, input } => {
+                let player = NetworkId(*player_id).find(entities);
+                // Input is clamped here as well, a crafted message can not move faster than max speed
+                if let Some(movement) =
+                    player.and_then(|player| entities.get_mut::<Movement>(player))
+                {
+                    movement.set_input(*input);
+                }
+            }
+            InputEvent::Leave { player_id } => {
+                if let Some(player) = NetworkId(*player_id).find(entities) {
+                    entities.despawn(player);
+                }
+            }
+        }
+    }
+}
+
+/// Spawns a player at the spawn position, standing still
+pub fn spawn_player(entities: &mut Entities, player_id: u16, name: String) -> Entity {
+    let player = entities.spawn();
+    entities.insert(player, NetworkId(player_id));
+    entities.insert(player, Name(name));
+    entities.insert(player, Transform::new(SPAWN_POSITION.0, SPAWN_POSITION.1));
+    entities.insert(player, Movement::default());
+    entities.insert(player, Collider(PLAYER_COLLIDER));
+    player
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Input(InputEvent),
+    /// `simulation::checksum` of the state at the entry's tick, replays compare theirs with it
+    Checksum(u64),
+}
+
+/**
+ * Writes a replay file: the snapshot when created, then every recorded entry. Ticks must not
+ * decrease between entries.
+ */
+#[derive(Debug)]
+pub struct Recorder<W: Write> {
+    writer: W,
+    last_tick: u64,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts recording at `tick`, with the players of `entities` as the snapshot
+    pub fn new(mut writer: W, tick: u64, entities: &Entities) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&tick.to_le_bytes())?;
+        let players: Vec<_> = entities.iter::<NetworkId>().collect();
+        writer.write_all(&(players.len() as u16).to_le_bytes())?;
+        for (player, id) in players {
+            let name = entities.get::<Name>(player).map_or("", |name| &name.0);
+            let transform = entities
+                .get::<Transform>(player)
+                .copied()
+                .unwrap_or_default();
+            let movement = entities
+                .get::<Movement>(player)
+                .copied()
+                .unwrap_or_default();
+            writer.write_all(&id.0.to_le_bytes())?;
+            write_name(&mut writer, name)?;
+            for value in [
+                transform.x,
+                transform.y,
+                movement.velocity.0,
+                movement.velocity.1,
+            ] {
+                writer.write_all(&value.to_bits().to_le_bytes())?;
+            }
+            write_input(&mut writer, movement.input)?;
+        }
+        Ok(Recorder {
+            writer,
+            last_tick: tick,
+        })
+    }
+
+    /// Records an event applied before the simulation step of `tick`
+    pub fn record(&mut self, tick: u64, event: &InputEvent) -> io::Result<()> {
+        self.write_entry(tick, &Entry::Input(event.clone()))
+    }
+
+    /// Records the state of the simulation before the step of `tick`, so replays can check theirs
+    pub fn record_checksum(&mut self, tick: u64, entities: &Entities) -> io::Result<()> {
+        self.write_entry(tick, &Entry::Checksum(simulation::checksum(entities)))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_entry(&mut self, tick: u64, entry: &Entry) -> io::Result<()> {
+        write_varint(&mut self.writer, tick.saturating_sub(self.last_tick))?;
+        self.last_tick = self.last_tick.max(tick);
+        match entry {
+            Entry::Input(InputEvent::Join { player_id, name }) => {
+                self.writer.write_all(&[KIND_JOIN])?;
+                self.writer.write_all(&player_id.to_le_bytes())?;
+                write_name(&mut self.writer, name)
+            }
+            Entry::Input(InputEvent::Move { player_id, input }) => {
+                self.writer.write_all(&[KIND_MOVE])?;
+                self.writer.write_all(&player_id.to_le_bytes())?;
+                write_input(&mut self.writer, *input)
+            }
+            Entry::Input(InputEvent::Leave { player_id }) => {
+                self.writer.write_all(&[KIND_LEAVE])?;
+                self.writer.write_all(&player_id.to_le_bytes())
+            }
+            Entry::Checksum(checksum) => {
+                self.writer.write_all(&[KIND_CHECKSUM])?;
+                self.writer.write_all(&checksum.to_le_bytes())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PlayerSnapshot {
+    id: u16,
+    name: String,
+    transform: Transform,
+    movement: Movement,
+}
+
+/// State of a replay after running it to a tick
+#[derive(Debug)]
+pub struct Playback {
+    pub entities: Entities,
+    /// First tick whose recorded checksum differs from the replayed state, None if all matched
+    pub desync: Option<u64>,
+}
+
+/**
+ * A parsed replay file, see the module docs for the format.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    start_tick: u64,
+    players: Vec<PlayerSnapshot>,
+    /// Sorted by tick
+    entries: Vec<(u64, Entry)>,
+}
+
+impl Replay {
+    pub fn read(mut reader: impl Read) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|err| format!("Failed to read replay: {}", err))?;
+        let mut bytes = Bytes(&bytes);
+        if bytes.take(4)? != MAGIC {
+            return Err("Not a replay file".to_string());
+        }
+        let version = bytes.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported replay version {}", version));
+        }
+        let start_tick = bytes.u64()?;
+        let players = (0..bytes.u16()?)
+            .map(|_| {
+                Ok(PlayerSnapshot {
+                    id: bytes.u16()?,
+                    name: bytes.name()?,
+                    transform: Transform::new(bytes.f64()?, bytes.f64()?),
+                    movement: Movement {
+                        velocity: (bytes.f64()?, bytes.f64()?),
+                        input: bytes.input()?,
+                    },
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let mut entries = Vec::new();
+        let mut tick = start_tick;
+        while !bytes.0.is_empty() {
+            // A corrupt file could count past the last tick
+            tick = tick
+                .checked_add(bytes.varint()?)
+                .ok_or_else(|| format!("Invalid tick after tick {} in replay", tick))?;
+            let entry = match bytes.u8()? {
+                KIND_JOIN => Entry::Input(InputEvent::Join {
+                    player_id: bytes.u16()?,
+                    name: bytes.name()?,
+                }),
+                KIND_MOVE => Entry::Input(InputEvent::Move {
+                    player_id: bytes.u16()?,
+                    input: bytes.input()?,
+                }),
+                KIND_LEAVE => Entry::Input(InputEvent::Leave {
+                    player_id: bytes.u16()?,
+                }),
+                KIND_CHECKSUM => Entry::Checksum(bytes.u64()?),
+                kind => return Err(format!("Unknown entry kind {} at tick {}", kind, tick)),
+            };
+            entries.push((tick, entry));
+        }
+        Ok(Replay {
+            start_tick,
+            players,
+            entries,
+        })
+    }
+
+    pub fn start_tick(&self) -> u64 {
+        self.start_tick
+    }
+
+    /// Tick of the last entry, the recording ran at least this long
+    pub fn end_tick(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.start_tick, |(tick, _entry)| *tick)
+    }
+
+    /// Number of recorded seconds, at the fixed tick rate of the simulation
+    pub fn duration_seconds(&self) -> f64 {
+        (self.end_tick() - self.start_tick) as f64 / TICKS_PER_SECOND as f64
+    }
+
+    /**
+     * Simulates the default world from the snapshot up to `tick`, applying every input recorded
+     * before that tick's step. The state is the one the server had at the start of `tick`.
+     */
+    pub fn run(&self, tick: u64) -> Playback {
+        let mut entities = Entities::default();
+        World::default().spawn_objects(&mut entities);
+        for snapshot in &self.players {
+            let player = spawn_player(&mut entities, snapshot.id, snapshot.name.clone());
+            entities.insert(player, snapshot.transform);
+            entities.insert(player, snapshot.movement);
+        }
+        let mut desync = None;
+        let mut entries = self.entries.iter().peekable();
+        for current in self.start_tick..=tick {
+            while let Some((_tick, entry)) =
+                entries.next_if(|(entry_tick, _entry)| *entry_tick == current)
+            {
+                match entry {
+                    Entry::Input(event) => event.apply(&mut entities),
+                    Entry::Checksum(checksum) => {
+                        if desync.is_none() && simulation::checksum(&entities) != *checksum {
+                            desync = Some(current);
+                        }
+                    }
+                }
+            }
+            if current < tick {
+                simulation::step(&mut entities);
+            }
+        }
+        Playback { entities, desync }
+    }
+}
+
+fn write_name(writer: &mut impl Write, name: &str) -> io::Result<()> {
+    // Display names are at most 16 characters, longer ones are cut rather than corrupting the file
+    let bytes = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+    writer.write_all(&[bytes.len() as u8])?;
+    writer.write_all(bytes)
+}
+
+fn write_input(writer: &mut impl Write, input: (f64, f64)) -> io::Result<()> {
+    let (x, y) = clamp_input(input);
+    writer.write_all(&((x * INPUT_SCALE) as i16).to_le_bytes())?;
+    writer.write_all(&((y * INPUT_SCALE) as i16).to_le_bytes())
+}
+
+fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Reads values from the front of a byte slice
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < count {
+            return Err("Replay file ends too early".to_string());
+        }
+        let (taken, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("Took exactly N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    fn input(&mut self) -> Result<(f64, f64), String> {
+        let x = i16::from_le_bytes(self.array()?) as f64 / INPUT_SCALE;
+        let y = i16::from_le_bytes(self.array()?) as f64 / INPUT_SCALE;
+        Ok(clamp_input((x, y)))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let length = self.u8()? as usize;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_err| "Invalid player name in replay".to_string())
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid tick in replay".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A short game like the server runs it, returns the replay & the checksum at every tick
+    fn record_game() -> (Vec<u8>, Vec<u64>) {
+        let mut entities = Entities::default();
+        World::default().spawn_objects(&mut entities);
+        let mut recorder = Recorder::new(Vec::new(), 0, &entities).unwrap();
+        let events = [
+            (
+                0,
+                InputEvent::Join {
+                    player_id: 1,
+                    name: "first".to_string(),
+                },
+            ),
+            (
+                5,
+                InputEvent::Move {
+                    player_id: 1,
+                    input: (1.0, -0.4),
+                },
+            ),
+            (
+                40,
+                InputEvent::Join {
+                    player_id: 2,
+                    name: "second".to_string(),
+                },
+            ),
+            (
+                40,
+                InputEvent::Move {
+                    player_id: 2,
+                    input: (-0.3, 0.77),
+                },
+            ),
+            (
+                90,
+                InputEvent::Move {
+                    player_id: 1,
+                    input: (0.0, 0.0),
+                },
+            ),
+            (120, InputEvent::Leave { player_id: 1 }),
+            (
+                150,
+                InputEvent::Join {
+                    player_id: 3,
+                    name: "third".to_string(),
+                },
+            ),
+            (
+                151,
+                InputEvent::Move {
+                    player_id: 3,
+                    input: (5.0, 5.0),
+                },
+            ),
+        ];
+        let mut checksums = Vec::new();
+        for tick in 0..240 {
+            for (_tick, event) in events
+                .iter()
+                .filter(|(event_tick, _event)| *event_tick == tick)
+            {
+                event.apply(&mut entities);
+                recorder.record(tick, event).unwrap();
+            }
+            if tick % 30 == 0 {
+                recorder.record_checksum(tick, &entities).unwrap();
+            }
+            checksums.push(simulation::checksum(&entities));
+            simulation::step(&mut entities);
+        }
+        (recorder.writer, checksums)
+    }
+
+    #[test]
+    fn replays_reach_the_recorded_state_at_every_tick() {
+        let (bytes, checksums) = record_game();
+        let replay = Replay::read(bytes.as_slice()).unwrap();
+        assert_eq!(replay.end_tick(), 210);
+        for tick in [0, 5, 39, 40, 41, 120, 151, 239] {
+            let playback = replay.run(tick);
+            assert_eq!(
+                simulation::checksum(&playback.entities),
+                checksums[tick as usize]
+            );
+            assert_eq!(playback.desync, None);
+        }
+    }
+
+    #[test]
+    fn different_states_are_reported_as_desyncs() {
+        let (mut bytes, _checksums) = record_game();
+        // The last entry is the checksum of tick 210
+        let length = bytes.len();
+        bytes[length - 1] ^= 1;
+        let replay = Replay::read(bytes.as_slice()).unwrap();
+        assert_eq!(replay.run(200).desync, None);
+        assert_eq!(replay.run(239).desync, Some(210));
+    }
+
+    #[test]
+    fn joining_twice_replaces_the_player() {
+        let mut entities = Entities::default();
+        let join = |name: &str| InputEvent::Join {
+            player_id: 7,
+            name: name.to_string(),
+        };
+        join("first").apply(&mut entities);
+        join("second").apply(&mut entities);
+        let players: Vec<_> = entities.iter2::<NetworkId, Name>().collect();
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].2 .0, "second");
+
+        InputEvent::Leave { player_id: 7 }.apply(&mut entities);
+        assert_eq!(entities.iter::<NetworkId>().count(), 0);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(Replay::read(&b"state|1|"[..]).is_err());
+        let (bytes, _checksums) = record_game();
+        assert!(Replay::read(&bytes[..bytes.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn ticks_past_the_last_one_are_rejected() {
+        let start_tick = u64::MAX - 1;
+        let mut recorder = Recorder::new(Vec::new(), start_tick, &Entities::default()).unwrap();
+        let leave = InputEvent::Leave { player_id: 1 };
+        recorder.record(u64::MAX, &leave).unwrap();
+        let mut bytes = recorder.writer.clone();
+        assert_eq!(Replay::read(bytes.as_slice()).unwrap().end_tick(), u64::MAX);
+
+        // One more tick after the last possible one
+        write_varint(&mut bytes, 1).unwrap();
+        bytes.push(KIND_LEAVE);
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        assert!(Replay::read(bytes.as_slice()).is_err());
+    }
+}
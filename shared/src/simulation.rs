@@ -0,0 +1,168 @@
+//! The fixed step the client & server both advance the game by. Prediction on the client only
+//! matches the server if both reach bit-identical states from the same inputs, so a step always
+//! covers the same time & the rules only use float operations with exactly one correct result
+//! (`+ - * /` & `sqrt`, no `sin`, `powf`... whose results differ between platforms & libraries).
+
+use std::time::Duration;
+
+use super::components::Transform;
+use super::ecs::{Entities, Entity};
+use super::movement::{Movement, PLAYER_MOVEMENT};
+use super::world;
+
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const TICK_DURATION: Duration = Duration::from_nanos(1_000_000_000 / TICKS_PER_SECOND as u64);
+/// Simulated seconds per tick, the same on every machine no matter how long ticks really take
+const TICK_SECONDS: f64 = 1.0 / TICKS_PER_SECOND as f64;
+/// Frames longer than this many ticks (e.g. after the game was in the background) are cut short,
+/// catching up on all of them would make the next frames even longer
+const MAX_TICKS_PER_ADVANCE: u32 = 10;
+
+/// Advances every system by one tick, returns how far each moving entity tried to move
+pub fn step(entities: &mut Entities) -> Vec<(Entity, (f64, f64))> {
+    world::move_entities(entities, &PLAYER_MOVEMENT, TICK_SECONDS)
+}
+
+/**
+ * Turns the varying time between frames into a number of whole ticks to run, the rest is kept
+ * for the next frame.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct FixedTimestep {
+    accumulated: Duration,
+}
+
+impl FixedTimestep {
+    /// Adds the time since the last frame, returns how many ticks are due
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulated += elapsed;
+        let mut ticks = 0;
+        while self.accumulated >= TICK_DURATION {
+            self.accumulated -= TICK_DURATION;
+            ticks += 1;
+            if ticks == MAX_TICKS_PER_ADVANCE {
+                self.accumulated = Duration::ZERO;
+            }
+        }
+        ticks
+    }
+}
+
+/**
+ * Hash of the exact bits of every transform & movement, equal states have equal checksums.
+ * Cheap to compare between runs, builds or machines to spot where simulations diverge.
+ */
+pub fn checksum(entities: &Entities) -> u64 {
+    // FNV-1a, simple & the same everywhere unlike the std hashers
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |value: f64| {
+        for byte in value.to_bits().to_le_bytes() {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+    for (entity, transform) in entities.iter::<Transform>() {
+        add(transform.x);
+        add(transform.y);
+        if let Some(movement) = entities.get::<Movement>(entity) {
+            add(movement.velocity.0);
+            add(movement.velocity.1);
+            add(movement.input.0);
+            add(movement.input.1);
+        }
+    }
+    hash
+}
+
+/**
+ * These also run on wasm (`wasm-pack test --node` in this crate), where they prove the browser
+ * reaches the exact same state as native builds.
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Collider;
+    use crate::movement::{clamp_input, digital_input};
+    use crate::world::{World, PLAYER_COLLIDER};
+    #[cfg(target_arch = "wasm32")]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    /// Checksum of `run_scenario`, recorded from a native build. Only changes along with the rules.
+    const SCENARIO_CHECKSUM: u64 = 0x00bc_b1c3_37d2_b97a;
+
+    /// Two players walking around & into the trees of the default world with changing inputs
+    fn run_scenario() -> Entities {
+        let mut entities = Entities::default();
+        World::default().spawn_objects(&mut entities);
+        let players = [(0.0, 0.0), (4.5, -1.25)].map(|(x, y)| {
+            let player = entities.spawn();
+            entities.insert(player, Transform::new(x, y));
+            entities.insert(player, Movement::default());
+            entities.insert(player, Collider(PLAYER_COLLIDER));
+            player
+        });
+        let inputs = [
+            [digital_input(1, 0), (-0.3, 0.8)],
+            [digital_input(1, -1), (0.0, 0.0)],
+            [(0.123, -0.987), digital_input(-1, 0)],
+            [(0.0, 0.0), (2.5, 1.0 / 3.0)],
+        ];
+        for tick in 0..600 {
+            for (player, input) in players.iter().zip(inputs[tick / 150 % inputs.len()]) {
+                let movement = entities.get_mut::<Movement>(*player).unwrap();
+                movement.set_input(input);
+            }
+            step(&mut entities);
+        }
+        entities
+    }
+
+    #[test]
+    fn same_inputs_give_bit_identical_states() {
+        let (first, second) = (run_scenario(), run_scenario());
+        let bits = |entities: &Entities| -> Vec<(u64, u64)> {
+            entities
+                .iter::<Transform>()
+                .map(|(_entity, transform)| (transform.x.to_bits(), transform.y.to_bits()))
+                .collect()
+        };
+        assert_eq!(bits(&first), bits(&second));
+        assert_eq!(checksum(&first), checksum(&second));
+    }
+
+    #[test]
+    fn matches_the_recorded_state() {
+        assert_eq!(checksum(&run_scenario()), SCENARIO_CHECKSUM);
+    }
+
+    #[test]
+    fn inputs_survive_the_network_unchanged() {
+        for input in [
+            (0.7, 0.7),
+            (0.123, -0.987),
+            (-3.0, 1.0 / 3.0),
+            (1e-9, -1e-9),
+        ] {
+            let sent = clamp_input(input);
+            let message = format!("{},{}", sent.0, sent.1);
+            let (x, y) = message.split_once(',').unwrap();
+            let received = clamp_input((x.parse().unwrap(), y.parse().unwrap()));
+            assert_eq!(
+                (received.0.to_bits(), received.1.to_bits()),
+                (sent.0.to_bits(), sent.1.to_bits())
+            );
+        }
+    }
+
+    #[test]
+    fn frame_times_do_not_change_the_ticks() {
+        let ticks = |frame: Duration, frames: u32| {
+            let mut timestep = FixedTimestep::default();
+            (0..frames).map(|_| timestep.advance(frame)).sum::<u32>()
+        };
+        assert_eq!(ticks(TICK_DURATION, 60), 60);
+        assert_eq!(ticks(TICK_DURATION * 2, 30), 60);
+        assert_eq!(ticks(TICK_DURATION / 2, 120), 60);
+        // A long stall only catches up on a few ticks
+        assert_eq!(ticks(Duration::from_secs(5), 1), MAX_TICKS_PER_ADVANCE);
+    }
+}
@@ -0,0 +1,161 @@
+use super::collision::{move_and_slide, Rect};
+use super::components::{Collider, Sprite, Transform};
+use super::ecs::{Entities, Entity};
+use super::movement::{Movement, MovementConfig};
+
+/// Part of the player sprite that collides, its feet & body but not the top of its head
+pub const PLAYER_COLLIDER: Rect = Rect::new(0.2, 0.0, 0.6, 0.8);
+/// Where players appear when they join, on the server & when the client connects or plays offline
+pub const SPAWN_POSITION: (f64, f64) = (0.0, 0.0);
+
+/// Things placed on a square of the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Object {
+    Tree,
+}
+
+impl Object {
+    /// Collision shape relative to the square the object is placed on, None if it can be walked through
+    pub fn collider(&self) -> Option<Rect> {
+        match self {
+            Object::Tree => Some(Rect::new(0.0, 0.0, 1.0, 1.0)),
+        }
+    }
+
+    /// Atlas sprite the client draws the object with
+    pub fn sprite(&self) -> &'static str {
+        match self {
+            Object::Tree => "tree",
+        }
+    }
+}
+
+/// Ground covering a square of the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+    Grass,
+    Sand,
+}
+
+/// Width of the cells sand patches are spread over, in squares
+const SAND_CELL_SIZE: i32 = 8;
+/// Noise value from 0 to 1 above which squares are sand, higher values give less sand
+const SAND_THRESHOLD: f64 = 0.6;
+
+/**
+ * The static world every player moves through.
+ */
+#[derive(Debug, Clone)]
+pub struct World {
+    objects: Vec<((i32, i32), Object)>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        World::new(vec![
+            ((2, -1), Object::Tree),
+            ((-3, 2), Object::Tree),
+            ((-4, -1), Object::Tree),
+            ((5, 3), Object::Tree),
+        ])
+    }
+}
+
+impl World {
+    pub fn new(objects: Vec<((i32, i32), Object)>) -> Self {
+        World { objects }
+    }
+
+    /// Spawns an entity for every object, solid objects get a collider
+    pub fn spawn_objects(&self, entities: &mut Entities) {
+        for ((x, y), object) in &self.objects {
+            let entity = entities.spawn();
+            entities.insert(entity, Transform::new(*x as f64, *y as f64));
+            entities.insert(entity, Sprite(object.sprite().to_string()));
+            if let Some(collider) = object.collider() {
+                entities.insert(entity, Collider(collider));
+            }
+        }
+    }
+
+    /**
+     * Terrain of the given square. The world is endless, so it is computed from the square's
+     * coordinates rather than stored: the same square always has the same terrain, on every
+     * client & the server.
+     */
+    pub fn terrain(&self, (x, y): (i32, i32)) -> Terrain {
+        // Value noise, blending random values at the corners of the surrounding cell
+        let (cell_x, cell_y) = (x.div_euclid(SAND_CELL_SIZE), y.div_euclid(SAND_CELL_SIZE));
+        let size = SAND_CELL_SIZE as f64;
+        let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+        let tx = smooth(x.rem_euclid(SAND_CELL_SIZE) as f64 / size);
+        let ty = smooth(y.rem_euclid(SAND_CELL_SIZE) as f64 / size);
+        let bottom = lerp(
+            corner_value(cell_x, cell_y),
+            corner_value(cell_x + 1, cell_y),
+            tx,
+        );
+        let top = lerp(
+            corner_value(cell_x, cell_y + 1),
+            corner_value(cell_x + 1, cell_y + 1),
+            tx,
+        );
+        if lerp(bottom, top, ty) > SAND_THRESHOLD {
+            Terrain::Sand
+        } else {
+            Terrain::Grass
+        }
+    }
+}
+
+/**
+ * Steps the movement of every entity with a `Transform` & `Movement` by `delta_time` seconds.
+ * Moving entities with a `Collider` slide along the colliders of entities that do not move, they
+ * pass through each other. Returns how far each of them tried to move, before collisions.
+ */
+pub fn move_entities(
+    entities: &mut Entities,
+    config: &MovementConfig,
+    delta_time: f64,
+) -> Vec<(Entity, (f64, f64))> {
+    let obstacles: Vec<Rect> = entities
+        .iter2::<Collider, Transform>()
+        .filter(|(entity, _collider, _transform)| !entities.has::<Movement>(*entity))
+        .map(|(_entity, collider, transform)| collider.0.offset(transform.position()))
+        .collect();
+    let movers: Vec<Entity> = entities
+        .iter2::<Movement, Transform>()
+        .map(|(entity, _movement, _transform)| entity)
+        .collect();
+    let mut deltas = Vec::with_capacity(movers.len());
+    for entity in movers {
+        let delta = match entities.get_mut::<Movement>(entity) {
+            Some(movement) => movement.step(config, delta_time),
+            None => continue,
+        };
+        let collider = entities.get::<Collider>(entity).copied();
+        if let Some(transform) = entities.get_mut::<Transform>(entity) {
+            (transform.x, transform.y) = match collider {
+                Some(collider) => {
+                    move_and_slide(transform.position(), &collider.0, delta, &obstacles)
+                }
+                None => (transform.x + delta.0, transform.y + delta.1),
+            };
+        }
+        deltas.push((entity, delta));
+    }
+    deltas
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Random but fixed value from 0 to 1 for a corner of the terrain cells
+fn corner_value(x: i32, y: i32) -> f64 {
+    // Integer hash, so it is the same on every platform
+    let mut hash = (x as u32).wrapping_mul(0x8da6_b343) ^ (y as u32).wrapping_mul(0xd816_3841);
+    hash = (hash ^ (hash >> 13)).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 16;
+    hash as f64 / u32::MAX as f64
+}